@@ -0,0 +1,368 @@
+use serde::de::value::{MapAccessDeserializer, StrDeserializer};
+use serde::de::{
+    self, DeserializeSeed, Deserializer, IntoDeserializer, MapAccess, SeqAccess, Visitor,
+};
+use serde::ser::{SerializeMap, Serializer};
+use serde::{Deserialize, Serialize};
+
+use std::fmt;
+use std::marker::PhantomData;
+
+use crate::vector::Vector3;
+
+/// A trait for values that can be blended between two keyframes.
+pub trait Interpolate: Clone {
+    /// Blend between `self` at `t = 0` and `other` at `t = 1`.
+    fn interpolate(&self, other: &Self, t: f32) -> Self;
+}
+
+impl Interpolate for f32 {
+    fn interpolate(&self, other: &Self, t: f32) -> Self {
+        self + (other - self) * t
+    }
+}
+
+impl Interpolate for Vector3 {
+    fn interpolate(&self, other: &Self, t: f32) -> Self {
+        self + (other - self) * t
+    }
+}
+
+/// The easing curve used between a keyframe and the next one.
+/// - Linear: Blends at a constant rate.
+/// - SmoothStep: Eases in and out with `3t^2 - 2t^3`.
+/// - Bezier: Eases along a cubic Bezier timing curve from `(0, 0)` to `(1, 1)` with the control
+///   points `(x1, y1)` and `(x2, y2)`, given as `[x1, y1, x2, y2]`. `x1` and `x2` must lie in
+///   `[0, 1]` so that the curve moves forward in time.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "RawInterpolation")]
+pub enum Interpolation {
+    #[default]
+    Linear,
+    SmoothStep,
+    Bezier([f32; 4]),
+}
+
+/// The on-disk form of an `Interpolation`, which is checked before it is used.
+#[derive(Deserialize)]
+enum RawInterpolation {
+    Linear,
+    SmoothStep,
+    Bezier([f32; 4]),
+}
+
+impl TryFrom<RawInterpolation> for Interpolation {
+    type Error = String;
+
+    fn try_from(raw: RawInterpolation) -> Result<Self, Self::Error> {
+        match raw {
+            RawInterpolation::Linear => Ok(Interpolation::Linear),
+            RawInterpolation::SmoothStep => Ok(Interpolation::SmoothStep),
+            RawInterpolation::Bezier([x1, y1, x2, y2]) => {
+                if (0.0..=1.0).contains(&x1) && (0.0..=1.0).contains(&x2) {
+                    Ok(Interpolation::Bezier([x1, y1, x2, y2]))
+                } else {
+                    Err(format!(
+                        "Bezier control points need x1 and x2 between 0 and 1, found {} and {}.",
+                        x1, x2
+                    ))
+                }
+            }
+        }
+    }
+}
+
+impl Interpolation {
+    /// Map the linear progress `t` in `[0, 1]` through this easing curve.
+    pub fn ease(self, t: f32) -> f32 {
+        match self {
+            Interpolation::Linear => t,
+            Interpolation::SmoothStep => t * t * (3.0 - 2.0 * t),
+            Interpolation::Bezier([x1, y1, x2, y2]) => {
+                let bezier = |a: f32, b: f32, s: f32| {
+                    let r = 1.0 - s;
+                    3.0 * r * r * s * a + 3.0 * r * s * s * b + s * s * s
+                };
+
+                // The curve is monotonic in x for control points in [0, 1], so bisect for the
+                // parameter where it reaches `t`.
+                let (mut lo, mut hi) = (0.0, 1.0);
+                for _ in 0..32 {
+                    let mid = 0.5 * (lo + hi);
+                    if bezier(x1, x2, mid) < t {
+                        lo = mid;
+                    } else {
+                        hi = mid;
+                    }
+                }
+
+                bezier(y1, y2, 0.5 * (lo + hi))
+            }
+        }
+    }
+}
+
+/// A single key in an animation track. The key's `interpolation` is used between this key and
+/// the next one.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Keyframe<T> {
+    pub time: f32,
+    pub value: T,
+    #[serde(default)]
+    pub interpolation: Interpolation,
+}
+
+/// A value that is either constant or animated by a track of keyframes. Times are measured in
+/// frames. In JSON, a constant is written as the plain value, and a track is written as
+/// `{"Keyframes": [{"time": 0.0, "value": ..., "interpolation": "Linear"}, ...]}` with the
+/// keys sorted by time.
+#[derive(Clone, Debug)]
+pub enum Animated<T> {
+    Constant(T),
+    Keyframes(Vec<Keyframe<T>>),
+}
+
+impl<T: Interpolate> Animated<T> {
+    /// Return the value at the given `time`. Before the first key and after the last key the
+    /// value is held constant.
+    pub fn at(&self, time: f32) -> T {
+        let keys = match self {
+            Animated::Constant(value) => return value.clone(),
+            Animated::Keyframes(keys) => keys,
+        };
+
+        let next = keys.iter().position(|k| k.time > time);
+        match next {
+            Some(0) => keys[0].value.clone(),
+            Some(i) => {
+                let (a, b) = (&keys[i - 1], &keys[i]);
+                let t = (time - a.time) / (b.time - a.time);
+                a.value.interpolate(&b.value, a.interpolation.ease(t))
+            }
+            None => keys[keys.len() - 1].value.clone(),
+        }
+    }
+
+    /// Return whether this value changes over time.
+    pub fn is_animated(&self) -> bool {
+        matches!(self, Animated::Keyframes(_))
+    }
+}
+
+impl<T> Animated<T> {
+    /// Call `f` with every value of this constant or track, together with its path in the
+    /// scene's JSON given the `path` of this value, stopping at the first error.
+    pub fn try_each<E>(
+        &self,
+        path: &str,
+        mut f: impl FnMut(&str, &T) -> Result<(), E>,
+    ) -> Result<(), E> {
+        match self {
+            Animated::Constant(value) => f(path, value),
+            Animated::Keyframes(keys) => keys.iter().enumerate().try_for_each(|(i, key)| {
+                f(&format!("{}.Keyframes[{}].value", path, i), &key.value)
+            }),
+        }
+    }
+}
+
+impl<T> From<T> for Animated<T> {
+    fn from(value: T) -> Self {
+        Animated::Constant(value)
+    }
+}
+
+impl<T: Serialize> Serialize for Animated<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Animated::Constant(value) => value.serialize(serializer),
+            Animated::Keyframes(keys) => {
+                let mut map = serializer.serialize_map(Some(1))?;
+                map.serialize_entry("Keyframes", keys)?;
+                map.end()
+            }
+        }
+    }
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for Animated<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(AnimatedVisitor(PhantomData))
+    }
+}
+
+/// A visitor that tells keyframe tracks apart from constants by their first key, and otherwise
+/// hands the input on to `T` unchanged so that errors keep their position in the input.
+struct AnimatedVisitor<T>(PhantomData<T>);
+
+impl<'de, T: Deserialize<'de>> Visitor<'de> for AnimatedVisitor<T> {
+    type Value = Animated<T>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a value or a keyframe track")
+    }
+
+    fn visit_f64<E: de::Error>(self, v: f64) -> Result<Self::Value, E> {
+        T::deserialize(v.into_deserializer()).map(Animated::Constant)
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<Self::Value, E> {
+        T::deserialize(v.into_deserializer()).map(Animated::Constant)
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<Self::Value, E> {
+        T::deserialize(v.into_deserializer()).map(Animated::Constant)
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+        T::deserialize(v.into_deserializer()).map(Animated::Constant)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<Self::Value, A::Error> {
+        T::deserialize(de::value::SeqAccessDeserializer::new(seq)).map(Animated::Constant)
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let first = match map.next_key::<String>()? {
+            Some(key) => key,
+            None => return T::deserialize(MapAccessDeserializer::new(map)).map(Animated::Constant),
+        };
+
+        if first == "Keyframes" {
+            let keys: Vec<Keyframe<T>> = map.next_value()?;
+            if let Some(key) = map.next_key::<String>()? {
+                return Err(de::Error::custom(format!(
+                    "a keyframe track must only contain `Keyframes`, found extra key `{}`",
+                    key
+                )));
+            }
+            if keys.is_empty() {
+                return Err(de::Error::custom("a keyframe track needs at least one key"));
+            }
+            if keys.windows(2).any(|w| w[0].time >= w[1].time) {
+                return Err(de::Error::custom(
+                    "keyframes must be sorted by strictly increasing time",
+                ));
+            }
+
+            Ok(Animated::Keyframes(keys))
+        } else {
+            let map = Prefixed {
+                first: Some(first),
+                map,
+            };
+            T::deserialize(MapAccessDeserializer::new(map)).map(Animated::Constant)
+        }
+    }
+}
+
+/// A map that yields an already-consumed key before the rest of the entries in `map`.
+struct Prefixed<A> {
+    first: Option<String>,
+    map: A,
+}
+
+impl<'de, A: MapAccess<'de>> MapAccess<'de> for Prefixed<A> {
+    type Error = A::Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Self::Error> {
+        match self.first.take() {
+            Some(key) => {
+                let key: StrDeserializer<A::Error> = key.as_str().into_deserializer();
+                seed.deserialize(key).map(Some)
+            }
+            None => self.map.next_key_seed(seed),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.map.next_value_seed(seed)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_keyframes() {
+        let track: Animated<f32> = serde_json::from_str(
+            r#"{"Keyframes": [
+                {"time": 0.0, "value": 1.0},
+                {"time": 10.0, "value": 3.0, "interpolation": "SmoothStep"},
+                {"time": 20.0, "value": 5.0}
+            ]}"#,
+        )
+        .expect("Keyframe track should parse.");
+
+        for (time, expected) in [
+            (-5.0, 1.0),
+            (5.0, 2.0),
+            (15.0, 4.0),
+            (12.0, 3.208),
+            (30.0, 5.0),
+        ] {
+            let value = track.at(time);
+            assert!(
+                (value - expected).abs() < 1.0e-4,
+                "Animated::at() failed at time {}. Expected {}, got {}.",
+                time,
+                expected,
+                value
+            );
+        }
+
+        let constant: Animated<Vector3> = serde_json::from_str(r#"{"x": 1.0, "y": 2.0, "z": 3.0}"#)
+            .expect("Constant should parse.");
+        let expected = Vector3::new(1.0, 2.0, 3.0);
+        assert_eq!(
+            expected,
+            constant.at(7.0),
+            "Animated::at() failed on a constant. Expected {}, got {}.",
+            expected,
+            constant.at(7.0)
+        );
+    }
+
+    #[test]
+    fn test_bezier() {
+        let linear = Interpolation::Bezier([0.25, 0.25, 0.75, 0.75]);
+        let ease = Interpolation::Bezier([0.42, 0.0, 0.58, 1.0]);
+        for t in [0.0, 0.1, 0.5, 0.9, 1.0] {
+            let eased = linear.ease(t);
+            assert!(
+                (eased - t).abs() < 1.0e-4,
+                "Interpolation::ease() failed on a linear Bezier at {}. Got {}.",
+                t,
+                eased
+            );
+        }
+
+        let eased = ease.ease(0.5);
+        assert!(
+            (eased - 0.5).abs() < 1.0e-4 && ease.ease(0.1) < 0.1,
+            "Interpolation::ease() failed on an ease-in-out Bezier. Got {} at 0.5.",
+            eased
+        );
+
+        // Control points outside [0, 1] in x would make the curve go back in time.
+        for json in [
+            r#"{"Bezier": [2.0, 0.0, -1.0, 1.0]}"#,
+            r#"{"Bezier": [0.5, 0.0, 1.5, 1.0]}"#,
+        ] {
+            let parsed = serde_json::from_str::<Interpolation>(json);
+            assert!(
+                parsed.is_err(),
+                "Interpolation deserialization failed on {}. Expected an error, got {:?}.",
+                json,
+                parsed
+            );
+        }
+    }
+}
@@ -0,0 +1,927 @@
+use std::f32::consts::PI;
+use std::ptr;
+
+use crate::integrator::{first_hit, Integrator};
+use crate::material::{Material, Scatterer};
+use crate::object::{Emitter, Intersection, Object};
+use crate::random;
+use crate::render::Film;
+use crate::scene::Scene;
+use crate::vector::Vector3;
+use crate::{Camera, Ray};
+
+/// A bidirectional path tracer. Each sample traces one subpath from the camera and one from a
+/// point sampled on an emissive object, connects every vertex of one to every vertex of the
+/// other, and weights the strategies that could have found each path with the balance
+/// heuristic. Paths of up to `max_depth` bounces are found, including those to emissive
+/// objects, which `PathTracer` only finds with bounce rays and so one bounce sooner.
+///
+/// When rendering onto a film, light subpaths are also connected straight to the camera and
+/// splatted onto the pixels they reach, which finds caustics cast onto diffuse surfaces by
+/// glass far more often than camera subpaths do. `radiance` alone has no film, so it leaves
+/// that strategy out of the balance heuristic.
+///
+/// Punctual lights and the environment are sampled at every vertex of the camera subpath as
+/// they are by `PathTracer`, and participating media are ignored. In spectral mode only the
+/// hero wavelength is carried, so that both subpaths agree on it.
+pub struct Bidirectional {
+    pub max_depth: usize,
+}
+
+/// What lies at a vertex of a subpath.
+enum Kind<'a> {
+    /// The pinhole of the `camera` at `time`. When rendering onto a `film`, the camera casts
+    /// its ray through a pixel chosen uniformly, and light subpaths are connected to it.
+    Camera {
+        camera: &'a Camera,
+        time: f32,
+        film: Option<&'a Film>,
+    },
+    /// A point sampled on an emissive object, which starts a light subpath.
+    Light { object: &'a Object, uv: (f32, f32) },
+    Surface {
+        object: &'a Object,
+        intersection: Intersection,
+    },
+}
+
+/// A vertex of a camera or light subpath. `beta` is the throughput of the subpath up to the
+/// vertex, and `pdf_fwd` and `pdf_rev` are the densities per unit area with which the vertex is
+/// sampled by its own subpath and would be sampled by the opposite subpath. Surface normals face
+/// the previous vertex of the subpath, while the normal of a light vertex faces out of the
+/// emitter.
+struct Vertex<'a> {
+    kind: Kind<'a>,
+    position: Vector3,
+    normal: Vector3,
+    beta: Vector3,
+    delta: bool,
+    pdf_fwd: f32,
+    pdf_rev: f32,
+}
+
+impl<'a> Vertex<'a> {
+    /// Return the material at this vertex, which the camera does not have.
+    fn material(&self) -> Option<&'a Material> {
+        match self.kind {
+            Kind::Camera { .. } => None,
+            Kind::Light { object, .. } | Kind::Surface { object, .. } => Some(&object.material),
+        }
+    }
+
+    /// Convert the solid-angle density `pdf` of a direction sampled at this vertex to the
+    /// density per unit area of finding `next` in that direction.
+    fn convert(&self, pdf: f32, next: &Vertex) -> f32 {
+        let delta = next.position - self.position;
+        let distance2 = delta.squared_norm();
+        if distance2 == 0.0 {
+            return 0.0;
+        }
+
+        let cos_theta = if next.normal == Vector3::zeros() {
+            1.0
+        } else {
+            next.normal.dot(delta).abs() / distance2.sqrt()
+        };
+        pdf * cos_theta / distance2
+    }
+
+    /// Return the density per unit area with which this vertex samples `next` after being
+    /// reached from `prev`. Light vertices emit towards `next` instead.
+    fn pdf(&self, prev: Option<&Vertex>, next: &Vertex) -> f32 {
+        let dir = (next.position - self.position).normalized();
+        let pdf = match (&self.kind, prev) {
+            (
+                Kind::Camera {
+                    camera,
+                    time,
+                    film: Some(film),
+                },
+                _,
+            ) => camera_pdf(camera, *time, film, next.position),
+            (Kind::Light { object, .. }, _) => emission_pdf(&object.material, self.normal, dir),
+            (Kind::Surface { object, .. }, Some(prev)) => {
+                let view = (prev.position - self.position).normalized();
+                object.material.pdf(view, dir, self.normal)
+            }
+            _ => 0.0,
+        };
+
+        self.convert(pdf, next)
+    }
+
+    /// Return the density per unit area with which a light subpath starting at this vertex
+    /// finds `next`.
+    fn emission_pdf(&self, next: &Vertex) -> f32 {
+        let dir = (next.position - self.position).normalized();
+        match self.material() {
+            Some(material) => self.convert(emission_pdf(material, self.normal, dir), next),
+            None => 0.0,
+        }
+    }
+
+    /// Return the radiance emitted from this vertex towards `to`. Surface vertices only know
+    /// the radiance emitted towards the previous vertex of their subpath, which `to` must be.
+    fn emitted(&self, to: Vector3, ray: &Ray) -> Vector3 {
+        match &self.kind {
+            Kind::Camera { .. } => Vector3::zeros(),
+            Kind::Light { object, uv } => {
+                let intersection = Intersection {
+                    position: self.position,
+                    normal: self.normal,
+                    uv: *uv,
+                    front_face: (to - self.position).dot(self.normal) > 0.0,
+                };
+                ray.upsample(object.emitted(&intersection, ray.time))
+            }
+            Kind::Surface {
+                object,
+                intersection,
+            } => ray.upsample(object.emitted(intersection, ray.time)),
+        }
+    }
+
+    /// Evaluate the BSDF at this vertex for light arriving from `from` and leaving towards `to`.
+    fn bsdf(&self, to: &Vertex, from: &Vertex, ray: &Ray) -> Vector3 {
+        let Kind::Surface { object, .. } = &self.kind else {
+            return Vector3::zeros();
+        };
+
+        // Every material that can be connected to only reflects light.
+        let view = (to.position - self.position).normalized();
+        let dir = (from.position - self.position).normalized();
+        if view.dot(self.normal) <= 0.0 || dir.dot(self.normal) <= 0.0 {
+            return Vector3::zeros();
+        }
+
+        ray.upsample(object.material.eval(view, dir, self.normal, ray.time))
+    }
+}
+
+/// Return the solid-angle density with which a light subpath leaves an emissive `material` with
+/// the outward `normal` in the direction `dir`.
+fn emission_pdf(material: &Material, normal: Vector3, dir: Vector3) -> f32 {
+    let cos_theta = normal.dot(dir);
+    match material {
+        Material::Emissive {
+            two_sided: true, ..
+        } => cos_theta.abs() / (2.0 * PI),
+        Material::Emissive { .. } => cos_theta.max(0.0) / PI,
+        _ => 0.0,
+    }
+}
+
+/// Return the solid-angle density with which the `camera` casts its ray at `time` towards
+/// `point` when rendering onto the `film`. Each pixel is chosen uniformly, so this is the
+/// importance of the pixel in that direction shared between every pixel of the film.
+fn camera_pdf(camera: &Camera, time: f32, film: &Film, point: Vector3) -> f32 {
+    let pixels = film.width as f32 * film.height as f32;
+    camera
+        .importance(point, time, film.width, film.height)
+        .map_or(0.0, |(_, _, importance)| importance / pixels)
+}
+
+/// Return the fraction of light that travels between the vertices `a` and `b` at the time and
+/// wavelengths of `ray`.
+fn visibility(scene: &Scene, a: &Vertex, b: &Vertex, ray: &Ray) -> Vector3 {
+    let delta = b.position - a.position;
+    let distance = delta.norm();
+    let shadow = Ray {
+        origin: a.position,
+        direction: delta * (1.0 / distance),
+        ..*ray
+    };
+    scene.transmittance(shadow, distance, None)
+}
+
+/// Return the geometric term between two vertices, which converts the density of a connection
+/// between them from solid angle at one to area at the other.
+fn geometry(a: &Vertex, b: &Vertex) -> f32 {
+    let delta = b.position - a.position;
+    let distance2 = delta.squared_norm();
+    if distance2 == 0.0 {
+        return 0.0;
+    }
+
+    let dir = delta * (1.0 / distance2.sqrt());
+    let cos = |v: &Vertex| {
+        if v.normal == Vector3::zeros() {
+            1.0
+        } else {
+            v.normal.dot(dir).abs()
+        }
+    };
+    cos(a) * cos(b) / distance2
+}
+
+/// Sample a point on one of the `emitters`, chosen uniformly, as the first vertex of a light
+/// subpath.
+fn sample_emitter<'a>(emitters: &[Emitter<'a>]) -> Option<Vertex<'a>> {
+    if emitters.is_empty() {
+        return None;
+    }
+
+    let n = emitters.len();
+    let emitter = &emitters[((random::uniform() * n as f32) as usize).min(n - 1)];
+    let (position, normal, uv, pdf) = emitter.sample();
+    if pdf <= 0.0 || !pdf.is_finite() {
+        return None;
+    }
+
+    Some(Vertex {
+        kind: Kind::Light {
+            object: emitter.object,
+            uv,
+        },
+        position,
+        normal,
+        // The throughput of a light vertex is never used, since connections to it are
+        // evaluated from the radiance it emits.
+        beta: Vector3::zeros(),
+        delta: false,
+        pdf_fwd: pdf / n as f32,
+        pdf_rev: 0.0,
+    })
+}
+
+/// Return the density per unit area with which `sample_emitter` picks the position of the
+/// surface `vertex`, which is zero if it is not on any of the `emitters`.
+fn emitter_pdf(emitters: &[Emitter], vertex: &Vertex) -> f32 {
+    let Kind::Surface { object, .. } = vertex.kind else {
+        return 0.0;
+    };
+
+    emitters
+        .iter()
+        .filter(|emitter| ptr::eq(emitter.object, object))
+        .map(|emitter| emitter.pdf(vertex.position))
+        .find(|pdf| *pdf > 0.0)
+        .map_or(0.0, |pdf| pdf / emitters.len() as f32)
+}
+
+impl Bidirectional {
+    /// Trace a subpath from the camera along `ray`, returning its vertices together with the
+    /// light from punctual lights and the environment that it gathered.
+    fn camera_subpath<'a>(
+        &self,
+        scene: &'a Scene,
+        ray: Ray,
+        film: Option<&'a Film>,
+    ) -> (Vec<Vertex<'a>>, Vector3) {
+        let mut vertices = vec![Vertex {
+            kind: Kind::Camera {
+                camera: &scene.camera,
+                time: ray.time,
+                film,
+            },
+            position: ray.origin,
+            normal: Vector3::zeros(),
+            beta: Vector3::ones(),
+            delta: false,
+            pdf_fwd: 1.0,
+            pdf_rev: 0.0,
+        }];
+        let mut lighting = Vector3::zeros();
+
+        // The density of the camera ray is only needed when light subpaths are connected to
+        // the camera.
+        let pdf = film.map_or(1.0, |film| {
+            camera_pdf(&scene.camera, ray.time, film, ray.at(1.0))
+        });
+        self.walk(
+            scene,
+            ray,
+            Vector3::ones(),
+            pdf,
+            self.max_depth + 2,
+            &mut vertices,
+            Some(&mut lighting),
+        );
+
+        (vertices, lighting)
+    }
+
+    /// Trace a subpath from a point sampled on one of the `emitters`, carrying the wavelengths
+    /// of the camera `ray`.
+    fn light_subpath<'a>(
+        &self,
+        scene: &'a Scene,
+        emitters: &[Emitter<'a>],
+        ray: Ray,
+    ) -> Vec<Vertex<'a>> {
+        let Some(light) = sample_emitter(emitters) else {
+            return Vec::new();
+        };
+
+        // Leave the emitter in a cosine-weighted direction from one of its emitting faces.
+        let Some(material) = light.material() else {
+            return Vec::new();
+        };
+        let side = match material {
+            Material::Emissive {
+                two_sided: true, ..
+            } if random::uniform() < 0.5 => -light.normal,
+            _ => light.normal,
+        };
+        let direction = random::cosine_hemisphere(side);
+        let pdf = emission_pdf(material, light.normal, direction);
+        if pdf <= 0.0 {
+            return vec![light];
+        }
+
+        let emitted = light.emitted(light.position + direction, &ray);
+        let beta = emitted * (side.dot(direction) / (light.pdf_fwd * pdf));
+        let ray = Ray {
+            origin: light.position,
+            direction,
+            ..ray
+        };
+
+        let mut vertices = vec![light];
+        if beta != Vector3::zeros() {
+            self.walk(
+                scene,
+                ray,
+                beta,
+                pdf,
+                self.max_depth + 1,
+                &mut vertices,
+                None,
+            );
+        }
+
+        vertices
+    }
+
+    /// Extend a subpath from its last vertex along `ray`, which was sampled with the
+    /// solid-angle density `pdf` and carries the throughput `beta`, until it has `max_vertices`
+    /// vertices or ends. Camera subpaths pass `lighting`, which gathers light from punctual
+    /// lights and the environment at each vertex as `Scene::trace` does.
+    #[allow(clippy::too_many_arguments)]
+    fn walk<'a>(
+        &self,
+        scene: &'a Scene,
+        ray: Ray,
+        beta: Vector3,
+        pdf: f32,
+        max_vertices: usize,
+        vertices: &mut Vec<Vertex<'a>>,
+        mut lighting: Option<&mut Vector3>,
+    ) {
+        let camera = lighting.is_some();
+        let (mut ray, mut beta, mut pdf) = (ray, beta, pdf);
+        let mut tmin = if camera { 0.0 } else { 1.0e-3 };
+        let mut bsdf_pdf = None;
+
+        while vertices.len() < max_vertices {
+            // The number of bounces on the subpath before the next vertex.
+            let bounces = vertices.len() - 1;
+            let Some((_, intersection, object)) = first_hit(scene, ray, tmin) else {
+                if let Some(lighting) = lighting {
+                    if bounces == 0 || bounces < self.max_depth {
+                        let mut radiance = ray.upsample(scene.environment.radiance(ray.direction));
+                        if let Some(pdf) = bsdf_pdf {
+                            let light_pdf = scene.environment.pdf(ray.direction);
+                            radiance = random::power_heuristic(pdf, light_pdf) * radiance;
+                        }
+                        *lighting = *lighting + beta.cwise_mul(radiance);
+                    }
+                }
+                break;
+            };
+
+            let (position, normal) = (intersection.position, intersection.normal);
+            let mut vertex = Vertex {
+                kind: Kind::Surface {
+                    object,
+                    intersection,
+                },
+                position,
+                normal,
+                beta,
+                delta: false,
+                pdf_fwd: 0.0,
+                pdf_rev: 0.0,
+            };
+            vertex.pdf_fwd = vertices[bounces].convert(pdf, &vertex);
+            vertices.push(vertex);
+            if vertices.len() >= max_vertices {
+                break;
+            }
+
+            let material = &object.material;
+            if let Some(lighting) = &mut lighting {
+                let bounces = bounces + 1;
+                if bounces <= self.max_depth && !matches!(material, Material::Emissive { .. }) {
+                    let scatterer = Scatterer::Surface { material, normal };
+                    let mis = bounces < self.max_depth;
+                    let direct = scene.direct_lighting(&scatterer, ray, position, mis, None);
+                    **lighting = **lighting + beta.cwise_mul(direct);
+                }
+            }
+
+            let last = vertices.len() - 1;
+            let Kind::Surface { intersection, .. } = &vertices[last].kind else {
+                break;
+            };
+            let Some((bounce, sample, weight)) = material.bounce(ray, intersection) else {
+                break;
+            };
+            let weight = if camera {
+                weight
+            } else {
+                material.importance_weight(&ray, intersection, &sample, weight)
+            };
+
+            // Specular vertices cannot be connected to, so their densities are left out of the
+            // weights of the strategies.
+            let view = -ray.direction;
+            let pdf_rev = if sample.specular {
+                0.0
+            } else {
+                material.pdf(sample.direction, view, normal)
+            };
+            pdf = if sample.specular { 0.0 } else { sample.pdf };
+            vertices[last].delta = sample.specular;
+            vertices[last - 1].pdf_rev = vertices[last].convert(pdf_rev, &vertices[last - 1]);
+
+            beta = beta.cwise_mul(weight);
+            bsdf_pdf = (!sample.specular).then_some(sample.pdf);
+            ray = bounce;
+            tmin = 1.0e-3;
+            if beta == Vector3::zeros() {
+                break;
+            }
+        }
+    }
+
+    /// Return the contribution of the path made of the first `s` vertices of the `light`
+    /// subpath and the first `t` vertices of the `camera` subpath, weighted against the other
+    /// strategies that could have found it. The light vertex is sampled afresh when `s` is one.
+    #[allow(clippy::too_many_arguments)]
+    fn connect(
+        &self,
+        scene: &Scene,
+        emitters: &[Emitter],
+        light: &[Vertex],
+        camera: &[Vertex],
+        s: usize,
+        t: usize,
+        ray: &Ray,
+    ) -> Vector3 {
+        let pt = &camera[t - 1];
+        let pt_minus = &camera[t - 2];
+        let sampled = if s == 1 {
+            match sample_emitter(emitters) {
+                Some(vertex) => Some(vertex),
+                None => return Vector3::zeros(),
+            }
+        } else {
+            None
+        };
+        let qs = match s {
+            0 => None,
+            1 => sampled.as_ref(),
+            _ => Some(&light[s - 1]),
+        };
+
+        let contribution = match qs {
+            None => pt.beta.cwise_mul(pt.emitted(pt_minus.position, ray)),
+            Some(qs) => {
+                if qs.delta || pt.delta {
+                    return Vector3::zeros();
+                }
+
+                let from_light = if s == 1 {
+                    qs.emitted(pt.position, ray) * (1.0 / qs.pdf_fwd)
+                } else {
+                    qs.beta.cwise_mul(qs.bsdf(pt, &light[s - 2], ray))
+                };
+                from_light
+                    .cwise_mul(pt.bsdf(pt_minus, qs, ray))
+                    .cwise_mul(pt.beta)
+                    * geometry(qs, pt)
+            }
+        };
+        if contribution == Vector3::zeros() {
+            return Vector3::zeros();
+        }
+
+        let visibility = match qs {
+            None => Vector3::ones(),
+            Some(qs) => visibility(scene, pt, qs, ray),
+        };
+        if visibility == Vector3::zeros() {
+            return Vector3::zeros();
+        }
+
+        let weight = self.mis_weight(emitters, light, camera, qs, s, t);
+        contribution.cwise_mul(visibility) * weight
+    }
+
+    /// Connect the last of the first `s` vertices of the `light` subpath straight to the first
+    /// vertex of the `camera` subpath, returning the pixel of the `film` the path reaches and
+    /// its contribution there, weighted against the other strategies that could have found it.
+    #[allow(clippy::too_many_arguments)]
+    fn connect_camera(
+        &self,
+        scene: &Scene,
+        emitters: &[Emitter],
+        light: &[Vertex],
+        camera: &[Vertex],
+        s: usize,
+        ray: &Ray,
+        film: &Film,
+    ) -> Option<(u32, u32, Vector3)> {
+        let (qs, pt) = (&light[s - 1], &camera[0]);
+        if qs.delta {
+            return None;
+        }
+
+        let (x, y, importance) =
+            scene
+                .camera
+                .importance(qs.position, ray.time, film.width, film.height)?;
+        let contribution =
+            qs.beta.cwise_mul(qs.bsdf(pt, &light[s - 2], ray)) * (importance * geometry(qs, pt));
+        if contribution == Vector3::zeros() {
+            return None;
+        }
+
+        let visibility = visibility(scene, pt, qs, ray);
+        if visibility == Vector3::zeros() {
+            return None;
+        }
+
+        let weight = self.mis_weight(emitters, light, camera, Some(qs), s, 1);
+        Some((x, y, contribution.cwise_mul(visibility) * weight))
+    }
+
+    /// Return the balance heuristic weight of the strategy that joins the first `s` vertices
+    /// of the `light` subpath, whose last vertex is `qs`, to the first `t` vertices of the
+    /// `camera` subpath. Light subpaths are only connected to the camera when rendering onto a
+    /// film, and the strategy is only weighed against the others then.
+    fn mis_weight(
+        &self,
+        emitters: &[Emitter],
+        light: &[Vertex],
+        camera: &[Vertex],
+        qs: Option<&Vertex>,
+        s: usize,
+        t: usize,
+    ) -> f32 {
+        // Only the camera subpath finds paths straight from the camera to an emitter.
+        if s + t == 2 {
+            return 1.0;
+        }
+
+        let pdfs = |v: &Vertex| (v.pdf_fwd, v.pdf_rev, v.delta);
+        let mut camera_pdfs: Vec<_> = camera[..t].iter().map(pdfs).collect();
+        let mut light_pdfs: Vec<_> = match qs {
+            Some(qs) => light[..s - 1].iter().chain([qs]).map(pdfs).collect(),
+            None => Vec::new(),
+        };
+
+        // Find the reverse densities of the vertices next to the connection, as they are on
+        // this path rather than on the subpaths they came from.
+        let pt = &camera[t - 1];
+        let pt_minus = t.checked_sub(2).map(|i| &camera[i]);
+        match qs {
+            Some(qs) => {
+                let qs_minus = s.checked_sub(2).map(|i| &light[i]);
+                camera_pdfs[t - 1].1 = qs.pdf(qs_minus, pt);
+                if let Some(pt_minus) = pt_minus {
+                    camera_pdfs[t - 2].1 = pt.pdf(Some(qs), pt_minus);
+                }
+                light_pdfs[s - 1].1 = pt.pdf(pt_minus, qs);
+                if let Some(qs_minus) = qs_minus {
+                    light_pdfs[s - 2].1 = qs.pdf(Some(pt), qs_minus);
+                }
+            }
+            None => {
+                // Emitters that cannot be sampled are only ever found by camera subpaths.
+                let origin = emitter_pdf(emitters, pt);
+                if origin == 0.0 {
+                    return 1.0;
+                }
+                camera_pdfs[t - 1].1 = origin;
+                if let Some(pt_minus) = pt_minus {
+                    camera_pdfs[t - 2].1 = pt.emission_pdf(pt_minus);
+                }
+            }
+        }
+
+        // Densities of specular vertices are zero and cancel out of the ratios.
+        let remap = |pdf: f32| if pdf != 0.0 { pdf } else { 1.0 };
+
+        // Move the connection towards the camera, which only becomes a connection vertex when
+        // light subpaths are connected to it.
+        let light_tracing = matches!(camera[0].kind, Kind::Camera { film: Some(_), .. });
+        let last = if light_tracing { 1 } else { 2 };
+        let mut sum = 0.0;
+        let mut ratio = 1.0;
+        for i in (last..t).rev() {
+            let (fwd, rev, delta) = camera_pdfs[i];
+            ratio *= remap(rev) / remap(fwd);
+            if !delta && !camera_pdfs[i - 1].2 {
+                sum += ratio;
+            }
+        }
+
+        // Move the connection towards the light.
+        let mut ratio = 1.0;
+        for i in (0..s).rev() {
+            let (fwd, rev, delta) = light_pdfs[i];
+            ratio *= remap(rev) / remap(fwd);
+            let delta_before = i > 0 && light_pdfs[i - 1].2;
+            if !delta && !delta_before {
+                sum += ratio;
+            }
+        }
+
+        1.0 / (1.0 + sum)
+    }
+}
+
+impl Bidirectional {
+    /// Estimate the light arriving along the camera `ray`, splatting the light subpaths
+    /// connected to the camera onto the `film` if there is one.
+    fn estimate(&self, scene: &Scene, ray: Ray, film: Option<&Film>) -> Vector3 {
+        let wavelengths = ray.wavelengths;
+        let mut ray = ray;
+        let mut weight = Vector3::ones();
+        if let Some(wavelengths) = &mut ray.wavelengths {
+            weight = wavelengths.terminate_secondary();
+        }
+
+        let emitters = scene.emitters(ray.time);
+        let (camera, mut radiance) = self.camera_subpath(scene, ray, film);
+        let light = self.light_subpath(scene, &emitters, ray);
+
+        for t in 2..=camera.len() {
+            for s in 0..=light.len() {
+                if s + t - 2 > self.max_depth {
+                    break;
+                }
+                radiance = radiance + self.connect(scene, &emitters, &light, &camera, s, t, &ray);
+            }
+        }
+
+        if let Some(film) = film {
+            for s in 2..=light.len() {
+                let Some((x, y, splat)) =
+                    self.connect_camera(scene, &emitters, &light, &camera, s, &ray, film)
+                else {
+                    continue;
+                };
+                let splat = weight.cwise_mul(splat);
+                film.splat(
+                    x,
+                    y,
+                    match &wavelengths {
+                        Some(wavelengths) => wavelengths.to_rgb(splat),
+                        None => splat,
+                    },
+                );
+            }
+        }
+
+        weight.cwise_mul(radiance)
+    }
+}
+
+impl Integrator for Bidirectional {
+    fn radiance(&self, scene: &Scene, ray: Ray) -> Vector3 {
+        self.estimate(scene, ray, None)
+    }
+
+    fn radiance_with_film(&self, scene: &Scene, ray: Ray, film: &Film) -> Vector3 {
+        self.estimate(scene, ray, Some(film))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::integrator::PathTracer;
+    use crate::render::Tile;
+
+    const SEED: u64 = 1;
+
+    /// Return the mean radiance found along `ray` by `integrator` over `n` samples.
+    fn estimate(scene: &Scene, integrator: &dyn Integrator, ray: Ray, n: usize) -> Vector3 {
+        random::reseed(SEED, 0);
+        (0..n)
+            .map(|_| integrator.radiance(scene, ray))
+            .fold(Vector3::zeros(), |a, b| a + b)
+            * (1.0 / n as f32)
+    }
+
+    /// Render a `size` by `size` image of `scene` with `integrator` and `samples` per pixel
+    /// as `Scene::render_with` does, returning its pixels in linear sRGB.
+    fn render(
+        scene: &Scene,
+        integrator: &dyn Integrator,
+        size: u32,
+        samples: usize,
+        seed: u64,
+    ) -> Vec<Vector3> {
+        let window = Tile {
+            x: 0,
+            y: 0,
+            width: size,
+            height: size,
+        };
+        let film = Film::new(size, size, window);
+
+        random::reseed(seed, 0);
+        let mut pixels = Vec::new();
+        for y in 0..size {
+            for x in 0..size {
+                let radiance = (0..samples)
+                    .map(|_| {
+                        let ray = scene.camera.ray(x, y, size, size, 0.0);
+                        integrator.radiance_with_film(scene, ray, &film)
+                    })
+                    .fold(Vector3::zeros(), |a, b| a + b);
+                pixels.push(radiance * (1.0 / samples as f32));
+            }
+        }
+
+        // Each camera ray traced one light subpath for the film.
+        let rays = (size * size) as usize * samples;
+        for (i, pixel) in pixels.iter_mut().enumerate() {
+            let (x, y) = (i as u32 % size, i as u32 / size);
+            *pixel = *pixel + film.get(x, y) * (1.0 / rays as f32);
+        }
+        pixels
+    }
+
+    #[test]
+    fn test_matches_path_tracer() {
+        // A large diffuse ball lit from above by an emissive ball. The path tracer only finds
+        // emissive objects with bounce rays, so it needs one more bounce for the same paths.
+        let mut scene: Scene = serde_json::from_str(
+            r#"{
+                "camera": {"focal_len": 1.0, "width": 1.0},
+                "objects": [
+                    {
+                        "object": "Sphere",
+                        "material": {"Diffuse": {"color": {"x": 0.8, "y": 0.5, "z": 0.2}}},
+                        "transforms": [
+                            {"Scale": {"x": 100.0, "y": 100.0, "z": 100.0}},
+                            {"Translate": {"x": 0.0, "y": -101.0, "z": 5.0}}
+                        ]
+                    },
+                    {
+                        "object": "Sphere",
+                        "material": {"Emissive": {"color": {"x": 1.0, "y": 1.0, "z": 1.0}, "intensity": 4.0}},
+                        "transforms": [{"Translate": {"x": 0.0, "y": 2.0, "z": 5.0}}]
+                    }
+                ]
+            }"#,
+        )
+        .unwrap();
+        scene.link().unwrap();
+
+        let ray = Ray::new(Vector3::zeros(), Vector3::unit(0.0, -1.0, 5.0));
+        let expected = estimate(&scene, &PathTracer { max_depth: 3 }, ray, 20000);
+        let radiance = estimate(&scene, &Bidirectional { max_depth: 2 }, ray, 20000);
+        assert!(
+            (radiance - expected).norm() < 0.05 * expected.norm(),
+            "Bidirectional::radiance() failed on a lit ball. Expected {}, got {}.",
+            expected,
+            radiance
+        );
+    }
+
+    #[test]
+    fn test_matches_path_tracer_with_mirror() {
+        // A mirror ball resting on a diffuse floor next to an emissive ball. Looking at the
+        // floor beside the mirror sees the light it reflects there, which only bounce rays
+        // through the mirror find, and looking at the mirror sees the lit floor and the light.
+        // The path tracer rarely finds the light through the mirror, so it takes more samples.
+        let mut scene: Scene = serde_json::from_str(
+            r#"{
+                "camera": {"focal_len": 1.0, "width": 1.0},
+                "objects": [
+                    {
+                        "object": "Sphere",
+                        "material": {"Diffuse": {"color": {"x": 0.8, "y": 0.8, "z": 0.8}}},
+                        "transforms": [
+                            {"Scale": {"x": 100.0, "y": 100.0, "z": 100.0}},
+                            {"Translate": {"x": 0.0, "y": -101.0, "z": 5.0}}
+                        ]
+                    },
+                    {
+                        "object": "Sphere",
+                        "material": {"Specular": {"color": {"x": 0.9, "y": 0.9, "z": 0.9}, "roughness": 0.1}},
+                        "transforms": [
+                            {"Scale": {"x": 0.5, "y": 0.5, "z": 0.5}},
+                            {"Translate": {"x": 0.8, "y": -0.5, "z": 5.0}}
+                        ]
+                    },
+                    {
+                        "object": "Sphere",
+                        "material": {"Emissive": {"color": {"x": 1.0, "y": 1.0, "z": 1.0}, "intensity": 4.0}},
+                        "transforms": [
+                            {"Translate": {"x": 1.5, "y": 1.5, "z": 5.0}}
+                        ]
+                    }
+                ]
+            }"#,
+        )
+        .unwrap();
+        scene.link().unwrap();
+
+        let rays = [
+            ("the floor", Vector3::unit(0.0, -1.0, 5.0)),
+            ("the mirror", Vector3::unit(0.4, -0.7, 4.8)),
+        ];
+        for (name, direction) in rays {
+            let ray = Ray::new(Vector3::zeros(), direction);
+            let expected = estimate(&scene, &PathTracer { max_depth: 4 }, ray, 200000);
+            let radiance = estimate(&scene, &Bidirectional { max_depth: 3 }, ray, 50000);
+            assert!(
+                (radiance - expected).norm() < 0.05 * expected.norm(),
+                "Bidirectional::radiance() failed on {}. Expected {}, got {}.",
+                name,
+                expected,
+                radiance
+            );
+        }
+    }
+
+    #[test]
+    fn test_caustic() {
+        // A glass ball focuses a small emissive ball onto a diffuse wall behind it, in the
+        // ball's shadow. Camera subpaths only find the caustic by bouncing off the wall through
+        // the glass and hitting the light, while light subpaths reach it through the glass and
+        // are connected to the camera, so splatting them onto the film is much less noisy.
+        let mut scene: Scene = serde_json::from_str(
+            r#"{
+                "camera": {"focal_len": 1.0, "width": 0.25},
+                "objects": [
+                    {
+                        "object": "Sphere",
+                        "material": {"Diffuse": {"color": {"x": 0.8, "y": 0.8, "z": 0.8}}},
+                        "transforms": [
+                            {"Scale": {"x": 100.0, "y": 100.0, "z": 100.0}},
+                            {"Translate": {"x": 0.0, "y": 0.0, "z": 106.0}}
+                        ]
+                    },
+                    {
+                        "object": "Sphere",
+                        "material": {"Dielectric": {"ior": {"Constant": 1.5}}},
+                        "transforms": [
+                            {"Scale": {"x": 0.5, "y": 0.5, "z": 0.5}},
+                            {"Translate": {"x": 0.8, "y": 0.0, "z": 5.0}}
+                        ]
+                    },
+                    {
+                        "object": "Sphere",
+                        "material": {"Emissive": {"color": {"x": 1.0, "y": 1.0, "z": 1.0}, "intensity": 200.0}},
+                        "transforms": [
+                            {"Scale": {"x": 0.05, "y": 0.05, "z": 0.05}},
+                            {"Translate": {"x": 1.7, "y": 0.0, "z": 3.8}}
+                        ]
+                    }
+                ]
+            }"#,
+        )
+        .unwrap();
+        scene.link().unwrap();
+
+        // The noise of each integrator is found from the difference between two renders with
+        // different seeds, and the path tracer takes many more samples for the expected image.
+        let renders = |integrator: &dyn Integrator, samples: usize| {
+            [1, 2].map(|seed| render(&scene, integrator, 32, samples, seed))
+        };
+        let mean = |images: &[Vec<Vector3>]| {
+            let pixels = images.iter().flatten();
+            pixels.fold(Vector3::zeros(), |a, b| a + *b) * (1.0 / (2 * 32 * 32) as f32)
+        };
+        let noise = |[a, b]: &[Vec<Vector3>; 2]| {
+            let differences = a.iter().zip(b).map(|(a, b)| (*a - *b).squared_norm());
+            differences.sum::<f32>() / (2 * 32 * 32) as f32
+        };
+
+        let bidirectional = renders(&Bidirectional { max_depth: 3 }, 16);
+        let path_tracer = renders(&PathTracer { max_depth: 4 }, 16);
+        let expected = mean(&renders(&PathTracer { max_depth: 4 }, 256));
+        let radiance = mean(&bidirectional);
+        assert!(
+            (radiance - expected).norm() < 0.05 * expected.norm(),
+            "Bidirectional::radiance_with_film() failed on a caustic. Expected {}, got {}.",
+            expected,
+            radiance
+        );
+        assert!(
+            noise(&bidirectional) < 0.25 * noise(&path_tracer),
+            "Bidirectional::radiance_with_film() failed on a caustic. Expected less noise than \
+             PathTracer's {}, got {}.",
+            noise(&path_tracer),
+            noise(&bidirectional)
+        );
+    }
+}
@@ -0,0 +1,53 @@
+use crate::vector::Vector3;
+
+/// Compute the relative luminance of a linear sRGB color.
+pub fn luminance(rgb: Vector3) -> f32 {
+    0.2126 * rgb.x() + 0.7152 * rgb.y() + 0.0722 * rgb.z()
+}
+
+/// Apply the sRGB transfer function to a linear value, giving the value stored in an image.
+pub fn linear_to_srgb(u: f32) -> f32 {
+    if u < 0.0031308 {
+        12.92 * u
+    } else {
+        1.055 * u.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Undo the sRGB transfer function, giving the linear value of a value stored in an image.
+pub fn srgb_to_linear(u: f32) -> f32 {
+    if u < 0.04045 {
+        u / 12.92
+    } else {
+        ((u + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Convert a CIE XYZ color to linear sRGB. Colors outside the sRGB gamut have negative
+/// components.
+pub fn xyz_to_linear_srgb(xyz: Vector3) -> Vector3 {
+    let (x, y, z) = (xyz.x(), xyz.y(), xyz.z());
+    Vector3::new(
+        3.2406 * x - 1.5372 * y - 0.4986 * z,
+        -0.9689 * x + 1.8758 * y + 0.0415 * z,
+        0.0557 * x - 0.2040 * y + 1.0570 * z,
+    )
+}
+
+/// Convert a linear sRGB color to CIE XYZ.
+pub fn linear_srgb_to_xyz(rgb: Vector3) -> Vector3 {
+    let (r, g, b) = (rgb.x(), rgb.y(), rgb.z());
+    Vector3::new(
+        0.4124 * r + 0.3576 * g + 0.1805 * b,
+        0.2126 * r + 0.7152 * g + 0.0722 * b,
+        0.0193 * r + 0.1192 * g + 0.9505 * b,
+    )
+}
+
+/// Convert a color given by its CIE `x`, `y` chromaticity and luminance `lum` to CIE XYZ.
+pub fn xyy_to_xyz(x: f32, y: f32, lum: f32) -> Vector3 {
+    if y <= 0.0 {
+        return Vector3::zeros();
+    }
+    Vector3::new(x * lum / y, lum, (1.0 - x - y) * lum / y)
+}
@@ -0,0 +1,412 @@
+use crate::color;
+use crate::image::Image;
+use crate::vector::Vector3;
+
+use std::fmt;
+use std::str::FromStr;
+
+/// How far a test image is from a reference image.
+/// - mse: The mean squared error over every channel of every pixel.
+/// - rel_mse: The mean squared error relative to the squared reference value, which weights
+///   errors in dark areas as much as those in bright ones.
+/// - psnr: The peak signal-to-noise ratio in decibels for values between 0 and 1, which is
+///   infinite for identical images.
+/// - ssim: The mean structural similarity of the luminance, which is 1 for identical images.
+/// - flip: The mean FLIP-style perceptual error, from 0 for identical images to 1.
+/// - errors: The FLIP-style error of each pixel in row-major order.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Comparison {
+    pub mse: f32,
+    pub rel_mse: f32,
+    pub psnr: f32,
+    pub ssim: f32,
+    pub flip: f32,
+    pub errors: Vec<f32>,
+    width: u32,
+    height: u32,
+}
+
+/// One of the measures of a `Comparison`, used to decide whether a test image is close enough.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Metric {
+    Mse,
+    RelMse,
+    Psnr,
+    Ssim,
+    Flip,
+}
+
+impl Metric {
+    /// Return whether a larger value of this metric means the images are closer.
+    pub fn higher_is_better(self) -> bool {
+        matches!(self, Metric::Psnr | Metric::Ssim)
+    }
+}
+
+impl FromStr for Metric {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_ascii_lowercase().as_str() {
+            "mse" => Ok(Metric::Mse),
+            "relmse" => Ok(Metric::RelMse),
+            "psnr" => Ok(Metric::Psnr),
+            "ssim" => Ok(Metric::Ssim),
+            "flip" => Ok(Metric::Flip),
+            _ => Err("expected one of mse, relmse, psnr, ssim and flip".to_string()),
+        }
+    }
+}
+
+impl fmt::Display for Metric {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Metric::Mse => "MSE",
+            Metric::RelMse => "relMSE",
+            Metric::Psnr => "PSNR",
+            Metric::Ssim => "SSIM",
+            Metric::Flip => "FLIP",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl Comparison {
+    /// Return the value of `metric`.
+    pub fn get(&self, metric: Metric) -> f32 {
+        match metric {
+            Metric::Mse => self.mse,
+            Metric::RelMse => self.rel_mse,
+            Metric::Psnr => self.psnr,
+            Metric::Ssim => self.ssim,
+            Metric::Flip => self.flip,
+        }
+    }
+
+    /// Return whether `metric` is worse than `threshold`, which is below it for PSNR and SSIM
+    /// and above it for the others.
+    pub fn exceeds(&self, metric: Metric, threshold: f32) -> bool {
+        let value = self.get(metric);
+        if metric.higher_is_better() {
+            value < threshold
+        } else {
+            value > threshold
+        }
+    }
+
+    /// Return an image of the error of each pixel in false color, running from black for no
+    /// error through purple and orange to pale yellow for the largest.
+    pub fn false_color(&self) -> Image {
+        const RAMP: [(f32, f32, f32); 5] = [
+            (0.0, 0.0, 0.02),
+            (0.23, 0.06, 0.44),
+            (0.72, 0.21, 0.47),
+            (0.99, 0.56, 0.38),
+            (0.99, 0.99, 0.75),
+        ];
+
+        let data = self
+            .errors
+            .iter()
+            .map(|&e| {
+                let t = e.clamp(0.0, 1.0) * (RAMP.len() - 1) as f32;
+                let i = (t as usize).min(RAMP.len() - 2);
+                let (a, b) = (RAMP[i], RAMP[i + 1]);
+                let (a, b) = (Vector3::new(a.0, a.1, a.2), Vector3::new(b.0, b.1, b.2));
+                a + (b - a) * (t - i as f32)
+            })
+            .collect();
+        Image::new(data, self.width, self.height)
+    }
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "MSE:    {:.6}", self.mse)?;
+        writeln!(f, "relMSE: {:.6}", self.rel_mse)?;
+        writeln!(f, "PSNR:   {:.2} dB", self.psnr)?;
+        writeln!(f, "SSIM:   {:.4}", self.ssim)?;
+        write!(f, "FLIP:   {:.4}", self.flip)
+    }
+}
+
+/// Compare a `test` image against a `reference`, both holding sRGB values between 0 and 1 as
+/// rendered images and PNG files do. Panics if the images differ in size.
+pub fn compare(reference: &Image, test: &Image) -> Comparison {
+    assert!(
+        reference.width() == test.width() && reference.height() == test.height(),
+        "Images of {}x{} and {}x{} pixels differ in size.",
+        reference.width(),
+        reference.height(),
+        test.width(),
+        test.height()
+    );
+
+    let channels = |p: Vector3| [p.x(), p.y(), p.z()];
+    let count = 3.0 * reference.pixels().len().max(1) as f32;
+    let (mut mse, mut rel_mse) = (0.0, 0.0);
+    for (r, t) in reference.pixels().iter().zip(test.pixels()) {
+        for (r, t) in channels(*r).into_iter().zip(channels(*t)) {
+            let error = (t - r) * (t - r);
+            mse += error / count;
+            // The small constant keeps black reference pixels from dominating.
+            rel_mse += error / (r * r + 0.01) / count;
+        }
+    }
+
+    let errors = flip(reference, test);
+    let flip = errors.iter().sum::<f32>() / errors.len().max(1) as f32;
+
+    Comparison {
+        mse,
+        rel_mse,
+        psnr: -10.0 * mse.log10(),
+        ssim: ssim(reference, test),
+        flip,
+        errors,
+        width: reference.width(),
+        height: reference.height(),
+    }
+}
+
+/// Blur `values`, an image of `width` pixels per row, with a Gaussian of standard deviation
+/// `sigma` pixels, extending the edges outwards.
+fn blur(values: &[Vector3], width: usize, sigma: f32) -> Vec<Vector3> {
+    let height = values.len() / width.max(1);
+    let radius = (3.0 * sigma).ceil() as isize;
+    let kernel: Vec<f32> = (-radius..=radius)
+        .map(|i| (-(i * i) as f32 / (2.0 * sigma * sigma)).exp())
+        .collect();
+    let total: f32 = kernel.iter().sum();
+
+    let pass = |values: &[Vector3], step: (isize, isize)| -> Vec<Vector3> {
+        (0..values.len())
+            .map(|i| {
+                let (x, y) = ((i % width) as isize, (i / width) as isize);
+                kernel
+                    .iter()
+                    .enumerate()
+                    .fold(Vector3::zeros(), |sum, (k, w)| {
+                        let offset = k as isize - radius;
+                        let sx = (x + offset * step.0).clamp(0, width as isize - 1);
+                        let sy = (y + offset * step.1).clamp(0, height as isize - 1);
+                        sum + values[sy as usize * width + sx as usize] * (*w / total)
+                    })
+            })
+            .collect()
+    };
+
+    pass(&pass(values, (1, 0)), (0, 1))
+}
+
+/// Compute the mean structural similarity of the luminance of two sRGB images, over Gaussian
+/// windows with a standard deviation of 1.5 pixels.
+fn ssim(reference: &Image, test: &Image) -> f32 {
+    const C1: f32 = 0.01 * 0.01;
+    const C2: f32 = 0.03 * 0.03;
+
+    // Blur the means and second moments of both images at once, packed into vectors.
+    let (means, moments): (Vec<Vector3>, Vec<Vector3>) = reference
+        .pixels()
+        .iter()
+        .zip(test.pixels())
+        .map(|(r, t)| {
+            let (a, b) = (color::luminance(*r), color::luminance(*t));
+            (Vector3::new(a, b, 0.0), Vector3::new(a * a, b * b, a * b))
+        })
+        .unzip();
+    let width = reference.width() as usize;
+    let (means, moments) = (blur(&means, width, 1.5), blur(&moments, width, 1.5));
+
+    let total: f32 = means
+        .iter()
+        .zip(&moments)
+        .map(|(mean, moment)| {
+            let (ma, mb) = (mean.x(), mean.y());
+            let (va, vb) = (moment.x() - ma * ma, moment.y() - mb * mb);
+            let covariance = moment.z() - ma * mb;
+            ((2.0 * ma * mb + C1) * (2.0 * covariance + C2))
+                / ((ma * ma + mb * mb + C1) * (va + vb + C2))
+        })
+        .sum();
+    total / means.len().max(1) as f32
+}
+
+/// Convert a linear sRGB color to CIE L*a*b* under a D65 white point.
+fn linear_srgb_to_lab(rgb: Vector3) -> Vector3 {
+    let xyz = color::linear_srgb_to_xyz(rgb);
+    let f = |t: f32| {
+        if t > 216.0 / 24389.0 {
+            t.cbrt()
+        } else {
+            t * 24389.0 / 27.0 / 116.0 + 16.0 / 116.0
+        }
+    };
+    let (fx, fy, fz) = (f(xyz.x() / 0.9505), f(xyz.y()), f(xyz.z() / 1.089));
+    Vector3::new(116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz))
+}
+
+/// Return the HyAB distance between two L*a*b* colors, which suits large color differences.
+fn hyab(a: Vector3, b: Vector3) -> f32 {
+    let d = a - b;
+    d.x().abs() + (d.y() * d.y() + d.z() * d.z()).sqrt()
+}
+
+/// Compute a per-pixel perceptual error between two sRGB images in the style of FLIP, which
+/// combines a color difference with a difference in edges and points.
+///
+/// The colors are blurred by about a pixel, as the eye does at a normal viewing distance,
+/// compared in L*a*b* with the HyAB distance, and scaled so that the difference between green
+/// and blue is 1. The lightness of each image is filtered to find edges and isolated points,
+/// and where these differ, the color error is raised towards 1, since the eye is drawn to
+/// them. This follows the structure of FLIP but not its exact filters, so its values are
+/// comparable only with each other.
+pub fn flip(reference: &Image, test: &Image) -> Vec<f32> {
+    // The exponents and the knee of the error compression used by FLIP.
+    const QC: f32 = 0.7;
+    const QF: f32 = 0.5;
+    const PC: f32 = 0.4;
+    const PT: f32 = 0.95;
+
+    let width = reference.width() as usize;
+    let linear = |image: &Image| -> Vec<Vector3> {
+        image
+            .pixels()
+            .iter()
+            .map(|p| {
+                p.cwise(Vector3::ones(), |u, _| {
+                    color::srgb_to_linear(u.clamp(0.0, 1.0))
+                })
+            })
+            .collect()
+    };
+    let (reference, test) = (linear(reference), linear(test));
+    let lab = |values: &[Vector3]| -> Vec<Vector3> {
+        values.iter().map(|p| linear_srgb_to_lab(*p)).collect()
+    };
+    let (blurred_reference, blurred_test) = (
+        lab(&blur(&reference, width, 1.0)),
+        lab(&blur(&test, width, 1.0)),
+    );
+
+    let green = linear_srgb_to_lab(Vector3::new(0.0, 1.0, 0.0));
+    let blue = linear_srgb_to_lab(Vector3::new(0.0, 0.0, 1.0));
+    let max = hyab(green, blue).powf(QC);
+
+    // Find the edge and point strength of the lightness between 0 and 1, with filters that
+    // respond with 1 to a full step and a lone pixel.
+    let features = |values: &[Vector3]| -> Vec<(f32, f32)> {
+        let lightness: Vec<f32> = lab(values).iter().map(|p| p.x() / 100.0).collect();
+        let height = lightness.len() / width.max(1);
+        let at = |x: isize, y: isize| {
+            let x = x.clamp(0, width as isize - 1) as usize;
+            let y = y.clamp(0, height as isize - 1) as usize;
+            lightness[y * width + x]
+        };
+
+        (0..lightness.len())
+            .map(|i| {
+                let (x, y) = ((i % width) as isize, (i / width) as isize);
+                let gx = (at(x + 1, y - 1) + 2.0 * at(x + 1, y) + at(x + 1, y + 1)
+                    - at(x - 1, y - 1)
+                    - 2.0 * at(x - 1, y)
+                    - at(x - 1, y + 1))
+                    / 4.0;
+                let gy = (at(x - 1, y + 1) + 2.0 * at(x, y + 1) + at(x + 1, y + 1)
+                    - at(x - 1, y - 1)
+                    - 2.0 * at(x, y - 1)
+                    - at(x + 1, y - 1))
+                    / 4.0;
+                let point =
+                    (4.0 * at(x, y) - at(x - 1, y) - at(x + 1, y) - at(x, y - 1) - at(x, y + 1))
+                        / 4.0;
+                ((gx * gx + gy * gy).sqrt(), point.abs())
+            })
+            .collect()
+    };
+    let (reference_features, test_features) = (features(&reference), features(&test));
+
+    (0..reference.len())
+        .map(|i| {
+            let color = hyab(blurred_reference[i], blurred_test[i]).powf(QC);
+            let color = if color < PC * max {
+                color * PT / (PC * max)
+            } else {
+                PT + (color - PC * max) / (max - PC * max) * (1.0 - PT)
+            }
+            .min(1.0);
+
+            let (edge, point) = (
+                (reference_features[i].0 - test_features[i].0).abs(),
+                (reference_features[i].1 - test_features[i].1).abs(),
+            );
+            let feature = (edge.max(point) / 2f32.sqrt()).min(1.0).powf(QF);
+            color.powf(1.0 - feature)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::random;
+
+    #[test]
+    fn test_identical() {
+        let image = Image::new(
+            (0..64)
+                .map(|i| Vector3::new(i as f32 / 64.0, 0.5, 1.0 - i as f32 / 64.0))
+                .collect(),
+            8,
+            8,
+        );
+        let comparison = compare(&image, &image);
+        let found = (
+            comparison.mse,
+            comparison.psnr,
+            comparison.ssim,
+            comparison.flip,
+        );
+        let expected = (0.0, f32::INFINITY, 1.0, 0.0);
+        assert!(
+            found.0 == expected.0
+                && found.1 == expected.1
+                && (found.2 - expected.2).abs() < 1.0e-4
+                && found.3 == expected.3,
+            "compare() failed on identical images. Expected {:?}, got {:?}.",
+            expected,
+            found
+        );
+    }
+
+    #[test]
+    fn test_noise() {
+        let reference = Image::filled(16, 16, Vector3::ones() * 0.5);
+        let noisy = |amount: f32| {
+            reference.map(|p| p + Vector3::ones() * (amount * (random::uniform() - 0.5)))
+        };
+
+        // More noise should be worse by every measure.
+        let (slight, heavy) = (
+            compare(&reference, &noisy(0.05)),
+            compare(&reference, &noisy(0.5)),
+        );
+        for metric in [
+            Metric::Mse,
+            Metric::RelMse,
+            Metric::Psnr,
+            Metric::Ssim,
+            Metric::Flip,
+        ] {
+            let threshold = (slight.get(metric) + heavy.get(metric)) / 2.0;
+            assert!(
+                !slight.exceeds(metric, threshold) && heavy.exceeds(metric, threshold),
+                "compare() failed on {}. Expected slight noise ({}) to be better than heavy \
+                 noise ({}).",
+                metric,
+                slight.get(metric),
+                heavy.get(metric)
+            );
+        }
+    }
+}
@@ -0,0 +1,522 @@
+use serde::{Deserialize, Serialize};
+
+use std::f32::consts::{FRAC_PI_2, PI, TAU};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::color;
+use crate::error::{self, SceneError};
+use crate::image::{Image, ImageError};
+use crate::random;
+use crate::transform::deserialize_angle;
+use crate::vector::Vector3;
+
+/// An enum describing the light arriving from infinitely far away, seen by rays that escape the
+/// scene. The `+y` axis points to the zenith. Available environments are:
+/// - Constant: The same `color` in every direction.
+/// - Gradient: Blends from the `horizon` color to the `zenith` color above the horizon, with a
+///   constant `ground` color below it.
+/// - Image: An equirectangular HDR image loaded from `path`, relative to the scene file, with
+///   the center of the image facing `+z`. The image is turned around the `+y` axis by
+///   `rotation` and scaled by `intensity`.
+/// - Sky: The Preetham analytic daylight model for a sun in the direction `sun_direction` with
+///   the given atmospheric `turbidity` above the horizon, and a constant `ground` color below
+///   it, both scaled by `intensity` so the zenith has unit luminance. The sun itself is not
+///   included.
+#[derive(Serialize, Deserialize)]
+pub enum Environment {
+    Constant(Vector3),
+    Gradient {
+        zenith: Vector3,
+        horizon: Vector3,
+        ground: Vector3,
+    },
+    Image {
+        path: String,
+        #[serde(default = "default_intensity")]
+        intensity: f32,
+        #[serde(default, deserialize_with = "deserialize_angle")]
+        rotation: f32,
+        #[serde(skip)]
+        map: Option<Arc<EnvironmentMap>>,
+    },
+    Sky {
+        sun_direction: Vector3,
+        #[serde(default = "default_turbidity")]
+        turbidity: f32,
+        #[serde(default = "default_intensity")]
+        intensity: f32,
+        #[serde(default = "Vector3::zeros")]
+        ground: Vector3,
+    },
+}
+
+fn default_intensity() -> f32 {
+    1.0
+}
+
+fn default_turbidity() -> f32 {
+    3.0
+}
+
+impl Default for Environment {
+    fn default() -> Self {
+        Environment::Constant(Vector3::zeros())
+    }
+}
+
+/// A direction sampled towards the environment, with the radiance arriving from it and the
+/// solid-angle pdf with which it was sampled.
+pub struct EnvironmentSample {
+    pub direction: Vector3,
+    pub radiance: Vector3,
+    pub pdf: f32,
+}
+
+impl Environment {
+    /// Check that the colors of this environment at `path` are finite.
+    pub fn validate(&self, path: &str) -> Result<(), SceneError> {
+        match self {
+            Environment::Constant(color) => {
+                error::check_color(&format!("{}.Constant", path), *color)
+            }
+            Environment::Gradient {
+                zenith,
+                horizon,
+                ground,
+            } => {
+                error::check_color(&format!("{}.Gradient.zenith", path), *zenith)?;
+                error::check_color(&format!("{}.Gradient.horizon", path), *horizon)?;
+                error::check_color(&format!("{}.Gradient.ground", path), *ground)
+            }
+            Environment::Sky { ground, .. } => {
+                error::check_color(&format!("{}.Sky.ground", path), *ground)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Load any images used by this environment, resolving paths relative to `base`.
+    pub fn load(&mut self, base: &Path) -> Result<(), ImageError> {
+        if let Environment::Image { path, map, .. } = self {
+            let image = Image::load_hdr(base.join(path.as_str()))?;
+            *map = Some(Arc::new(EnvironmentMap::new(image)));
+        }
+
+        Ok(())
+    }
+
+    /// Return whether this environment emits no light at all.
+    pub fn is_black(&self) -> bool {
+        match self {
+            Environment::Constant(color) => *color == Vector3::zeros(),
+            Environment::Image { map, .. } => map.is_none(),
+            _ => false,
+        }
+    }
+
+    /// Return the radiance arriving from the direction `dir`.
+    pub fn radiance(&self, dir: Vector3) -> Vector3 {
+        match self {
+            Environment::Constant(color) => *color,
+            Environment::Gradient {
+                zenith,
+                horizon,
+                ground,
+            } => {
+                let up = dir.normalized().y();
+                if up < 0.0 {
+                    *ground
+                } else {
+                    horizon + (zenith - horizon) * up
+                }
+            }
+            Environment::Image {
+                intensity,
+                rotation,
+                map,
+                ..
+            } => match map {
+                Some(map) => *intensity * map.lookup(to_uv(dir, *rotation)),
+                None => Vector3::zeros(),
+            },
+            Environment::Sky {
+                sun_direction,
+                turbidity,
+                intensity,
+                ground,
+            } => {
+                let dir = dir.normalized();
+                if dir.y() < 0.0 {
+                    *intensity * *ground
+                } else {
+                    *intensity * sky_radiance(dir, sun_direction.normalized(), *turbidity)
+                }
+            }
+        }
+    }
+
+    /// Sample a direction towards the environment, preferring directions that bring more
+    /// light. Returns `None` if the environment is black.
+    pub fn sample(&self) -> Option<EnvironmentSample> {
+        if self.is_black() {
+            return None;
+        }
+
+        let direction = match self {
+            Environment::Image {
+                rotation,
+                map: Some(map),
+                ..
+            } => {
+                let (uv, _) = map
+                    .distribution
+                    .sample(random::uniform(), random::uniform())?;
+                from_uv(uv, *rotation)
+            }
+            _ => random::unit_sphere(),
+        };
+
+        let pdf = self.pdf(direction);
+        if pdf <= 0.0 {
+            return None;
+        }
+
+        Some(EnvironmentSample {
+            direction,
+            radiance: self.radiance(direction),
+            pdf,
+        })
+    }
+
+    /// Return the solid-angle pdf with which `sample` picks the direction `dir`.
+    pub fn pdf(&self, dir: Vector3) -> f32 {
+        if self.is_black() {
+            return 0.0;
+        }
+
+        match self {
+            Environment::Image {
+                rotation,
+                map: Some(map),
+                ..
+            } => {
+                let uv = to_uv(dir, *rotation);
+                let sin_theta = (uv.1 * PI).sin();
+                if sin_theta <= 0.0 {
+                    0.0
+                } else {
+                    map.distribution.pdf(uv) / (2.0 * PI * PI * sin_theta)
+                }
+            }
+            _ => 1.0 / (4.0 * PI),
+        }
+    }
+}
+
+/// Map a direction to equirectangular image coordinates in `[0, 1)`, with `v = 0` at the
+/// zenith.
+fn to_uv(dir: Vector3, rotation: f32) -> (f32, f32) {
+    let dir = dir.normalized();
+    let phi = dir.x().atan2(dir.z()) - rotation;
+    let u = (0.5 + phi / TAU).rem_euclid(1.0);
+    let v = dir.y().clamp(-1.0, 1.0).acos() / PI;
+
+    (u, v)
+}
+
+/// Map equirectangular image coordinates to a direction.
+fn from_uv((u, v): (f32, f32), rotation: f32) -> Vector3 {
+    let phi = (u - 0.5) * TAU + rotation;
+    let theta = v * PI;
+
+    Vector3::new(
+        theta.sin() * phi.sin(),
+        theta.cos(),
+        theta.sin() * phi.cos(),
+    )
+}
+
+/// An equirectangular environment image together with a distribution for sampling its pixels
+/// in proportion to their brightness.
+pub struct EnvironmentMap {
+    image: Image,
+    distribution: Distribution2D,
+}
+
+impl EnvironmentMap {
+    /// Build the sampling distribution for an equirectangular `image`.
+    pub fn new(image: Image) -> Self {
+        let (width, height) = (image.width() as usize, image.height() as usize);
+
+        // Weight rows by the solid angle they cover, since rows near the poles are squeezed.
+        let weights: Vec<f32> = image
+            .pixels()
+            .iter()
+            .enumerate()
+            .map(|(i, &p)| {
+                let sin_theta = (((i / width) as f32 + 0.5) / height as f32 * PI).sin();
+                color::luminance(p).max(0.0) * sin_theta
+            })
+            .collect();
+
+        EnvironmentMap {
+            distribution: Distribution2D::new(&weights, width, height),
+            image,
+        }
+    }
+
+    /// Return the pixel at the image coordinates `(u, v)`.
+    fn lookup(&self, (u, v): (f32, f32)) -> Vector3 {
+        let (width, height) = (self.image.width() as usize, self.image.height() as usize);
+        let x = ((u * width as f32) as usize).min(width - 1);
+        let y = ((v * height as f32) as usize).min(height - 1);
+
+        self.image.pixels()[y * width + x]
+    }
+}
+
+/// A piecewise-constant distribution over `[0, 1)`.
+struct Distribution1D {
+    func: Vec<f32>,
+    cdf: Vec<f32>,
+    integral: f32,
+}
+
+impl Distribution1D {
+    fn new(func: &[f32]) -> Self {
+        let n = func.len() as f32;
+        let mut cdf = Vec::with_capacity(func.len() + 1);
+        cdf.push(0.0);
+        for f in func {
+            cdf.push(cdf[cdf.len() - 1] + f / n);
+        }
+
+        let integral = cdf[func.len()];
+        if integral > 0.0 {
+            cdf.iter_mut().for_each(|c| *c /= integral);
+        }
+
+        Distribution1D {
+            func: func.to_vec(),
+            cdf,
+            integral,
+        }
+    }
+
+    /// Sample a point in `[0, 1)` from the uniform variate `u`, returning it together with its
+    /// density and the index of the piece it falls in.
+    fn sample(&self, u: f32) -> Option<(f32, f32, usize)> {
+        if self.integral <= 0.0 {
+            return None;
+        }
+
+        let index = self
+            .cdf
+            .partition_point(|&c| c <= u)
+            .clamp(1, self.func.len())
+            - 1;
+        let width = self.cdf[index + 1] - self.cdf[index];
+        let offset = if width > 0.0 {
+            (u - self.cdf[index]) / width
+        } else {
+            0.5
+        };
+        let x = (index as f32 + offset.clamp(0.0, 1.0)) / self.func.len() as f32;
+
+        Some((
+            x.min(1.0 - f32::EPSILON),
+            self.func[index] / self.integral,
+            index,
+        ))
+    }
+
+    fn pdf(&self, x: f32) -> f32 {
+        if self.integral <= 0.0 {
+            return 0.0;
+        }
+
+        let index = ((x * self.func.len() as f32) as usize).min(self.func.len() - 1);
+        self.func[index] / self.integral
+    }
+}
+
+/// A piecewise-constant distribution over `[0, 1)^2`, sampled by choosing a row and then a
+/// column within it.
+struct Distribution2D {
+    rows: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    fn new(func: &[f32], width: usize, height: usize) -> Self {
+        let rows: Vec<Distribution1D> = func.chunks(width).map(Distribution1D::new).collect();
+        let marginal: Vec<f32> = rows.iter().map(|r| r.integral).collect();
+        debug_assert_eq!(rows.len(), height);
+
+        Distribution2D {
+            marginal: Distribution1D::new(&marginal),
+            rows,
+        }
+    }
+
+    fn sample(&self, u1: f32, u2: f32) -> Option<((f32, f32), f32)> {
+        let (v, pdf_v, row) = self.marginal.sample(u1)?;
+        let (u, pdf_u, _) = self.rows[row].sample(u2)?;
+
+        Some(((u, v), pdf_u * pdf_v))
+    }
+
+    fn pdf(&self, (u, v): (f32, f32)) -> f32 {
+        let row = ((v * self.rows.len() as f32) as usize).min(self.rows.len() - 1);
+        self.rows[row].pdf(u) * self.marginal.pdf(v)
+    }
+}
+
+/// Evaluate the Preetham daylight model in the direction `dir` for a sun in the direction
+/// `sun`, normalized so that the zenith has unit luminance.
+fn sky_radiance(dir: Vector3, sun: Vector3, turbidity: f32) -> Vector3 {
+    let t = turbidity;
+
+    // The model diverges at the horizon, so directions close to it see the sky just above.
+    let theta = dir.y().clamp(0.01, 1.0).acos();
+    let theta_sun = sun.y().clamp(0.0, 1.0).acos().min(FRAC_PI_2);
+    let gamma = dir.dot(sun).clamp(-1.0, 1.0).acos();
+
+    let perez = |[a, b, c, d, e]: [f32; 5], theta: f32, gamma: f32| {
+        (1.0 + a * (b / theta.cos()).exp())
+            * (1.0 + c * (d * gamma).exp() + e * gamma.cos().powi(2))
+    };
+
+    let lum = [
+        0.1787 * t - 1.4630,
+        -0.3554 * t + 0.4275,
+        -0.0227 * t + 5.3251,
+        0.1206 * t - 2.5771,
+        -0.0670 * t + 0.3703,
+    ];
+    let x = [
+        -0.0193 * t - 0.2592,
+        -0.0665 * t + 0.0008,
+        -0.0004 * t + 0.2125,
+        -0.0641 * t - 0.8989,
+        -0.0033 * t + 0.0452,
+    ];
+    let y = [
+        -0.0167 * t - 0.2608,
+        -0.0950 * t + 0.0092,
+        -0.0079 * t + 0.2102,
+        -0.0441 * t - 1.6537,
+        -0.0109 * t + 0.0529,
+    ];
+
+    let (s1, s2, s3) = (theta_sun, theta_sun.powi(2), theta_sun.powi(3));
+    let x_zenith = t * t * (0.00166 * s3 - 0.00375 * s2 + 0.00209 * s1)
+        + t * (-0.02903 * s3 + 0.06377 * s2 - 0.03202 * s1 + 0.00394)
+        + (0.11693 * s3 - 0.21196 * s2 + 0.06052 * s1 + 0.25886);
+    let y_zenith = t * t * (0.00275 * s3 - 0.00610 * s2 + 0.00317 * s1)
+        + t * (-0.04214 * s3 + 0.08970 * s2 - 0.04153 * s1 + 0.00516)
+        + (0.15346 * s3 - 0.26756 * s2 + 0.06670 * s1 + 0.26688);
+
+    let relative = |coefficients, zenith: f32| {
+        zenith * perez(coefficients, theta, gamma) / perez(coefficients, 0.0, theta_sun)
+    };
+
+    let xyz = color::xyy_to_xyz(
+        relative(x, x_zenith),
+        relative(y, y_zenith),
+        relative(lum, 1.0),
+    );
+    color::xyz_to_linear_srgb(xyz).cwise(Vector3::zeros(), f32::max)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_distribution_pdf() {
+        let weights = [0.0, 1.0, 3.0, 0.0, 2.0, 2.0];
+        let distribution = Distribution2D::new(&weights, 3, 2);
+
+        // The pdf must integrate to one over the unit square.
+        let n = 600;
+        let integral: f32 = (0..n * n)
+            .map(|i| {
+                let uv = (
+                    ((i % n) as f32 + 0.5) / n as f32,
+                    ((i / n) as f32 + 0.5) / n as f32,
+                );
+                distribution.pdf(uv)
+            })
+            .sum::<f32>()
+            / (n * n) as f32;
+        assert!(
+            (integral - 1.0).abs() < 1.0e-3,
+            "Distribution2D::pdf() should integrate to one, got {}.",
+            integral
+        );
+
+        // Sampling must agree with the pdf and never pick an empty cell.
+        for i in 0..100 {
+            let (u1, u2) = (i as f32 / 100.0, (i * 37 % 100) as f32 / 100.0);
+            let (uv, pdf) = distribution
+                .sample(u1, u2)
+                .expect("Sampling should succeed.");
+            let expected = distribution.pdf(uv);
+            assert!(
+                (pdf - expected).abs() < 1.0e-4 && pdf > 0.0,
+                "Distribution2D::sample() returned pdf {} at {:?}, expected {}.",
+                pdf,
+                uv,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn test_uv_round_trip() {
+        let dir = Vector3::unit(0.3, -0.5, 0.8);
+        let back = from_uv(to_uv(dir, 0.4), 0.4);
+        assert!(
+            (back - dir).norm() < 1.0e-5,
+            "Equirectangular mapping failed on {}. Got {}.",
+            dir,
+            back
+        );
+    }
+
+    #[test]
+    fn test_sky_below_horizon() {
+        let sky = Environment::Sky {
+            sun_direction: Vector3::unit(0.0, 1.0, 1.0),
+            turbidity: 3.0,
+            intensity: 2.0,
+            ground: Vector3::new(0.1, 0.2, 0.3),
+        };
+        let expected = Vector3::new(0.2, 0.4, 0.6);
+        for dir in [
+            Vector3::unit(0.0, -1.0, 1.0),
+            Vector3::unit(1.0, -0.01, 0.0),
+        ] {
+            let radiance = sky.radiance(dir);
+            assert!(
+                (radiance - expected).norm() < 1.0e-6,
+                "Environment::radiance() failed on the sky towards {}. Expected {}, got {}.",
+                dir,
+                expected,
+                radiance
+            );
+        }
+
+        // The sky above the horizon is brighter than the ground.
+        let radiance = sky.radiance(Vector3::unit(1.0, 0.01, 0.0));
+        assert!(
+            radiance.y() > expected.y(),
+            "Environment::radiance() failed on the sky above the horizon. Expected more than \
+             {}, got {}.",
+            expected,
+            radiance
+        );
+    }
+}
@@ -0,0 +1,460 @@
+use crate::image::ImageError;
+use crate::vector::Vector3;
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+/// An error in loading a scene. Where they are known, the error keeps the scene `file`, the
+/// `path` within the JSON of the value at fault, such as `objects[4].material.Specular.roughness`,
+/// and the 1-based `line` and `column` of that value in the file.
+#[derive(Debug)]
+pub struct SceneError {
+    pub kind: SceneErrorKind,
+    pub file: Option<PathBuf>,
+    pub path: Option<String>,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+}
+
+/// What went wrong in loading a scene.
+/// - Io: The scene file could not be read.
+/// - Json: The scene file is not valid JSON or does not describe a scene.
+/// - Invalid: The scene describes something that cannot be rendered, such as a negative scale.
+/// - Image: An image used by the scene could not be loaded.
+/// - Link: An instance refers to a definition that does not exist or instances itself.
+#[derive(Debug)]
+pub enum SceneErrorKind {
+    Io(io::Error),
+    Json(serde_json::Error),
+    Invalid(String),
+    Image(Box<ImageError>),
+    Link(String),
+}
+
+impl SceneError {
+    pub fn new(kind: SceneErrorKind) -> Self {
+        SceneError {
+            kind,
+            file: None,
+            path: None,
+            line: None,
+            column: None,
+        }
+    }
+
+    /// Create an error for the value at `path` in the scene that cannot be rendered.
+    pub fn invalid(path: impl Into<String>, message: impl Into<String>) -> Self {
+        SceneError {
+            path: Some(path.into()),
+            ..SceneError::new(SceneErrorKind::Invalid(message.into()))
+        }
+    }
+
+    /// Return this error for the scene loaded from `file`.
+    pub fn in_file(self, file: impl Into<PathBuf>) -> Self {
+        SceneError {
+            file: Some(file.into()),
+            ..self
+        }
+    }
+
+    /// Fill in whichever of the path and the position of the error can be found from the
+    /// other in the scene's JSON `source`.
+    pub fn locate(self, source: &str) -> Self {
+        match (&self.path, self.line, self.column) {
+            (None, Some(line), Some(column)) => SceneError {
+                path: Some(json_path_at(source, line, column)),
+                ..self
+            },
+            (Some(path), None, _) => match json_position(source, path) {
+                Some((line, column)) => SceneError {
+                    line: Some(line),
+                    column: Some(column),
+                    ..self
+                },
+                None => self,
+            },
+            _ => self,
+        }
+    }
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}:", file.display())?;
+            if let (Some(line), Some(column)) = (self.line, self.column) {
+                write!(f, "{}:{}:", line, column)?;
+            }
+            write!(f, " ")?;
+        }
+        if let Some(path) = self.path.as_deref().filter(|path| !path.is_empty()) {
+            write!(f, "{}: ", path)?;
+        }
+
+        match &self.kind {
+            SceneErrorKind::Io(err) => write!(f, "{}", err),
+            SceneErrorKind::Json(err) => {
+                // The position is already given above, so leave it off serde's message.
+                let message = err.to_string();
+                let suffix = format!(" at line {} column {}", err.line(), err.column());
+                write!(f, "{}", message.strip_suffix(&suffix).unwrap_or(&message))
+            }
+            SceneErrorKind::Invalid(message) | SceneErrorKind::Link(message) => {
+                write!(f, "{}", message)
+            }
+            SceneErrorKind::Image(err) => {
+                // Image errors leave their cause to `source()`, so give it here as well.
+                write!(f, "{}", err)?;
+                let mut source = err.source();
+                while let Some(err) = source {
+                    write!(f, ": {}", err)?;
+                    source = err.source();
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Error for SceneError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match &self.kind {
+            SceneErrorKind::Io(err) => Some(err),
+            SceneErrorKind::Json(err) => Some(err),
+            SceneErrorKind::Image(err) => Some(err.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for SceneError {
+    fn from(err: io::Error) -> Self {
+        SceneError::new(SceneErrorKind::Io(err))
+    }
+}
+
+impl From<serde_json::Error> for SceneError {
+    fn from(err: serde_json::Error) -> Self {
+        // serde counts from line 1, and reports line 0 for errors that have no position.
+        let position = (err.line() > 0).then(|| (err.line(), err.column()));
+        SceneError {
+            line: position.map(|(line, _)| line),
+            column: position.map(|(_, column)| column),
+            ..SceneError::new(SceneErrorKind::Json(err))
+        }
+    }
+}
+
+impl From<ImageError> for SceneError {
+    fn from(err: ImageError) -> Self {
+        SceneError::new(SceneErrorKind::Image(Box::new(err)))
+    }
+}
+
+/// Check that every component of the `color` at `path` is a finite number.
+pub(crate) fn check_color(path: &str, color: Vector3) -> Result<(), SceneError> {
+    if [color.x(), color.y(), color.z()]
+        .iter()
+        .all(|c| c.is_finite())
+    {
+        Ok(())
+    } else {
+        Err(SceneError::invalid(
+            path,
+            format!("color {} must be finite", color),
+        ))
+    }
+}
+
+/// A container that is open at some point in a JSON document, with the key or index of the
+/// value currently being read in it.
+enum Frame {
+    Object {
+        key: Option<String>,
+        expect_key: bool,
+    },
+    Array {
+        index: usize,
+    },
+}
+
+/// Format the path to the value being read inside the open `frames`.
+fn format_path(frames: &[Frame]) -> String {
+    let mut path = String::new();
+    for frame in frames {
+        match frame {
+            Frame::Object { key: Some(key), .. } => {
+                if !path.is_empty() {
+                    path.push('.');
+                }
+                path.push_str(key);
+            }
+            Frame::Object { key: None, .. } => {}
+            Frame::Array { index } => path.push_str(&format!("[{}]", index)),
+        }
+    }
+    path
+}
+
+/// Walk the JSON `source`, calling `visit` with the open containers, whether a value starts
+/// there, and the line and column of every character, and once more past the end.
+/// The walk stops early when `visit` returns `true`. Malformed JSON is walked as far as it makes
+/// sense, since only the part before an error is needed.
+fn walk(source: &str, mut visit: impl FnMut(&[Frame], bool, usize, usize) -> bool) {
+    let mut frames: Vec<Frame> = Vec::new();
+    let (mut line, mut column) = (1, 0);
+    let mut chars = source.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c == '\n' {
+            line += 1;
+            column = 0;
+        } else {
+            column += 1;
+        }
+
+        let is_key = matches!(
+            frames.last(),
+            Some(Frame::Object {
+                expect_key: true,
+                ..
+            })
+        );
+        let starts_value = !is_key && !c.is_whitespace() && !matches!(c, ',' | ':' | ']' | '}');
+        if visit(&frames, starts_value, line, column) {
+            return;
+        }
+
+        match c {
+            '{' => frames.push(Frame::Object {
+                key: None,
+                expect_key: true,
+            }),
+            '[' => frames.push(Frame::Array { index: 0 }),
+            '}' | ']' => {
+                frames.pop();
+            }
+            ',' => match frames.last_mut() {
+                Some(Frame::Object { key, expect_key }) => {
+                    *key = None;
+                    *expect_key = true;
+                }
+                Some(Frame::Array { index }) => *index += 1,
+                None => {}
+            },
+            '"' => {
+                // Read the rest of the string, reporting each character so that errors inside
+                // it are found.
+                let mut text = String::new();
+                let mut escaped = false;
+                for c in chars.by_ref() {
+                    column += 1;
+                    if visit(&frames, false, line, column) {
+                        return;
+                    }
+                    match c {
+                        '"' if !escaped => break,
+                        '\\' if !escaped => escaped = true,
+                        _ => {
+                            escaped = false;
+                            text.push(c);
+                        }
+                    }
+                }
+
+                if let Some(Frame::Object { key, expect_key }) = frames.last_mut() {
+                    if *expect_key {
+                        *key = Some(text);
+                        *expect_key = false;
+                    }
+                }
+            }
+            c if c.is_whitespace() => {}
+            _ => {
+                // Skip the rest of a number or literal.
+                while let Some(&c) = chars.peek() {
+                    if !(c.is_alphanumeric() || matches!(c, '.' | '+' | '-')) {
+                        break;
+                    }
+                    chars.next();
+                    column += 1;
+                    if visit(&frames, false, line, column) {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+
+    visit(&frames, false, usize::MAX, usize::MAX);
+}
+
+/// Return the path to the value being read at the 1-based `line` and `column` of the JSON
+/// `source`, such as where serde reported an error. serde reports errors at the last character
+/// it read, so that character is taken as read, closing any object or array it ends.
+pub fn json_path_at(source: &str, line: usize, column: usize) -> String {
+    let mut path = None;
+    walk(source, |frames, _, l, c| {
+        if (l, c) > (line, column) {
+            path = Some(format_path(frames));
+            return true;
+        }
+        false
+    });
+
+    path.unwrap_or_default()
+}
+
+/// Return the 1-based line and column where the value at `path` starts in the JSON `source`.
+pub fn json_position(source: &str, path: &str) -> Option<(usize, usize)> {
+    let mut position = None;
+    walk(source, |frames, starts_value, line, column| {
+        if starts_value && format_path(frames) == path {
+            position = Some((line, column));
+            return true;
+        }
+        false
+    });
+
+    position
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::scene::Scene;
+
+    #[test]
+    fn test_json_path() {
+        let source = "{\n  \"objects\": [\n    {\"a\": 1},\n    {\"material\": {\"Specular\": {\"roughness\": \"x\"}}}\n  ]\n}";
+        let path = "objects[1].material.Specular.roughness";
+
+        let position = json_position(source, path);
+        let expected = Some((4, 45));
+        assert_eq!(
+            expected, position,
+            "json_position() failed on {}. Expected {:?}, got {:?}.",
+            path, expected, position
+        );
+
+        let found = json_path_at(source, 4, 47);
+        assert_eq!(
+            path, found,
+            "json_path_at() failed on line 4, column 47. Expected {}, got {}.",
+            path, found
+        );
+    }
+
+    #[test]
+    fn test_deserialize_error_path() {
+        let source = r#"{
+            "camera": {
+                "focal_len": {"Keyframes": [
+                    {"time": 0.0, "value": 1.0, "interpolation": {"Bezier": [2.0, 0.0, -1.0, 1.0]}},
+                    {"time": 1.0, "value": 2.0}
+                ]},
+                "width": 1.0
+            },
+            "objects": []
+        }"#;
+        let err = match serde_json::from_str::<Scene>(source) {
+            Ok(_) => panic!("Scene deserialization failed on a Bezier going back in time."),
+            Err(err) => SceneError::from(err).locate(source),
+        };
+        let expected = (Some("camera.focal_len.Keyframes[0]".to_string()), Some(4));
+        let found = (err.path.clone(), err.line);
+        assert_eq!(
+            expected, found,
+            "SceneError::locate() failed on a Bezier going back in time. Expected {:?}, got \
+             {:?}.",
+            expected, found
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        let source = r#"{
+            "camera": {"focal_len": 1.0, "width": 1.0},
+            "objects": [
+                {
+                    "object": "Sphere",
+                    "material": {"Specular": {"color": {"x": 1.0, "y": 1.0, "z": 1.0}, "roughness": 2.0}},
+                    "transforms": []
+                }
+            ]
+        }"#;
+        let scene: Scene = serde_json::from_str(source).unwrap();
+
+        let err = scene.validate().unwrap_err().locate(source);
+        let expected = (
+            Some("objects[0].material.Specular.roughness".to_string()),
+            Some(6),
+        );
+        let found = (err.path.clone(), err.line);
+        assert_eq!(
+            expected, found,
+            "Scene::validate() failed on a roughness of 2. Expected {:?}, got {:?}.",
+            expected, found
+        );
+
+        // Media with NaN coefficients and transforms that cannot be inverted would make rays
+        // vanish or loop forever, so they are rejected too.
+        let cases = [
+            (
+                r#""medium": {"absorption": {"x": -1.0, "y": 0.0, "z": 0.0}, "scattering": {"x": 0.0, "y": 0.0, "z": 0.0}}, "objects": []"#,
+                "medium.absorption",
+            ),
+            (
+                r#""objects": [{"object": "Sphere", "material": "Interface", "transforms": [], "medium": {"absorption": {"x": 0.0, "y": 0.0, "z": 0.0}, "scattering": {"x": 1.0, "y": 1.0, "z": 1.0}, "anisotropy": 1.5}}]"#,
+                "objects[0].medium.anisotropy",
+            ),
+            (
+                r#""objects": [{"object": "Sphere", "material": "Interface", "transforms": [{"Shear": {"xy": 1.0, "yx": 1.0}}]}]"#,
+                "objects[0].transforms[0].Shear",
+            ),
+            (
+                r#""objects": [{"object": "Sphere", "material": "Interface", "transforms": [{"LookAt": {"eye": {"x": 0.0, "y": 0.0, "z": 0.0}, "target": {"x": 0.0, "y": 2.0, "z": 0.0}, "up": {"x": 0.0, "y": 1.0, "z": 0.0}}}]}]"#,
+                "objects[0].transforms[0].LookAt.up",
+            ),
+            (
+                r#""objects": [], "lights": [{"Spot": {"position": {"x": 0.0, "y": 0.0, "z": 0.0}, "direction": {"x": 0.0, "y": 0.0, "z": 0.0}, "color": {"x": 1.0, "y": 1.0, "z": 1.0}, "intensity": 1.0, "angle": 0.5}}]"#,
+                "lights[0].Spot.direction",
+            ),
+            (
+                r#""objects": [], "lights": [{"Spot": {"position": {"x": 0.0, "y": 0.0, "z": 0.0}, "direction": {"x": 0.0, "y": -1.0, "z": 0.0}, "color": {"x": 1.0, "y": 1.0, "z": 1.0}, "intensity": 1.0, "angle": -0.5}}]"#,
+                "lights[0].Spot.angle",
+            ),
+            (
+                r#""objects": [], "lights": [{"Spot": {"position": {"x": 0.0, "y": 0.0, "z": 0.0}, "direction": {"x": 0.0, "y": -1.0, "z": 0.0}, "color": {"x": 1.0, "y": 1.0, "z": 1.0}, "intensity": 1.0, "angle": 0.5, "soft_edge": {"degrees": 200.0}}}]"#,
+                "lights[0].Spot.soft_edge",
+            ),
+            (
+                r#""objects": [], "lights": [{"Directional": {"direction": {"x": 0.0, "y": 0.0, "z": 0.0}, "color": {"x": 1.0, "y": 1.0, "z": 1.0}, "intensity": 1.0}}]"#,
+                "lights[0].Directional.direction",
+            ),
+            (
+                r#""objects": [], "lights": [{"Directional": {"direction": {"x": 0.0, "y": 1.0, "z": 0.0}, "color": {"x": 1.0, "y": 1.0, "z": 1.0}, "intensity": 1.0, "angular_diameter": 4.0}}]"#,
+                "lights[0].Directional.angular_diameter",
+            ),
+        ];
+        for (fields, expected) in cases {
+            let source = format!(
+                r#"{{"camera": {{"focal_len": 1.0, "width": 1.0}}, {}}}"#,
+                fields
+            );
+            let scene: Scene = serde_json::from_str(&source).unwrap();
+            let found = scene.validate().err().and_then(|err| err.path);
+            assert_eq!(
+                Some(expected.to_string()),
+                found,
+                "Scene::validate() failed on {}. Expected {}, got {:?}.",
+                fields,
+                expected,
+                found
+            );
+        }
+    }
+}
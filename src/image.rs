@@ -0,0 +1,1196 @@
+use crate::color;
+use crate::vector::Vector3;
+
+use std::error::Error;
+use std::f32::consts::PI;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+
+use miniz_oxide::inflate::decompress_to_vec_zlib;
+use png::{BitDepth, ColorType, Decoder, Encoder, Transformations};
+
+/// An error in reading or writing the image at `path`, with the error that caused it, if
+/// any, as its source.
+#[derive(Debug)]
+pub struct ImageError {
+    pub kind: ImageErrorKind,
+    pub path: PathBuf,
+    source: Option<Box<dyn Error + Send + Sync>>,
+}
+
+/// What went wrong in reading or writing an image.
+/// - NotFound: The image file does not exist.
+/// - DirectoryMissing: The directory to write the image into does not exist.
+/// - PermissionDenied: The image file may not be read or written.
+/// - UnsupportedFormat: The image would have to be read or written in a format that is not
+///   supported, given by the extension of its path.
+/// - Io: Reading or writing the file failed for another reason.
+/// - Encode: The image could not be encoded.
+/// - Decode: The file is not an image in a supported format, or is corrupt.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ImageErrorKind {
+    NotFound,
+    DirectoryMissing,
+    PermissionDenied,
+    UnsupportedFormat(String),
+    Io,
+    Encode,
+    Decode,
+}
+
+impl ImageError {
+    pub fn new(kind: ImageErrorKind, path: impl Into<PathBuf>) -> Self {
+        ImageError {
+            kind,
+            path: path.into(),
+            source: None,
+        }
+    }
+
+    /// Return this error with the error that caused it.
+    pub fn with_source(self, source: impl Into<Box<dyn Error + Send + Sync>>) -> Self {
+        ImageError {
+            source: Some(source.into()),
+            ..self
+        }
+    }
+
+    /// Create an error for a failure to open the file at `path`, telling apart the common
+    /// reasons from the I/O error `err`.
+    fn from_io(err: io::Error, path: &Path) -> Self {
+        let kind = match err.kind() {
+            io::ErrorKind::PermissionDenied => ImageErrorKind::PermissionDenied,
+            io::ErrorKind::NotFound => match path.parent() {
+                Some(dir) if !dir.as_os_str().is_empty() && !dir.is_dir() => {
+                    ImageErrorKind::DirectoryMissing
+                }
+                _ => ImageErrorKind::NotFound,
+            },
+            _ => ImageErrorKind::Io,
+        };
+        ImageError::new(kind, path).with_source(err)
+    }
+}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path = self.path.display();
+        match &self.kind {
+            ImageErrorKind::NotFound => write!(f, "{} does not exist", path),
+            ImageErrorKind::DirectoryMissing => {
+                let dir = self.path.parent().unwrap_or_else(|| Path::new(""));
+                write!(
+                    f,
+                    "the directory {} for {} does not exist",
+                    dir.display(),
+                    path
+                )
+            }
+            ImageErrorKind::PermissionDenied => write!(f, "permission denied for {}", path),
+            ImageErrorKind::UnsupportedFormat(extension) => {
+                write!(f, "unsupported image format `.{}` for {}", extension, path)
+            }
+            ImageErrorKind::Io => write!(f, "unable to access {}", path),
+            ImageErrorKind::Encode => write!(f, "unable to encode {}", path),
+            ImageErrorKind::Decode => write!(f, "unable to decode {}", path),
+        }
+    }
+}
+
+impl Error for ImageError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.source
+            .as_deref()
+            .map(|source| source as &(dyn Error + 'static))
+    }
+}
+
+/// An image of RGB pixels stored in row-major order from the top left. Images hold whatever
+/// values they are given: rendered images and PNG files hold sRGB values between 0 and 1,
+/// while HDR and EXR files hold linear values.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Image {
+    data: Vec<Vector3>,
+    width: u32,
+    height: u32,
+}
+
+/// A filter for resampling images, which weights the source pixels around each new pixel.
+/// - Nearest: Takes the closest source pixel, keeping hard edges but aliasing.
+/// - Box: Averages the source pixels that the new pixel covers.
+/// - Triangle: Blends linearly between neighbouring source pixels.
+/// - CatmullRom: A sharper cubic filter.
+/// - Lanczos3: A windowed sinc filter over three pixels on each side, which is the sharpest but
+///   may ring around hard edges.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Filter {
+    Nearest,
+    Box,
+    Triangle,
+    CatmullRom,
+    Lanczos3,
+}
+
+impl Filter {
+    /// Return how far from its center the filter reaches, in source pixels.
+    fn radius(self) -> f32 {
+        match self {
+            Filter::Nearest | Filter::Box => 0.5,
+            Filter::Triangle => 1.0,
+            Filter::CatmullRom => 2.0,
+            Filter::Lanczos3 => 3.0,
+        }
+    }
+
+    /// Return the weight of the filter at the offset `x` from its center.
+    fn weight(self, x: f32) -> f32 {
+        let x = x.abs();
+        match self {
+            Filter::Nearest | Filter::Box => (x <= 0.5) as u8 as f32,
+            Filter::Triangle => (1.0 - x).max(0.0),
+            Filter::CatmullRom if x < 1.0 => 1.5 * x * x * x - 2.5 * x * x + 1.0,
+            Filter::CatmullRom if x < 2.0 => -0.5 * x * x * x + 2.5 * x * x - 4.0 * x + 2.0,
+            Filter::CatmullRom => 0.0,
+            Filter::Lanczos3 if x < 1.0e-6 => 1.0,
+            Filter::Lanczos3 if x < 3.0 => {
+                let sinc = |x: f32| (PI * x).sin() / (PI * x);
+                sinc(x) * sinc(x / 3.0)
+            }
+            Filter::Lanczos3 => 0.0,
+        }
+    }
+
+    /// Return the first source pixel and the normalized weights of the source pixels that
+    /// make up each of `to` pixels resampled from `from` pixels.
+    fn weights(self, from: u32, to: u32) -> Vec<(usize, Vec<f32>)> {
+        let scale = from as f32 / to as f32;
+        // When shrinking, the filter is widened to cover every source pixel.
+        let spread = scale.max(1.0);
+        let radius = self.radius() * spread;
+
+        (0..to)
+            .map(|i| {
+                let center = (i as f32 + 0.5) * scale;
+                if self == Filter::Nearest {
+                    return ((center as usize).min(from as usize - 1), vec![1.0]);
+                }
+
+                let start = (center - radius).floor().max(0.0) as usize;
+                let end = ((center + radius).ceil() as usize).min(from as usize);
+                let mut weights: Vec<f32> = (start..end)
+                    .map(|j| self.weight((j as f32 + 0.5 - center) / spread))
+                    .collect();
+                let total: f32 = weights.iter().sum();
+                if total != 0.0 {
+                    weights.iter_mut().for_each(|w| *w /= total);
+                }
+                (start, weights)
+            })
+            .collect()
+    }
+}
+
+impl Image {
+    pub fn new(data: Vec<Vector3>, width: u32, height: u32) -> Self {
+        Image {
+            data,
+            width,
+            height,
+        }
+    }
+
+    /// Create an image with every pixel set to `color`.
+    pub fn filled(width: u32, height: u32, color: Vector3) -> Self {
+        Image::new(vec![color; (width * height) as usize], width, height)
+    }
+
+    /// Load an image, choosing the format from the extension of `path`, which may be `.png`,
+    /// `.hdr` or `.exr`.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ImageError> {
+        let path = path.as_ref();
+        let extension = path
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_ascii_lowercase())
+            .unwrap_or_default();
+
+        match extension.as_str() {
+            "png" => Image::load_png(path),
+            "hdr" => Image::load_hdr(path),
+            "exr" => Image::load_exr(path),
+            _ => Err(ImageError::new(
+                ImageErrorKind::UnsupportedFormat(extension),
+                path,
+            )),
+        }
+    }
+
+    /// Load a PNG image, with pixel values between 0 and 1 as they are stored, which is usually
+    /// in sRGB. Grey images are loaded as RGB, and alpha is dropped.
+    pub fn load_png(path: impl AsRef<Path>) -> Result<Self, ImageError> {
+        let path = path.as_ref();
+        let file = File::open(path).map_err(|err| ImageError::from_io(err, path))?;
+        let decode = |err| ImageError::new(ImageErrorKind::Decode, path).with_source(err);
+
+        let mut decoder = Decoder::new(BufReader::new(file));
+        decoder.set_transformations(Transformations::EXPAND);
+        let (info, mut reader) = decoder.read_info().map_err(decode)?;
+        let mut buffer = vec![0; info.buffer_size()];
+        reader.next_frame(&mut buffer).map_err(decode)?;
+
+        let channels = match info.color_type {
+            ColorType::Grayscale => 1,
+            ColorType::GrayscaleAlpha => 2,
+            ColorType::RGB => 3,
+            ColorType::RGBA => 4,
+            ColorType::Indexed => return Err(ImageError::new(ImageErrorKind::Decode, path)),
+        };
+        let sample = |i: usize| match info.bit_depth {
+            BitDepth::Sixteen => {
+                u16::from_be_bytes([buffer[2 * i], buffer[2 * i + 1]]) as f32 / 65535.0
+            }
+            _ => buffer[i] as f32 / 255.0,
+        };
+
+        let data = (0..(info.width * info.height) as usize)
+            .map(|p| {
+                let i = p * channels;
+                if channels < 3 {
+                    Vector3::ones() * sample(i)
+                } else {
+                    Vector3::new(sample(i), sample(i + 1), sample(i + 2))
+                }
+            })
+            .collect();
+
+        Ok(Image::new(data, info.width, info.height))
+    }
+
+    /// Load a Radiance RGBE (`.hdr`) image with linear pixel values.
+    pub fn load_hdr(path: impl AsRef<Path>) -> Result<Self, ImageError> {
+        let path = path.as_ref();
+        let bytes = fs::read(path).map_err(|err| ImageError::from_io(err, path))?;
+        let (data, width, height) =
+            decode_hdr(&bytes).ok_or_else(|| ImageError::new(ImageErrorKind::Decode, path))?;
+
+        Ok(Image::new(data, width, height))
+    }
+
+    /// Load an OpenEXR image with linear pixel values. Only single-part scanline images that are
+    /// uncompressed or compressed with RLE, ZIPS or ZIP can be read, and images with a `Y`
+    /// channel but no color are loaded as grey.
+    pub fn load_exr(path: impl AsRef<Path>) -> Result<Self, ImageError> {
+        let path = path.as_ref();
+        let bytes = fs::read(path).map_err(|err| ImageError::from_io(err, path))?;
+        let (data, width, height) = decode_exr(&bytes)
+            .map_err(|err| ImageError::new(ImageErrorKind::Decode, path).with_source(err))?;
+
+        Ok(Image::new(data, width, height))
+    }
+
+    /// Return the width of this image in pixels.
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    /// Return the height of this image in pixels.
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Return the pixels of this image in row-major order, starting at the top left.
+    pub fn pixels(&self) -> &[Vector3] {
+        &self.data
+    }
+
+    /// Return the pixels of this image in row-major order for changing them.
+    pub fn pixels_mut(&mut self) -> &mut [Vector3] {
+        &mut self.data
+    }
+
+    /// Return the pixel at (`x`, `y`). Panics if the pixel is outside the image.
+    pub fn get(&self, x: u32, y: u32) -> Vector3 {
+        assert!(
+            x < self.width && y < self.height,
+            "Pixel ({}, {}) is outside the image.",
+            x,
+            y
+        );
+        self.data[(y * self.width + x) as usize]
+    }
+
+    /// Set the pixel at (`x`, `y`) to `color`. Panics if the pixel is outside the image.
+    pub fn set(&mut self, x: u32, y: u32, color: Vector3) {
+        assert!(
+            x < self.width && y < self.height,
+            "Pixel ({}, {}) is outside the image.",
+            x,
+            y
+        );
+        self.data[(y * self.width + x) as usize] = color;
+    }
+
+    /// Iterate over the pixels of this image in row-major order, with their coordinates.
+    pub fn iter(&self) -> impl Iterator<Item = (u32, u32, Vector3)> + '_ {
+        let width = self.width.max(1);
+        self.data
+            .iter()
+            .enumerate()
+            .map(move |(i, pixel)| (i as u32 % width, i as u32 / width, *pixel))
+    }
+
+    /// Return a new image with `f` applied to every pixel.
+    pub fn map(&self, f: impl Fn(Vector3) -> Vector3) -> Image {
+        Image::new(
+            self.data.iter().map(|p| f(*p)).collect(),
+            self.width,
+            self.height,
+        )
+    }
+
+    /// Return the part of this image with its top left corner at (`x`, `y`) and the given size,
+    /// clipped to the image.
+    pub fn crop(&self, x: u32, y: u32, width: u32, height: u32) -> Image {
+        let (x, y) = (x.min(self.width), y.min(self.height));
+        let width = width.min(self.width - x);
+        let height = height.min(self.height - y);
+
+        let data = (y..y + height)
+            .flat_map(|row| {
+                let start = (row * self.width + x) as usize;
+                self.data[start..start + width as usize].iter().copied()
+            })
+            .collect();
+        Image::new(data, width, height)
+    }
+
+    /// Resample this image to `width` by `height` pixels with the given `filter`.
+    pub fn resize(&self, width: u32, height: u32, filter: Filter) -> Image {
+        if self.data.is_empty() || width == 0 || height == 0 {
+            return Image::filled(width, height, Vector3::zeros());
+        }
+
+        // Resample the rows, then the columns of the result.
+        let columns = filter.weights(self.width, width);
+        let rows = filter.weights(self.height, height);
+        let resample = |weights: &(usize, Vec<f32>), pixel: &dyn Fn(usize) -> Vector3| {
+            let (start, weights) = weights;
+            weights
+                .iter()
+                .enumerate()
+                .fold(Vector3::zeros(), |sum, (i, w)| sum + pixel(start + i) * *w)
+        };
+
+        let wide: Vec<Vector3> = (0..self.height as usize)
+            .flat_map(|y| {
+                let resample = &resample;
+                columns.iter().map(move |weights| {
+                    resample(weights, &|x| self.data[y * self.width as usize + x])
+                })
+            })
+            .collect();
+        let data = rows
+            .iter()
+            .flat_map(|weights| {
+                let (wide, resample) = (&wide, &resample);
+                (0..width as usize)
+                    .map(move |x| resample(weights, &|y| wide[y * width as usize + x]))
+            })
+            .collect();
+
+        Image::new(data, width, height)
+    }
+
+    /// Return the sum of this image and `other`. Panics if the images differ in size.
+    pub fn add(&self, other: &Image) -> Image {
+        self.combine(other, |a, b| a + b)
+    }
+
+    /// Return this image with every pixel multiplied by `factor`.
+    pub fn scale(&self, factor: f32) -> Image {
+        self.map(|p| p * factor)
+    }
+
+    /// Blend linearly from this image at `t = 0` to `other` at `t = 1`. Panics if the images
+    /// differ in size.
+    pub fn lerp(&self, other: &Image, t: f32) -> Image {
+        self.combine(other, |a, b| a * (1.0 - t) + b * t)
+    }
+
+    /// Combine the pixels of this image and `other` pairwise with `f`.
+    fn combine(&self, other: &Image, f: impl Fn(Vector3, Vector3) -> Vector3) -> Image {
+        assert!(
+            self.width == other.width && self.height == other.height,
+            "Images of {}x{} and {}x{} pixels differ in size.",
+            self.width,
+            self.height,
+            other.width,
+            other.height
+        );
+        let data = self
+            .data
+            .iter()
+            .zip(&other.data)
+            .map(|(a, b)| f(*a, *b))
+            .collect();
+        Image::new(data, self.width, self.height)
+    }
+
+    /// Convert the sRGB values of this image to linear values.
+    pub fn to_linear(&self) -> Image {
+        self.map(|p| p.cwise(Vector3::ones(), |u, _| color::srgb_to_linear(u)))
+    }
+
+    /// Convert the linear values of this image to sRGB values.
+    pub fn to_srgb(&self) -> Image {
+        self.map(|p| p.cwise(Vector3::ones(), |u, _| color::linear_to_srgb(u)))
+    }
+
+    /// Save this image as an 8-bit PNG, which must have the extension `.png` if it has one.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), ImageError> {
+        let path = path.as_ref();
+        if let Some(extension) = path.extension() {
+            if !extension.eq_ignore_ascii_case("png") {
+                let extension = extension.to_string_lossy().into_owned();
+                return Err(ImageError::new(
+                    ImageErrorKind::UnsupportedFormat(extension),
+                    path,
+                ));
+            }
+        }
+
+        // Round to the nearest level so that loading the image back gives the closest values.
+        let quantize = |u: f32| (u * 255.0).round().clamp(0.0, 255.0) as u8;
+        let mut buffer = Vec::with_capacity(3 * self.data.len());
+        for pixel in &self.data {
+            buffer.push(quantize(pixel.x()));
+            buffer.push(quantize(pixel.y()));
+            buffer.push(quantize(pixel.z()));
+        }
+
+        let file = File::create(path).map_err(|err| ImageError::from_io(err, path))?;
+        let writer = BufWriter::new(file);
+
+        let mut encoder = Encoder::new(writer, self.width, self.height);
+        encoder.set_color(ColorType::RGB);
+        encoder.set_depth(BitDepth::Eight);
+
+        let encode = |err| ImageError::new(ImageErrorKind::Encode, path).with_source(err);
+        let mut writer = encoder.write_header().map_err(encode)?;
+        writer.write_image_data(&buffer).map_err(encode)?;
+
+        Ok(())
+    }
+}
+
+/// Decode the contents of a Radiance RGBE file with the standard `-Y height +X width`
+/// orientation, returning the pixels, width and height.
+fn decode_hdr(bytes: &[u8]) -> Option<(Vec<Vector3>, u32, u32)> {
+    let mut pos = 0;
+    let mut next_line = || {
+        let start = pos;
+        let end = start + bytes[start..].iter().position(|&b| b == b'\n')?;
+        pos = end + 1;
+        std::str::from_utf8(&bytes[start..end]).ok()
+    };
+
+    if !next_line()?.starts_with("#?") {
+        return None;
+    }
+
+    // Header lines end with an empty line, followed by the resolution line.
+    loop {
+        let line = next_line()?;
+        if line.is_empty() {
+            break;
+        }
+        if line.starts_with("FORMAT=") && line != "FORMAT=32-bit_rle_rgbe" {
+            return None;
+        }
+    }
+
+    let resolution: Vec<&str> = next_line()?.split_whitespace().collect();
+    let (height, width) = match resolution.as_slice() {
+        ["-Y", h, "+X", w] => (h.parse::<usize>().ok()?, w.parse::<usize>().ok()?),
+        _ => return None,
+    };
+
+    // Check that the file can hold every scanline before allocating the image.
+    let min_scanline = if (8..0x8000).contains(&width) {
+        4 + 8 * width.div_ceil(127)
+    } else {
+        4 * width
+    };
+    if width == 0 || height == 0 || height.checked_mul(min_scanline)? > bytes.len() - pos {
+        return None;
+    }
+
+    let mut rgbe = vec![0u8; 4 * width * height];
+    for row in rgbe.chunks_mut(4 * width) {
+        pos = decode_hdr_scanline(bytes, pos, row, width)?;
+    }
+
+    let data = rgbe
+        .chunks(4)
+        .map(|p| {
+            if p[3] == 0 {
+                Vector3::zeros()
+            } else {
+                let scale = 2f32.powi(p[3] as i32 - 136);
+                Vector3::new(p[0] as f32, p[1] as f32, p[2] as f32) * scale
+            }
+        })
+        .collect();
+
+    Some((data, width as u32, height as u32))
+}
+
+/// Decode one scanline of RGBE pixels starting at `pos` into `row`, returning the position
+/// after the scanline.
+fn decode_hdr_scanline(
+    bytes: &[u8],
+    mut pos: usize,
+    row: &mut [u8],
+    width: usize,
+) -> Option<usize> {
+    let header = bytes.get(pos..pos + 4)?;
+    let run_length = header[0] == 2 && header[1] == 2 && header[2] & 0x80 == 0;
+
+    if !run_length || !(8..0x8000).contains(&width) {
+        // Flat scanline of RGBE quadruples.
+        row.copy_from_slice(bytes.get(pos..pos + row.len())?);
+        return Some(pos + row.len());
+    }
+
+    if ((header[2] as usize) << 8 | header[3] as usize) != width {
+        return None;
+    }
+    pos += 4;
+
+    // Each of the four channels is stored separately with run-length encoding.
+    for channel in 0..4 {
+        let mut x = 0;
+        while x < width {
+            let count = *bytes.get(pos)? as usize;
+            pos += 1;
+            if count > 128 {
+                let count = count - 128;
+                let value = *bytes.get(pos)?;
+                pos += 1;
+                if x + count > width {
+                    return None;
+                }
+                for i in x..x + count {
+                    row[4 * i + channel] = value;
+                }
+                x += count;
+            } else {
+                if count == 0 || x + count > width {
+                    return None;
+                }
+                for i in x..x + count {
+                    row[4 * i + channel] = *bytes.get(pos)?;
+                    pos += 1;
+                }
+                x += count;
+            }
+        }
+    }
+
+    Some(pos)
+}
+
+/// Read little-endian values from the bytes of an OpenEXR file.
+struct ExrReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl ExrReader<'_> {
+    fn take(&mut self, n: usize) -> Result<&[u8], String> {
+        let end = self.pos.checked_add(n).ok_or("unexpected end of file")?;
+        let bytes = self
+            .bytes
+            .get(self.pos..end)
+            .ok_or("unexpected end of file")?;
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn i32(&mut self) -> Result<i32, String> {
+        Ok(i32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    /// Read a size stored as an `i32`, which must not be negative.
+    fn size(&mut self) -> Result<usize, String> {
+        self.i32()?
+            .try_into()
+            .map_err(|_| "negative size".to_string())
+    }
+
+    /// Read a null-terminated string.
+    fn string(&mut self) -> Result<String, String> {
+        let len = self
+            .bytes
+            .get(self.pos..)
+            .ok_or("unexpected end of file")?
+            .iter()
+            .position(|&b| b == 0)
+            .ok_or("unterminated string")?;
+        let string = String::from_utf8_lossy(self.take(len)?).into_owned();
+        self.pos += 1;
+        Ok(string)
+    }
+}
+
+/// Convert a 16-bit floating point number to an `f32`.
+fn half_to_f32(half: u16) -> f32 {
+    let sign = if half >> 15 == 1 { -1.0 } else { 1.0 };
+    let exponent = (half >> 10) & 0x1f;
+    let mantissa = (half & 0x3ff) as f32;
+    match exponent {
+        0 => sign * mantissa * 2f32.powi(-24),
+        31 if mantissa == 0.0 => sign * f32::INFINITY,
+        31 => f32::NAN,
+        _ => sign * (1.0 + mantissa / 1024.0) * 2f32.powi(exponent as i32 - 15),
+    }
+}
+
+/// Undo the byte reordering and delta encoding that OpenEXR applies to data before RLE and ZIP
+/// compression.
+fn exr_unpredict(mut data: Vec<u8>) -> Vec<u8> {
+    for i in 1..data.len() {
+        data[i] = data[i - 1].wrapping_add(data[i]).wrapping_sub(128);
+    }
+
+    // The first half holds the even bytes and the second half the odd ones.
+    let (even, odd) = data.split_at(data.len().div_ceil(2));
+    let mut out = Vec::with_capacity(data.len());
+    for (i, &b) in even.iter().enumerate() {
+        out.push(b);
+        if let Some(&b) = odd.get(i) {
+            out.push(b);
+        }
+    }
+    out
+}
+
+/// Decode OpenEXR run-length encoding, where each run starts with a signed count that gives
+/// either the number of literal bytes to copy or one less than the number of repeats.
+fn exr_unrle(data: &[u8]) -> Result<Vec<u8>, String> {
+    let mut out = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        let count = data[pos] as i8;
+        pos += 1;
+        if count < 0 {
+            let n = -(count as i32) as usize;
+            out.extend_from_slice(data.get(pos..pos + n).ok_or("corrupt RLE data")?);
+            pos += n;
+        } else {
+            let value = *data.get(pos).ok_or("corrupt RLE data")?;
+            out.extend(std::iter::repeat_n(value, count as usize + 1));
+            pos += 1;
+        }
+    }
+    Ok(out)
+}
+
+/// The largest factor by which the supported compression methods shrink pixel data, which is
+/// that of deflate in ZIP compression.
+const EXR_MAX_RATIO: usize = 1032;
+
+/// Decode the contents of a single-part scanline OpenEXR file, returning the linear pixels,
+/// width and height.
+fn decode_exr(bytes: &[u8]) -> Result<(Vec<Vector3>, u32, u32), String> {
+    let mut reader = ExrReader { bytes, pos: 0 };
+    if reader.i32()? != 20000630 {
+        return Err("not an OpenEXR file".to_string());
+    }
+    let version = reader.i32()?;
+    if version & 0x200 != 0 {
+        return Err("tiled images are not supported".to_string());
+    }
+    if version & 0x1800 != 0 {
+        return Err("deep and multi-part images are not supported".to_string());
+    }
+
+    // Each channel has a name, a pixel type of 0 for u32, 1 for half or 2 for f32, and its
+    // sampling rates.
+    let mut channels: Vec<(String, i32)> = Vec::new();
+    let mut compression = None;
+    let mut window = None;
+    loop {
+        let name = reader.string()?;
+        if name.is_empty() {
+            break;
+        }
+        let _kind = reader.string()?;
+        let size = reader.size()?;
+        let mut value = ExrReader {
+            bytes: reader.take(size)?,
+            pos: 0,
+        };
+
+        match name.as_str() {
+            "channels" => loop {
+                let channel = value.string()?;
+                if channel.is_empty() {
+                    break;
+                }
+                let pixel_type = value.i32()?;
+                if !(0..=2).contains(&pixel_type) {
+                    return Err(format!("pixel type {} is not supported", pixel_type));
+                }
+                value.take(4)?;
+                if (value.i32()?, value.i32()?) != (1, 1) {
+                    return Err("subsampled channels are not supported".to_string());
+                }
+                channels.push((channel, pixel_type));
+            },
+            "compression" => compression = Some(value.take(1)?[0]),
+            "dataWindow" => {
+                window = Some((value.i32()?, value.i32()?, value.i32()?, value.i32()?));
+            }
+            _ => {}
+        }
+    }
+
+    let (x_min, y_min, x_max, y_max) = window.ok_or("missing data window")?;
+    let extent = |min: i32, max: i32| -> Option<usize> {
+        let extent = (max as i64 - min as i64 + 1).try_into().ok()?;
+        (1..=u32::MAX as usize).contains(&extent).then_some(extent)
+    };
+    let (width, height) = extent(x_min, x_max)
+        .zip(extent(y_min, y_max))
+        .ok_or("invalid data window")?;
+    let lines = match compression.ok_or("missing compression")? {
+        0..=2 => 1,
+        3 => 16,
+        other => return Err(format!("compression method {} is not supported", other)),
+    };
+
+    let sizes: Vec<usize> = channels
+        .iter()
+        .map(|(_, pixel_type)| if *pixel_type == 1 { 2 } else { 4 })
+        .collect();
+    let line_size = width
+        .checked_mul(sizes.iter().sum())
+        .ok_or("data window is too large")?;
+    let find = |name: &str| channels.iter().position(|(channel, _)| channel == name);
+    let rgb = match (find("R"), find("G"), find("B"), find("Y")) {
+        (Some(r), Some(g), Some(b), _) => [r, g, b],
+        (_, _, _, Some(y)) => [y, y, y],
+        _ => return Err("no R, G and B or Y channels".to_string()),
+    };
+
+    // Check that the file could hold every pixel before allocating the image.
+    let pixel_bytes = line_size
+        .checked_mul(height)
+        .ok_or("data window is too large")?;
+    if pixel_bytes / EXR_MAX_RATIO > bytes.len() {
+        return Err("data window is larger than the file".to_string());
+    }
+
+    let mut data = vec![Vector3::zeros(); width * height];
+    let chunks = height.div_ceil(lines);
+    let offsets: Vec<u64> = (0..chunks)
+        .map(|_| reader.u64())
+        .collect::<Result<_, _>>()?;
+    for offset in offsets {
+        reader.pos = offset.try_into().map_err(|_| "invalid scanline offset")?;
+        let y = (reader.i32()? as i64 - y_min as i64)
+            .try_into()
+            .ok()
+            .filter(|&y: &usize| y < height)
+            .ok_or("scanline block is outside the data window")?;
+        let size = reader.size()?;
+        let packed = reader.take(size)?;
+
+        let count = lines.min(height.saturating_sub(y));
+        let expected = count * line_size;
+        let block = if size >= expected {
+            packed.to_vec()
+        } else {
+            match compression {
+                Some(1) => exr_unpredict(exr_unrle(packed)?),
+                Some(2 | 3) => exr_unpredict(
+                    decompress_to_vec_zlib(packed)
+                        .map_err(|status| format!("corrupt ZIP data: {:?}", status))?,
+                ),
+                _ => return Err("corrupt scanline block".to_string()),
+            }
+        };
+        if block.len() < expected {
+            return Err("scanline block is too short".to_string());
+        }
+
+        // Each line holds all the values of each channel in turn.
+        for line in 0..count {
+            let mut starts = Vec::with_capacity(channels.len());
+            let mut start = line * line_size;
+            for size in &sizes {
+                starts.push(start);
+                start += size * width;
+            }
+
+            for x in 0..width {
+                let value = |c: usize| {
+                    let i = starts[c] + x * sizes[c];
+                    let bytes = &block[i..i + sizes[c]];
+                    match channels[c].1 {
+                        0 => u32::from_le_bytes(bytes.try_into().unwrap()) as f32,
+                        1 => half_to_f32(u16::from_le_bytes(bytes.try_into().unwrap())),
+                        _ => f32::from_le_bytes(bytes.try_into().unwrap()),
+                    }
+                };
+                data[(y + line) * width + x] =
+                    Vector3::new(value(rgb[0]), value(rgb[1]), value(rgb[2]));
+            }
+        }
+    }
+
+    Ok((data, width as u32, height as u32))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_save_errors() {
+        let image = Image::new(vec![Vector3::zeros()], 1, 1);
+        let dir = std::env::temp_dir().join("raytracer_missing_dir");
+        let cases = [
+            (dir.join("out.png"), ImageErrorKind::DirectoryMissing, true),
+            (
+                std::env::temp_dir().join("out.jpg"),
+                ImageErrorKind::UnsupportedFormat("jpg".to_string()),
+                false,
+            ),
+        ];
+
+        for (path, expected, has_source) in cases {
+            let err = image.save(&path).unwrap_err();
+            assert!(
+                err.kind == expected && err.path == path && err.source().is_some() == has_source,
+                "Image::save() failed on {}. Expected {:?}, got {:?}.",
+                path.display(),
+                expected,
+                err
+            );
+
+            // The source is reported by `source()` alone, as std errors do.
+            if let Some(source) = err.source() {
+                assert!(
+                    !err.to_string().contains(&source.to_string()),
+                    "ImageError::fmt() failed on {}. Expected no source message, got {}.",
+                    path.display(),
+                    err
+                );
+            }
+        }
+    }
+
+    /// Build a single-part scanline EXR of half RGB pixels with the given compression, which
+    /// is either none or ZIP.
+    fn encode_exr(pixels: &[Vector3], width: u32, height: u32, zip: bool) -> Vec<u8> {
+        let attribute = |out: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]| {
+            for text in [name, kind] {
+                out.extend_from_slice(text.as_bytes());
+                out.push(0);
+            }
+            out.extend_from_slice(&(value.len() as i32).to_le_bytes());
+            out.extend_from_slice(value);
+        };
+        let half = |u: f32| {
+            // Exact for the small dyadic values used below.
+            let bits = u.to_bits();
+            let exponent = ((bits >> 23) & 0xff) as i32 - 127 + 15;
+            if u == 0.0 {
+                0u16
+            } else {
+                ((exponent as u16) << 10) | ((bits >> 13) & 0x3ff) as u16
+            }
+        };
+
+        let mut out = Vec::new();
+        out.extend_from_slice(&20000630i32.to_le_bytes());
+        out.extend_from_slice(&2i32.to_le_bytes());
+        let mut channels = Vec::new();
+        for name in ["B", "G", "R"] {
+            channels.extend_from_slice(name.as_bytes());
+            channels.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0]);
+        }
+        channels.push(0);
+        attribute(&mut out, "channels", "chlist", &channels);
+        attribute(
+            &mut out,
+            "compression",
+            "compression",
+            &[if zip { 3 } else { 0 }],
+        );
+        let window: Vec<u8> = [0, 0, width as i32 - 1, height as i32 - 1]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        attribute(&mut out, "dataWindow", "box2i", &window);
+        out.push(0);
+
+        // Every line holds the B, G and R values in turn.
+        let mut raw = Vec::new();
+        for y in 0..height as usize {
+            for channel in [2, 1, 0] {
+                for x in 0..width as usize {
+                    let p = pixels[y * width as usize + x];
+                    let u = [p.x(), p.y(), p.z()][channel];
+                    raw.extend_from_slice(&half(u).to_le_bytes());
+                }
+            }
+        }
+
+        let lines = if zip { 16 } else { 1 };
+        let line_size = raw.len() / height as usize;
+        let blocks: Vec<Vec<u8>> = raw
+            .chunks(lines * line_size)
+            .map(|block| {
+                if !zip {
+                    return block.to_vec();
+                }
+                let (even, odd): (Vec<_>, Vec<_>) =
+                    block.iter().enumerate().partition(|(i, _)| i % 2 == 0);
+                let mut data: Vec<u8> = even.into_iter().chain(odd).map(|(_, &b)| b).collect();
+                for i in (1..data.len()).rev() {
+                    data[i] = data[i].wrapping_sub(data[i - 1]).wrapping_add(128);
+                }
+                miniz_oxide::deflate::compress_to_vec_zlib(&data, 6)
+            })
+            .collect();
+
+        let mut offset = out.len() + 8 * blocks.len();
+        for block in &blocks {
+            out.extend_from_slice(&(offset as u64).to_le_bytes());
+            offset += 8 + block.len();
+        }
+        for (i, block) in blocks.iter().enumerate() {
+            out.extend_from_slice(&((i * lines) as i32).to_le_bytes());
+            out.extend_from_slice(&(block.len() as i32).to_le_bytes());
+            out.extend_from_slice(block);
+        }
+        out
+    }
+
+    #[test]
+    fn test_decode_hdr() {
+        let header = |resolution: &str| {
+            format!("#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n{}\n", resolution).into_bytes()
+        };
+
+        // Flat scanlines of RGBE quadruples.
+        let mut flat = header("-Y 1 +X 2");
+        flat.extend_from_slice(&[128, 64, 32, 129, 0, 0, 0, 0]);
+        let expected = vec![Vector3::new(1.0, 0.5, 0.25), Vector3::zeros()];
+        let decoded = decode_hdr(&flat);
+        assert!(
+            decoded == Some((expected.clone(), 2, 1)),
+            "decode_hdr() failed on flat scanlines. Expected {:?}, got {:?}.",
+            expected,
+            decoded
+        );
+
+        // A run-length encoded scanline, with runs and literals in each channel.
+        let mut rle = header("-Y 1 +X 8");
+        rle.extend_from_slice(&[2, 2, 0, 8]);
+        rle.extend_from_slice(&[136, 128]);
+        rle.extend_from_slice(&[8, 0, 8, 16, 24, 32, 40, 48, 56]);
+        rle.extend_from_slice(&[132, 64, 132, 0]);
+        rle.extend_from_slice(&[136, 129]);
+        let expected: Vec<Vector3> = (0..8)
+            .map(|i| Vector3::new(1.0, i as f32 / 16.0, if i < 4 { 0.5 } else { 0.0 }))
+            .collect();
+        let decoded = decode_hdr(&rle);
+        assert!(
+            decoded == Some((expected.clone(), 8, 1)),
+            "decode_hdr() failed on a run-length encoded scanline. Expected {:?}, got {:?}.",
+            expected,
+            decoded
+        );
+
+        // Malformed files are rejected rather than panicking or allocating the image.
+        let mut overrun = rle.clone();
+        overrun[rle.len() - 2] = 137;
+        for (name, bytes) in [
+            ("an empty image", header("-Y 0 +X 0")),
+            ("an empty row", header("-Y 4 +X 0")),
+            ("a huge image", header("-Y 1000000 +X 1000000")),
+            ("a truncated scanline", flat[..flat.len() - 1].to_vec()),
+            ("a run past the end of a scanline", overrun),
+        ] {
+            let decoded = decode_hdr(&bytes);
+            assert!(
+                decoded.is_none(),
+                "decode_hdr() failed on {}. Expected None, got {:?}.",
+                name,
+                decoded
+            );
+        }
+    }
+
+    #[test]
+    fn test_load() {
+        let (width, height) = (5, 3);
+        let pixels: Vec<Vector3> = (0..width * height)
+            .map(|i| Vector3::new(i as f32 * 0.25, 0.5, 8.0 - i as f32 * 0.5))
+            .collect();
+        let image = Image::new(pixels.clone(), width, height);
+        let dir = std::env::temp_dir();
+
+        let png = dir.join("raytracer_test_load.png");
+        let srgb = image.map(|p| p.cwise(Vector3::ones(), |u, _| (u / 8.0).min(1.0)));
+        srgb.save(&png).unwrap();
+        let loaded = Image::load(&png).unwrap();
+        let error = loaded
+            .iter()
+            .map(|(x, y, p)| (p - srgb.get(x, y)).norm())
+            .fold(0.0, f32::max);
+        assert!(
+            error <= 1.0 / 255.0,
+            "Image::load() failed on {}. Expected an error of at most one level, got {}.",
+            png.display(),
+            error
+        );
+
+        for zip in [false, true] {
+            let exr = dir.join(format!("raytracer_test_load_{}.exr", zip));
+            fs::write(&exr, encode_exr(&pixels, width, height, zip)).unwrap();
+            let loaded = Image::load(&exr).unwrap();
+            assert_eq!(
+                image,
+                loaded,
+                "Image::load() failed on {}. Expected {:?}, got {:?}.",
+                exr.display(),
+                image,
+                loaded
+            );
+        }
+    }
+
+    #[test]
+    fn test_load_exr_fixture() {
+        // A file from CPython's test data, written by an independent encoder, with half-float
+        // A, B, G and R channels. It declares RLE compression, but its blocks did not shrink and
+        // are stored as they are. It holds the 8-bit values of the PNG next to it unchanged,
+        // rather than converted to linear values.
+        let data = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data");
+        let exr = Image::load(data.join("python.exr")).unwrap();
+        let png = Image::load(data.join("python.png")).unwrap();
+        let error = exr
+            .iter()
+            .map(|(x, y, p)| (p - png.get(x, y)).norm())
+            .fold(0.0, f32::max);
+        assert!(
+            (exr.width(), exr.height()) == (16, 16) && error < 1.0e-3,
+            "Image::load() failed on python.exr. Expected the 16x16 pixels of python.png, got \
+             {}x{} pixels with an error of {}.",
+            exr.width(),
+            exr.height(),
+            error
+        );
+    }
+
+    #[test]
+    fn test_decode_exr_errors() {
+        /// Overwrite the `i32` that starts `offset` bytes after the first `marker` in `bytes`.
+        fn patch(bytes: &[u8], marker: &[u8], offset: usize, value: i32) -> Vec<u8> {
+            let start = bytes
+                .windows(marker.len())
+                .position(|w| w == marker)
+                .unwrap()
+                + marker.len()
+                + offset;
+            let mut bytes = bytes.to_vec();
+            bytes[start..start + 4].copy_from_slice(&value.to_le_bytes());
+            bytes
+        }
+
+        let valid = encode_exr(&[Vector3::ones(); 4], 2, 2, false);
+        let window = b"dataWindow\0box2i\0\x10\0\0\0";
+        let unbounded = patch(&patch(&valid, window, 0, i32::MIN), window, 8, i32::MAX);
+        for (name, bytes) in [
+            (
+                "a negative attribute size",
+                patch(&valid, b"chlist\0", 0, -1),
+            ),
+            ("a reversed data window", patch(&valid, window, 12, -5)),
+            ("an empty data window", patch(&valid, window, 12, -1)),
+            ("a data window wider than u32", unbounded),
+            ("a huge data window", patch(&valid, window, 12, 1 << 20)),
+            ("a truncated file", valid[..valid.len() - 1].to_vec()),
+        ] {
+            let decoded = decode_exr(&bytes);
+            assert!(
+                decoded.is_err(),
+                "decode_exr() failed on {}. Expected an error, got {:?}.",
+                name,
+                decoded
+            );
+        }
+    }
+
+    #[test]
+    fn test_resize() {
+        let mut image = Image::filled(8, 4, Vector3::zeros());
+        for (x, y, _) in image.clone().iter() {
+            if x >= 4 {
+                image.set(x, y, Vector3::ones());
+            }
+        }
+
+        // Halving the size keeps the hard edge for every filter, since each new pixel covers
+        // whole source pixels on one side of it.
+        for filter in [Filter::Nearest, Filter::Box, Filter::Triangle] {
+            let resized = image.resize(4, 2, filter);
+            let row: Vec<f32> = (0..4).map(|x| resized.get(x, 1).x()).collect();
+            assert!(
+                row[0] < 0.01 && row[3] > 0.99 && row[0] <= row[1] && row[1] <= row[2],
+                "Image::resize() failed on {:?}. Expected a rising edge, got {:?}.",
+                filter,
+                row
+            );
+        }
+
+        // Resampling a flat image at any size keeps it flat.
+        let flat = Image::filled(7, 5, Vector3::new(0.2, 0.4, 0.6));
+        for filter in [Filter::CatmullRom, Filter::Lanczos3] {
+            for (width, height) in [(3, 2), (16, 11)] {
+                let resized = flat.resize(width, height, filter);
+                let error = resized
+                    .iter()
+                    .map(|(_, _, p)| (p - flat.get(0, 0)).norm())
+                    .fold(0.0, f32::max);
+                assert!(
+                    error < 1.0e-5,
+                    "Image::resize() failed on {:?} to {}x{}. Expected a flat image, got an error of {}.",
+                    filter,
+                    width,
+                    height,
+                    error
+                );
+            }
+        }
+
+        let crop = image.crop(3, 1, 10, 10);
+        let expected = (5, 3, Vector3::zeros(), Vector3::ones());
+        let found = (crop.width(), crop.height(), crop.get(0, 0), crop.get(1, 0));
+        assert_eq!(
+            expected, found,
+            "Image::crop() failed on (3, 1, 10, 10). Expected {:?}, got {:?}.",
+            expected, found
+        );
+    }
+}
@@ -0,0 +1,670 @@
+use serde::{Deserialize, Serialize};
+
+use std::str::FromStr;
+
+use crate::bidirectional::Bidirectional;
+use crate::material::{Material, Scatterer};
+use crate::object::{Intersection, Object};
+use crate::photon::PhotonMapper;
+use crate::random;
+use crate::render::{self, Film};
+use crate::scene::Scene;
+use crate::vector::Vector3;
+use crate::Ray;
+
+/// A strategy for estimating the light that arrives at the camera along a ray.
+pub trait Integrator: Send + Sync {
+    /// Estimate the light arriving along the camera `ray` from the `scene`.
+    fn radiance(&self, scene: &Scene, ray: Ray) -> Vector3;
+
+    /// Estimate the light arriving along the camera `ray` like `radiance`, adding any light
+    /// found for other pixels of the image to the film. Most integrators only find light for
+    /// the pixel of their ray.
+    fn radiance_with_film(&self, scene: &Scene, ray: Ray, _film: &Film) -> Vector3 {
+        self.radiance(scene, ray)
+    }
+
+    /// Return whether this integrator estimates light, which is carried at sampled wavelengths
+    /// in spectral mode, rather than false colors for debugging.
+    fn is_spectral(&self) -> bool {
+        true
+    }
+}
+
+/// An enum that selects the integrator a scene is rendered with. Available integrators are:
+/// - PathTracer: Traces paths of up to `max_depth` bounces with next-event estimation, through
+///   participating media.
+/// - Bidirectional: Connects paths traced from the camera and from emissive objects, which
+///   finds light that is hard to reach from the camera alone, up to `max_depth` bounces.
+/// - PhotonMapping: Emits `photons` photons from emissive objects and estimates the light they
+///   bring to surfaces from the photons within `radius`, which finds caustics. Photons and
+///   camera rays through specular surfaces are followed for up to `max_depth` bounces.
+/// - Whitted: Lights surfaces directly and only follows perfectly specular reflection and
+///   refraction, up to `max_depth` bounces. Specular materials up to a roughness of
+///   `MIRROR_ROUGHNESS` are followed as perfect mirrors.
+/// - DirectLighting: Shows emitted light and light arriving directly from light sources and
+///   emissive surfaces only.
+/// - AmbientOcclusion: Shows how much of the hemisphere above each surface is unoccluded within
+///   `distance`.
+/// - Normals: Shows the surface normal facing the camera, mapped from `[-1, 1]` to `[0, 1]`.
+/// - Depth: Shows the distance to the first surface, fading from white to black at `far`.
+/// - Uv: Shows the surface coordinates as red and green.
+/// - BounceCount: Shows how many surfaces a path of up to `max_depth` bounces hits, from blue
+///   for none to red for `max_depth`.
+///
+/// Debug integrators ignore participating media and see through interfaces between media.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum IntegratorKind {
+    PathTracer {
+        #[serde(default = "default_depth")]
+        max_depth: usize,
+    },
+    Bidirectional {
+        #[serde(default = "default_depth")]
+        max_depth: usize,
+    },
+    PhotonMapping {
+        #[serde(default = "default_photons")]
+        photons: usize,
+        #[serde(default = "default_radius")]
+        radius: f32,
+        #[serde(default = "default_photon_depth")]
+        max_depth: usize,
+    },
+    Whitted {
+        #[serde(default = "default_depth")]
+        max_depth: usize,
+    },
+    AmbientOcclusion {
+        #[serde(default = "default_distance")]
+        distance: f32,
+    },
+    DirectLighting,
+    Normals,
+    Depth {
+        #[serde(default = "default_far")]
+        far: f32,
+    },
+    Uv,
+    BounceCount {
+        #[serde(default = "default_depth")]
+        max_depth: usize,
+    },
+}
+
+fn default_depth() -> usize {
+    3
+}
+
+fn default_photons() -> usize {
+    100000
+}
+
+fn default_radius() -> f32 {
+    0.1
+}
+
+fn default_photon_depth() -> usize {
+    5
+}
+
+fn default_distance() -> f32 {
+    f32::MAX
+}
+
+fn default_far() -> f32 {
+    10.0
+}
+
+impl Default for IntegratorKind {
+    fn default() -> Self {
+        IntegratorKind::PathTracer {
+            max_depth: default_depth(),
+        }
+    }
+}
+
+impl IntegratorKind {
+    /// Create the integrator this selects for rendering the `scene`, running any passes it
+    /// needs before the camera pass.
+    pub fn build(self, scene: &Scene) -> Box<dyn Integrator> {
+        match self {
+            IntegratorKind::PathTracer { max_depth } => Box::new(PathTracer { max_depth }),
+            IntegratorKind::Bidirectional { max_depth } => Box::new(Bidirectional { max_depth }),
+            IntegratorKind::PhotonMapping {
+                photons,
+                radius,
+                max_depth,
+            } => Box::new(PhotonMapper::new(scene, photons, radius, max_depth)),
+            IntegratorKind::Whitted { max_depth } => Box::new(Whitted { max_depth }),
+            IntegratorKind::AmbientOcclusion { distance } => {
+                Box::new(AmbientOcclusion { distance })
+            }
+            IntegratorKind::DirectLighting => Box::new(DirectLighting),
+            IntegratorKind::Normals => Box::new(Normals),
+            IntegratorKind::Depth { far } => Box::new(Depth { far }),
+            IntegratorKind::Uv => Box::new(Uv),
+            IntegratorKind::BounceCount { max_depth } => Box::new(BounceCount { max_depth }),
+        }
+    }
+
+    /// Return the short name of this integrator, as given on the command line.
+    pub fn name(&self) -> &'static str {
+        match self {
+            IntegratorKind::PathTracer { .. } => "path",
+            IntegratorKind::Bidirectional { .. } => "bdpt",
+            IntegratorKind::PhotonMapping { .. } => "photons",
+            IntegratorKind::Whitted { .. } => "whitted",
+            IntegratorKind::AmbientOcclusion { .. } => "ao",
+            IntegratorKind::DirectLighting => "direct",
+            IntegratorKind::Normals => "normals",
+            IntegratorKind::Depth { .. } => "depth",
+            IntegratorKind::Uv => "uv",
+            IntegratorKind::BounceCount { .. } => "bounces",
+        }
+    }
+
+    /// Return this integrator with its maximum path depth set to `depth`, or unchanged if it
+    /// does not have one.
+    pub fn with_max_depth(self, depth: usize) -> Self {
+        match self {
+            IntegratorKind::PathTracer { .. } => IntegratorKind::PathTracer { max_depth: depth },
+            IntegratorKind::Bidirectional { .. } => {
+                IntegratorKind::Bidirectional { max_depth: depth }
+            }
+            IntegratorKind::PhotonMapping {
+                photons, radius, ..
+            } => IntegratorKind::PhotonMapping {
+                photons,
+                radius,
+                max_depth: depth,
+            },
+            IntegratorKind::Whitted { .. } => IntegratorKind::Whitted { max_depth: depth },
+            IntegratorKind::BounceCount { .. } => IntegratorKind::BounceCount { max_depth: depth },
+            other => other,
+        }
+    }
+}
+
+impl FromStr for IntegratorKind {
+    type Err = String;
+
+    /// Parse the short name of an integrator as given on the command line, with default
+    /// settings.
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Ok(match name {
+            "path" => IntegratorKind::default(),
+            "bdpt" => IntegratorKind::Bidirectional {
+                max_depth: default_depth(),
+            },
+            "photons" => IntegratorKind::PhotonMapping {
+                photons: default_photons(),
+                radius: default_radius(),
+                max_depth: default_photon_depth(),
+            },
+            "whitted" => IntegratorKind::Whitted {
+                max_depth: default_depth(),
+            },
+            "ao" => IntegratorKind::AmbientOcclusion {
+                distance: default_distance(),
+            },
+            "direct" => IntegratorKind::DirectLighting,
+            "normals" => IntegratorKind::Normals,
+            "depth" => IntegratorKind::Depth { far: default_far() },
+            "uv" => IntegratorKind::Uv,
+            "bounces" => IntegratorKind::BounceCount {
+                max_depth: default_depth(),
+            },
+            _ => {
+                return Err(format!(
+                    "Unknown integrator `{}`. Expected one of path, bdpt, photons, whitted, ao, direct, \
+                     normals, depth, uv or bounces.",
+                    name
+                ))
+            }
+        })
+    }
+}
+
+/// Find the first surface along `ray` beyond `tmin` that is not an interface between media,
+/// returning the distance travelled to it along with the intersection and the object that was
+/// hit.
+pub fn first_hit(scene: &Scene, ray: Ray, tmin: f32) -> Option<(f32, Intersection, &Object)> {
+    let mut ray = ray;
+    let mut tmin = tmin;
+    let mut travelled = 0.0;
+    loop {
+        let (t, intersection, object) = scene.closest_intersection(ray, tmin)?;
+        if !matches!(object.material, Material::Interface) {
+            return Some((travelled + t, intersection, object));
+        }
+
+        travelled += t;
+        ray.origin = intersection.position;
+        tmin = 1.0e-3;
+    }
+}
+
+/// Return the light emitted towards the origin of a `ray` that was sampled from a BSDF with the
+/// pdf `bsdf_pdf`, by the first surface it hits or by the environment. Light from the environment
+/// is weighted against the environment sample taken by `Scene::direct_lighting`.
+fn emitted_along(scene: &Scene, ray: Ray, bsdf_pdf: Option<f32>) -> Vector3 {
+    match first_hit(scene, ray, 1.0e-3) {
+        Some((_, intersection, object)) => ray.upsample(object.emitted(&intersection, ray.time)),
+        None => {
+            let radiance = ray.upsample(scene.environment.radiance(ray.direction));
+            match bsdf_pdf {
+                Some(pdf) => {
+                    let light_pdf = scene.environment.pdf(ray.direction);
+                    random::power_heuristic(pdf, light_pdf) * radiance
+                }
+                None => radiance,
+            }
+        }
+    }
+}
+
+/// The roughness up to which `Whitted` treats specular materials as perfect mirrors, since
+/// their GGX lobe is too narrow to be sampled as a glossy surface.
+pub const MIRROR_ROUGHNESS: f32 = 0.1;
+
+/// Trace a ray Whitted-style, following perfectly specular bounces up to `depth` times. At
+/// every other surface, the light emitted by it and arriving at it directly from light sources
+/// and emissive surfaces is found.
+fn whitted(scene: &Scene, ray: Ray, tmin: f32, depth: usize) -> Vector3 {
+    let (_, intersection, object) = match first_hit(scene, ray, tmin) {
+        Some(hit) => hit,
+        None => return ray.upsample(scene.environment.radiance(ray.direction)),
+    };
+
+    let emitted = ray.upsample(object.emitted(&intersection, ray.time));
+    match &object.material {
+        Material::Emissive { .. } => return emitted,
+        Material::Specular { color, roughness } if *roughness <= MIRROR_ROUGHNESS => {
+            render::count_bounce();
+            let normal = intersection.normal;
+            let mirrored = Ray {
+                origin: intersection.position,
+                direction: ray.direction - normal * (2.0 * ray.direction.dot(normal)),
+                ..ray
+            };
+            let reflected = if depth > 0 {
+                whitted(scene, mirrored, 1.0e-3, depth - 1)
+            } else {
+                emitted_along(scene, mirrored, None)
+            };
+            return emitted + ray.upsample(color.at(ray.time)).cwise_mul(reflected);
+        }
+        _ => {}
+    }
+
+    let scatterer = Scatterer::Surface {
+        material: &object.material,
+        normal: intersection.normal,
+    };
+    let direct = scene.direct_lighting(&scatterer, ray, intersection.position, true, None);
+
+    // Emissive surfaces can only be found by the bounce ray, which is followed further if it
+    // is specular.
+    let bounced = match object.material.bounce(ray, &intersection) {
+        Some((bounce, sample, weight)) if sample.specular && depth > 0 => {
+            weight.cwise_mul(whitted(scene, bounce, 1.0e-3, depth - 1))
+        }
+        Some((bounce, sample, weight)) => {
+            let pdf = (!sample.specular).then_some(sample.pdf);
+            weight.cwise_mul(emitted_along(scene, bounce, pdf))
+        }
+        None => Vector3::zeros(),
+    };
+
+    emitted + direct + bounced
+}
+
+/// The recursive path tracer of `Scene::sample`.
+pub struct PathTracer {
+    pub max_depth: usize,
+}
+
+impl Integrator for PathTracer {
+    fn radiance(&self, scene: &Scene, ray: Ray) -> Vector3 {
+        scene.sample(ray, 0.0, self.max_depth)
+    }
+}
+
+/// A Whitted-style recursive ray tracer.
+pub struct Whitted {
+    pub max_depth: usize,
+}
+
+impl Integrator for Whitted {
+    fn radiance(&self, scene: &Scene, ray: Ray) -> Vector3 {
+        whitted(scene, ray, 0.0, self.max_depth)
+    }
+}
+
+/// An ambient occlusion integrator that casts one cosine-weighted ray per sample.
+pub struct AmbientOcclusion {
+    pub distance: f32,
+}
+
+impl Integrator for AmbientOcclusion {
+    fn radiance(&self, scene: &Scene, ray: Ray) -> Vector3 {
+        let intersection = match first_hit(scene, ray, 0.0) {
+            Some((_, intersection, _)) => intersection,
+            None => return Vector3::zeros(),
+        };
+
+        let occlusion = Ray {
+            origin: intersection.position,
+            direction: random::cosine_hemisphere(intersection.normal),
+            ..ray
+        };
+        match first_hit(scene, occlusion, 1.0e-3) {
+            Some((t, _, _)) if t < self.distance => Vector3::zeros(),
+            _ => Vector3::ones(),
+        }
+    }
+}
+
+/// An integrator that only includes emitted light and light arriving directly from light
+/// sources and emissive surfaces.
+pub struct DirectLighting;
+
+impl Integrator for DirectLighting {
+    fn radiance(&self, scene: &Scene, ray: Ray) -> Vector3 {
+        whitted(scene, ray, 0.0, 0)
+    }
+}
+
+/// A debug integrator that shows surface normals.
+pub struct Normals;
+
+impl Integrator for Normals {
+    fn radiance(&self, scene: &Scene, ray: Ray) -> Vector3 {
+        match first_hit(scene, ray, 0.0) {
+            Some((_, intersection, _)) => 0.5 * (intersection.normal + Vector3::ones()),
+            None => Vector3::zeros(),
+        }
+    }
+
+    fn is_spectral(&self) -> bool {
+        false
+    }
+}
+
+/// A debug integrator that shows the distance to surfaces.
+pub struct Depth {
+    pub far: f32,
+}
+
+impl Integrator for Depth {
+    fn radiance(&self, scene: &Scene, ray: Ray) -> Vector3 {
+        match first_hit(scene, ray, 0.0) {
+            Some((t, _, _)) => (1.0 - t / self.far).clamp(0.0, 1.0) * Vector3::ones(),
+            None => Vector3::zeros(),
+        }
+    }
+
+    fn is_spectral(&self) -> bool {
+        false
+    }
+}
+
+/// A debug integrator that shows surface coordinates.
+pub struct Uv;
+
+impl Integrator for Uv {
+    fn radiance(&self, scene: &Scene, ray: Ray) -> Vector3 {
+        match first_hit(scene, ray, 0.0) {
+            Some((_, Intersection { uv: (u, v), .. }, _)) => {
+                Vector3::new(u.rem_euclid(1.0), v.rem_euclid(1.0), 0.0)
+            }
+            None => Vector3::zeros(),
+        }
+    }
+
+    fn is_spectral(&self) -> bool {
+        false
+    }
+}
+
+/// A debug integrator that shows how many surfaces a path bounces off.
+pub struct BounceCount {
+    pub max_depth: usize,
+}
+
+impl BounceCount {
+    /// Count the surfaces hit by a path that starts with `ray`.
+    fn count(&self, scene: &Scene, ray: Ray) -> usize {
+        let mut ray = ray;
+        let mut tmin = 0.0;
+        for count in 0..self.max_depth {
+            let (_, intersection, object) = match first_hit(scene, ray, tmin) {
+                Some(hit) => hit,
+                None => return count,
+            };
+            ray = match object.material.bounce(ray, &intersection) {
+                Some((bounce, _, _)) => bounce,
+                None => return count + 1,
+            };
+            tmin = 1.0e-3;
+        }
+
+        self.max_depth
+    }
+}
+
+impl Integrator for BounceCount {
+    fn radiance(&self, scene: &Scene, ray: Ray) -> Vector3 {
+        let t = self.count(scene, ray) as f32 / self.max_depth.max(1) as f32;
+        Vector3::new(t, 1.0 - (2.0 * t - 1.0).abs(), 1.0 - t)
+    }
+
+    fn is_spectral(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Return a scene with the `objects` given as JSON, seen by a pinhole camera.
+    fn scene(objects: &str) -> Scene {
+        let mut scene: Scene = serde_json::from_str(&format!(
+            r#"{{"camera": {{"focal_len": 1.0, "width": 1.0}}, "objects": [{}]}}"#,
+            objects
+        ))
+        .unwrap();
+        scene.link().unwrap();
+        scene
+    }
+
+    /// A plane facing the camera 5 units away, which hits the square from -1 to 1.
+    const PLANE: &str = r#"{
+        "object": "Plane",
+        "material": {"Diffuse": {"color": {"x": 0.5, "y": 0.5, "z": 0.5}}},
+        "transforms": [{"Translate": {"x": 0.0, "y": 0.0, "z": 5.0}}]
+    }"#;
+
+    /// A diffuse ball lit from above by an emissive ball.
+    const LIT_BALL: &str = r#"{
+        "object": "Sphere",
+        "material": {"Diffuse": {"color": {"x": 0.8, "y": 0.5, "z": 0.2}}},
+        "transforms": [
+            {"Scale": {"x": 100.0, "y": 100.0, "z": 100.0}},
+            {"Translate": {"x": 0.0, "y": -101.0, "z": 5.0}}
+        ]
+    }, {
+        "object": "Sphere",
+        "material": {"Emissive": {"color": {"x": 1.0, "y": 1.0, "z": 1.0}, "intensity": 4.0}},
+        "transforms": [{"Translate": {"x": 0.0, "y": 2.0, "z": 5.0}}]
+    }"#;
+
+    #[test]
+    fn test_debug_integrators() {
+        let scene = scene(PLANE);
+        let center = Ray::new(Vector3::zeros(), Vector3::unit(0.0, 0.0, 1.0));
+        let corner = Ray::new(Vector3::zeros(), Vector3::unit(0.5, -0.5, 5.0));
+        let miss = Ray::new(Vector3::zeros(), Vector3::unit(0.0, 0.0, -1.0));
+
+        let cases: [(&str, &dyn Integrator, Ray, Vector3); 6] = [
+            ("Normals", &Normals, center, Vector3::new(0.5, 0.5, 0.0)),
+            (
+                "Depth",
+                &Depth { far: 10.0 },
+                center,
+                Vector3::new(0.5, 0.5, 0.5),
+            ),
+            ("Uv", &Uv, corner, Vector3::new(0.75, 0.25, 0.0)),
+            ("Normals", &Normals, miss, Vector3::zeros()),
+            ("Depth", &Depth { far: 10.0 }, miss, Vector3::zeros()),
+            ("Uv", &Uv, miss, Vector3::zeros()),
+        ];
+        for (name, integrator, ray, expected) in cases {
+            let radiance = integrator.radiance(&scene, ray);
+            assert!(
+                (radiance - expected).norm() < 1.0e-4,
+                "{}::radiance() failed on {:?}. Expected {}, got {}.",
+                name,
+                ray.direction,
+                expected,
+                radiance
+            );
+        }
+    }
+
+    #[test]
+    fn test_ambient_occlusion() {
+        // Nothing can occlude a lone convex surface, whatever direction is sampled.
+        let scene = scene(PLANE);
+        let integrator = AmbientOcclusion { distance: 100.0 };
+        let ray = Ray::new(Vector3::zeros(), Vector3::unit(0.0, 0.0, 1.0));
+        for _ in 0..100 {
+            let radiance = integrator.radiance(&scene, ray);
+            assert!(
+                radiance == Vector3::ones(),
+                "AmbientOcclusion::radiance() failed in open space. Expected {}, got {}.",
+                Vector3::ones(),
+                radiance
+            );
+        }
+    }
+
+    #[test]
+    fn test_direct_lighting() {
+        // The path tracer only finds emissive objects with bounce rays, so with two bounces it
+        // finds the same emitted and direct light.
+        let scene = scene(LIT_BALL);
+        let ray = Ray::new(Vector3::zeros(), Vector3::unit(0.0, -1.0, 5.0));
+        let n = 20000;
+        let estimate = |integrator: &dyn Integrator| {
+            random::reseed(1, 0);
+            (0..n)
+                .map(|_| integrator.radiance(&scene, ray))
+                .fold(Vector3::zeros(), |a, b| a + b)
+                * (1.0 / n as f32)
+        };
+        let expected = estimate(&PathTracer { max_depth: 2 });
+        let radiance = estimate(&DirectLighting);
+        assert!(
+            (radiance - expected).norm() < 0.02 * expected.norm(),
+            "DirectLighting::radiance() failed on a lit ball. Expected {}, got {}.",
+            expected,
+            radiance
+        );
+    }
+
+    #[test]
+    fn test_whitted_mirror() {
+        // A mirror facing the camera reflects a diffuse ball behind the camera, which is lit by
+        // a directional light.
+        let mut scene: Scene = serde_json::from_str(
+            r#"{
+                "camera": {"focal_len": 1.0, "width": 1.0},
+                "objects": [
+                    {
+                        "object": "Plane",
+                        "material": {"Specular": {"color": {"x": 0.9, "y": 0.8, "z": 0.7}, "roughness": 0.1}},
+                        "transforms": [{"Translate": {"x": 0.0, "y": 0.0, "z": 5.0}}]
+                    },
+                    {
+                        "object": "Sphere",
+                        "material": {"Diffuse": {"color": {"x": 0.5, "y": 0.5, "z": 0.5}}},
+                        "transforms": [{"Translate": {"x": 1.3, "y": 0.0, "z": -3.0}}]
+                    }
+                ],
+                "lights": [{"Directional": {
+                    "direction": {"x": 0.0, "y": 0.0, "z": 1.0},
+                    "color": {"x": 1.0, "y": 1.0, "z": 1.0},
+                    "intensity": 1.0
+                }}]
+            }"#,
+        )
+        .unwrap();
+        scene.link().unwrap();
+
+        let ray = Ray::new(Vector3::zeros(), Vector3::unit(0.1, 0.0, 1.0));
+        let reflected = Ray::new(Vector3::new(0.5, 0.0, 5.0), Vector3::unit(0.1, 0.0, -1.0));
+        let expected = Vector3::new(0.9, 0.8, 0.7).cwise_mul(whitted(&scene, reflected, 1.0e-3, 0));
+        let radiance = Whitted { max_depth: 1 }.radiance(&scene, ray);
+        assert!(
+            expected.norm() > 0.0 && (radiance - expected).norm() < 1.0e-4,
+            "Whitted::radiance() failed on a mirror. Expected {}, got {}.",
+            expected,
+            radiance
+        );
+    }
+
+    #[test]
+    fn test_bounce_count() {
+        // Diffuse bounces off the plane escape, and those inside a closed ball never do.
+        let plane = scene(PLANE);
+        let inside = scene(
+            r#"{
+                "object": "Sphere",
+                "material": {"Diffuse": {"color": {"x": 0.5, "y": 0.5, "z": 0.5}}},
+                "transforms": [{"Scale": {"x": 10.0, "y": 10.0, "z": 10.0}}]
+            }"#,
+        );
+        let integrator = BounceCount { max_depth: 4 };
+        let cases = [
+            ("a miss", &plane, Vector3::unit(0.0, 0.0, -1.0), 0),
+            ("a plane", &plane, Vector3::unit(0.0, 0.0, 1.0), 1),
+            ("a closed ball", &inside, Vector3::unit(0.0, 0.0, 1.0), 4),
+        ];
+        for (name, scene, direction, expected) in cases {
+            let ray = Ray::new(Vector3::zeros(), direction);
+            let count = integrator.count(scene, ray);
+            assert!(
+                count == expected,
+                "BounceCount::count() failed on {}. Expected {}, got {}.",
+                name,
+                expected,
+                count
+            );
+        }
+    }
+
+    #[test]
+    fn test_from_str() {
+        for (name, expected) in [
+            ("path", IntegratorKind::PathTracer { max_depth: 3 }),
+            ("direct", IntegratorKind::DirectLighting),
+            ("uv", IntegratorKind::Uv),
+        ] {
+            let kind = name.parse::<IntegratorKind>();
+            assert!(
+                kind == Ok(expected),
+                "IntegratorKind::from_str() failed on {}. Expected {:?}, got {:?}.",
+                name,
+                expected,
+                kind
+            );
+        }
+        assert!(
+            "nope".parse::<IntegratorKind>().is_err(),
+            "IntegratorKind::from_str() failed on nope. Expected an error."
+        );
+    }
+}
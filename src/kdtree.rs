@@ -0,0 +1,141 @@
+use crate::vector::Vector3;
+
+/// A balanced kd-tree over points with attached data, stored implicitly in an array so that the
+/// median of every subtree sits at its middle and its halves on either side.
+pub struct KdTree<T> {
+    nodes: Vec<(Vector3, T)>,
+    /// The axis each node splits its subtree along.
+    axes: Vec<u8>,
+}
+
+/// Return the coordinate of `point` along `axis`.
+fn coordinate(point: Vector3, axis: u8) -> f32 {
+    match axis {
+        0 => point.x(),
+        1 => point.y(),
+        _ => point.z(),
+    }
+}
+
+impl<T> KdTree<T> {
+    /// Build a tree over the given points.
+    pub fn new(points: Vec<(Vector3, T)>) -> Self {
+        let mut nodes = points;
+        let mut axes = vec![0; nodes.len()];
+        build(&mut nodes, &mut axes);
+
+        KdTree { nodes, axes }
+    }
+
+    /// Return the number of points in the tree.
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Return whether the tree has no points.
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Call `f` on every point within `radius` of `center`, together with its data.
+    pub fn within(&self, center: Vector3, radius: f32, mut f: impl FnMut(Vector3, &T)) {
+        self.search(0, self.nodes.len(), center, radius, &mut f);
+    }
+
+    fn search(
+        &self,
+        start: usize,
+        end: usize,
+        center: Vector3,
+        radius: f32,
+        f: &mut impl FnMut(Vector3, &T),
+    ) {
+        if start >= end {
+            return;
+        }
+
+        let mid = start + (end - start) / 2;
+        let (point, data) = &self.nodes[mid];
+        if (*point - center).squared_norm() <= radius * radius {
+            f(*point, data);
+        }
+
+        // Only visit the halves of the subtree that the sphere reaches into.
+        let axis = self.axes[mid];
+        let offset = coordinate(center, axis) - coordinate(*point, axis);
+        if offset <= radius {
+            self.search(start, mid, center, radius, f);
+        }
+        if offset >= -radius {
+            self.search(mid + 1, end, center, radius, f);
+        }
+    }
+}
+
+/// Arrange `nodes` into a balanced tree in place, splitting every subtree along the axis its
+/// points spread furthest along and recording that axis in `axes`.
+fn build<T>(nodes: &mut [(Vector3, T)], axes: &mut [u8]) {
+    if nodes.len() <= 1 {
+        return;
+    }
+
+    let (min, max) = nodes.iter().fold(
+        (
+            Vector3::ones() * f32::INFINITY,
+            Vector3::ones() * f32::NEG_INFINITY,
+        ),
+        |(min, max), (point, _)| (min.cwise(*point, f32::min), max.cwise(*point, f32::max)),
+    );
+    let extent = max - min;
+    let axis = if extent.x() >= extent.y() && extent.x() >= extent.z() {
+        0
+    } else if extent.y() >= extent.z() {
+        1
+    } else {
+        2
+    };
+
+    let mid = nodes.len() / 2;
+    nodes.select_nth_unstable_by(mid, |(a, _), (b, _)| {
+        coordinate(*a, axis).total_cmp(&coordinate(*b, axis))
+    });
+    axes[mid] = axis;
+
+    let (left, right) = nodes.split_at_mut(mid);
+    let (left_axes, right_axes) = axes.split_at_mut(mid);
+    build(left, left_axes);
+    build(&mut right[1..], &mut right_axes[1..]);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::random;
+
+    #[test]
+    fn test_within() {
+        let points: Vec<_> = (0..2000)
+            .map(|i| (random::unit_sphere() * random::uniform(), i))
+            .collect();
+        let tree = KdTree::new(points.clone());
+
+        for _ in 0..20 {
+            let center = random::unit_sphere() * 0.5;
+            let radius = 0.3 * random::uniform();
+
+            let mut found = Vec::new();
+            tree.within(center, radius, |_, i| found.push(*i));
+            found.sort();
+            let expected: Vec<_> = points
+                .iter()
+                .filter(|(point, _)| (*point - center).squared_norm() <= radius * radius)
+                .map(|(_, i)| *i)
+                .collect();
+            assert_eq!(
+                expected, found,
+                "KdTree::within() failed on {} with radius {}. Expected {:?}, got {:?}.",
+                center, radius, expected, found
+            );
+        }
+    }
+}
@@ -1,23 +1,64 @@
+pub mod animation;
+pub mod bidirectional;
+pub mod color;
+pub mod compare;
+pub mod environment;
+pub mod error;
+pub mod image;
+pub mod integrator;
+pub mod kdtree;
+pub mod light;
 pub mod material;
+pub mod medium;
 pub mod object;
+pub mod photon;
+pub mod render;
+pub mod spectrum;
+pub mod texture;
 pub mod transform;
 pub mod vector;
 
 use serde::{Deserialize, Serialize};
 
+use animation::Animated;
+use error::SceneError;
+use spectrum::Wavelengths;
+use transform::TransformStack;
 use vector::Vector3;
 
 #[derive(Clone, Copy, Debug)]
-/// A ray in 3D space with direction and origin.
+/// A ray in 3D space with direction and origin, cast at the given `time` in frames. In spectral
+/// mode, the ray carries light at the given `wavelengths` instead of RGB.
 pub struct Ray {
     pub origin: Vector3,
     pub direction: Vector3,
+    pub time: f32,
+    pub wavelengths: Option<Wavelengths>,
 }
 
 impl Ray {
-    /// Create a new ray with the given `origin` in the given `direction`.
+    /// Create a new ray with the given `origin` in the given `direction` at time zero.
     pub fn new(origin: Vector3, direction: Vector3) -> Self {
-        Ray { origin, direction }
+        Ray {
+            origin,
+            direction,
+            time: 0.0,
+            wavelengths: None,
+        }
+    }
+
+    /// Convert the linear sRGB color `rgb` into the light carried by this ray, which is the
+    /// color itself unless the ray carries wavelengths.
+    pub fn upsample(&self, rgb: Vector3) -> Vector3 {
+        match &self.wavelengths {
+            Some(wavelengths) => wavelengths.upsample(rgb),
+            None => rgb,
+        }
+    }
+
+    /// Return a copy of this ray cast at the given `time`.
+    pub fn with_time(self, time: f32) -> Self {
+        Ray { time, ..self }
     }
 
     /// Get the position at the specified time `t`.
@@ -26,143 +67,339 @@ impl Ray {
     }
 }
 
-/// A struct representing a raytracing camera.
+/// A struct representing a raytracing camera. The camera sits at the origin looking down the
+/// `+z` axis, and is placed in the scene by its `transforms`. The shutter is open from
+/// `shutter_open` to `shutter_close` frames after the frame time, and objects with animated
+/// transforms are blurred over that interval.
 #[derive(Deserialize, Serialize)]
 pub struct Camera {
-    pub focal_len: f32,
-    pub width: f32,
+    pub focal_len: Animated<f32>,
+    pub width: Animated<f32>,
+    #[serde(default)]
+    pub transforms: TransformStack,
+    #[serde(default)]
+    pub shutter_open: f32,
+    #[serde(default)]
+    pub shutter_close: f32,
 }
 
 impl Camera {
-    /// Find the ray for the pixel at `x`, `y` if the image has resolution `x_res`, `y_res`.
-    pub fn ray(&self, x: u32, y: u32, x_res: u32, y_res: u32) -> Ray {
+    /// Check that the focal length and width of this camera at `path` are positive and that
+    /// its transforms are valid.
+    pub fn validate(&self, path: &str) -> Result<(), SceneError> {
+        for (name, value) in [("focal_len", &self.focal_len), ("width", &self.width)] {
+            value.try_each(&format!("{}.{}", path, name), |path, value| {
+                if value.is_finite() && *value > 0.0 {
+                    Ok(())
+                } else {
+                    Err(SceneError::invalid(
+                        path,
+                        format!("camera {} {} must be positive", name, value),
+                    ))
+                }
+            })?;
+        }
+        self.transforms.validate(&format!("{}.transforms", path))
+    }
+
+    /// Find a ray for the pixel at `x`, `y` if the image has resolution `x_res`, `y_res` when
+    /// rendering the frame at `frame_time`. The ray is cast at a random time while the shutter
+    /// is open.
+    pub fn ray(&self, x: u32, y: u32, x_res: u32, y_res: u32, frame_time: f32) -> Ray {
+        let time = frame_time + self.shutter_time();
+
         let aspect_ratio = x_res as f32 / y_res as f32;
+        let width = self.width.at(time);
 
-        let dw = self.width / x_res as f32;
-        let dh = self.width / (y_res as f32 * aspect_ratio);
+        let dw = width / x_res as f32;
+        let dh = width / (y_res as f32 * aspect_ratio);
 
         // Relative x and y positions
-        let x_i = (x as f32 - x_res as f32 / 2.0) as f32 * dw;
-        let y_i = (y as f32 - y_res as f32 / 2.0) as f32 * dh;
+        let x_i = (x as f32 - x_res as f32 / 2.0) * dw;
+        let y_i = (y as f32 - y_res as f32 / 2.0) * dh;
 
         // Ray direction
-        let direction = Vector3::unit(x_i, -y_i, self.focal_len);
-
-        Ray::new(Vector3::zeros(), direction)
+        let direction = Vector3::unit(x_i, -y_i, self.focal_len.at(time));
+
+        // Place the ray in the scene.
+        let affine = self.transforms.at(time);
+        Ray {
+            origin: affine.point_to_world(Vector3::zeros()),
+            direction: affine.matrix().transform_vector(direction).normalized(),
+            time,
+            wavelengths: None,
+        }
     }
-}
 
-pub mod image {
-    use crate::Vector3;
+    /// Project the `point` seen from the camera at `time` onto an image with resolution
+    /// `x_res`, `y_res`, the inverse of `ray`. Return the pixel whose ray passes closest to the
+    /// point, which covers the square of the image centered on its ray, together with the
+    /// importance of the pixel in the direction of the point: the inverse of the solid angle
+    /// the pixel covers there, so that light reaching the camera from the point is weighted by
+    /// it to find its contribution to the pixel. Points outside the image have no pixel.
+    pub fn importance(
+        &self,
+        point: Vector3,
+        time: f32,
+        x_res: u32,
+        y_res: u32,
+    ) -> Option<(u32, u32, f32)> {
+        let aspect_ratio = x_res as f32 / y_res as f32;
+        let width = self.width.at(time);
+        let focal_len = self.focal_len.at(time);
+
+        let dw = width / x_res as f32;
+        let dh = width / (y_res as f32 * aspect_ratio);
 
-    use std::fs::File;
-    use std::io::BufWriter;
-    use std::path::Path;
+        let affine = self.transforms.at(time);
+        let origin = affine.point_to_world(Vector3::zeros());
+        let local = affine.inverse().transform_vector(point - origin);
+        if local.z() <= 0.0 {
+            return None;
+        }
 
-    use png::{BitDepth, ColorType, Encoder};
+        // Relative x and y positions, and the pixel whose ray passes through them.
+        let x_i = local.x() / local.z() * focal_len;
+        let y_i = -local.y() / local.z() * focal_len;
+        let x = (x_i / dw + x_res as f32 / 2.0 + 0.5).floor();
+        let y = (y_i / dh + y_res as f32 / 2.0 + 0.5).floor();
+        if !(0.0..x_res as f32).contains(&x) || !(0.0..y_res as f32).contains(&y) {
+            return None;
+        }
 
-    #[derive(Clone, Copy, Debug)]
-    pub enum ImageError {
-        FileCreateError,
-        EncoderError,
-        ImageWriteError,
+        // The pixel covers an area of dw by dh on the image plane, which is placed in the scene
+        // by the transforms, so find the solid angle that area covers seen from the camera.
+        let matrix = affine.matrix();
+        let film = matrix.transform_vector(Vector3::new(x_i, -y_i, focal_len));
+        let normal = matrix
+            .transform_vector(Vector3::new(1.0, 0.0, 0.0))
+            .cross(matrix.transform_vector(Vector3::new(0.0, 1.0, 0.0)));
+        let distance = film.norm();
+        let importance = distance.powi(3) / (dw * dh * normal.dot(film).abs());
+
+        importance
+            .is_finite()
+            .then_some((x as u32, y as u32, importance))
     }
 
-    impl From<ImageError> for &'static str {
-        fn from(err: ImageError) -> Self {
-            match err {
-                ImageError::FileCreateError => "Error creating file.",
-                ImageError::EncoderError => "Error encoding image.",
-                ImageError::ImageWriteError => "Error writing image.",
-            }
+    /// Sample a time offset uniformly while the shutter is open.
+    fn shutter_time(&self) -> f32 {
+        if self.shutter_close > self.shutter_open {
+            self.shutter_open + (self.shutter_close - self.shutter_open) * random::uniform()
+        } else {
+            self.shutter_open
         }
     }
+}
 
-    pub struct Image {
-        data: Vec<Vector3>,
-        width: u32,
-        height: u32,
+pub mod random {
+    use crate::vector::Vector3;
+
+    use rand::prelude::*;
+    use rand::rngs::StdRng;
+    use std::cell::RefCell;
+    use std::f32::consts::{PI, TAU};
+
+    thread_local! {
+        /// Each thread draws from its own generator, which starts from entropy unless it is
+        /// reseeded.
+        static RNG: RefCell<StdRng> = RefCell::new(StdRng::from_entropy());
     }
 
-    impl Image {
-        pub fn new(data: Vec<Vector3>, width: u32, height: u32) -> Self {
-            Image {
-                data,
-                width,
-                height,
-            }
-        }
+    /// Reseed this thread's generator from `seed` and the index of a `stream` of work, such as
+    /// a tile, so that the work draws the same numbers whichever thread it runs on.
+    pub fn reseed(seed: u64, stream: u64) {
+        // Mix the stream into the seed with SplitMix64 so that nearby streams are unrelated.
+        let mut z = seed ^ stream.wrapping_mul(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
 
-        pub fn save(&self, path: impl AsRef<Path>) -> Result<(), ImageError> {
-            let mut buffer = Vec::with_capacity(4 * self.data.len());
-            for pixel in &self.data {
-                buffer.push((pixel.x() * 255.0) as u8);
-                buffer.push((pixel.y() * 255.0) as u8);
-                buffer.push((pixel.z() * 255.0) as u8);
-            }
+        RNG.with(|rng| *rng.borrow_mut() = StdRng::seed_from_u64(z));
+    }
 
-            let file = File::create(path).map_err(|_| ImageError::FileCreateError)?;
-            let writer = BufWriter::new(file);
+    pub fn uniform() -> f32 {
+        RNG.with(|rng| rng.borrow_mut().gen())
+    }
 
-            let mut encoder = Encoder::new(writer, self.width, self.height);
-            encoder.set_color(ColorType::RGB);
-            encoder.set_depth(BitDepth::Eight);
+    pub fn normal() -> f32 {
+        let u: f32 = uniform();
+        let v: f32 = uniform();
 
-            let mut writer = encoder
-                .write_header()
-                .map_err(|_| ImageError::EncoderError)?;
-            writer
-                .write_image_data(&buffer)
-                .map_err(|_| ImageError::ImageWriteError)?;
+        (-2.0 * u.ln()).sqrt() * (TAU * v).cos()
+    }
 
-            Ok(())
+    /// Sample a direction uniformly on the unit sphere, with pdf `1 / 4pi`.
+    pub fn unit_sphere() -> Vector3 {
+        Vector3::unit(normal(), normal(), normal())
+    }
+
+    /// Sample a direction uniformly on the hemisphere around `normal`, with pdf `1 / 2pi`.
+    pub fn hemisphere(normal: Vector3) -> Vector3 {
+        let dir = unit_sphere();
+        if dir.dot(normal) < 0.0 {
+            -dir
+        } else {
+            dir
         }
     }
-}
 
-pub mod random {
-    use rand::prelude::*;
-    use std::f32::consts::TAU;
+    /// Sample a direction on the hemisphere around `normal` with pdf `cos(theta) / pi`.
+    pub fn cosine_hemisphere(normal: Vector3) -> Vector3 {
+        let (tangent, bitangent) = normal.basis();
+        let r = uniform().sqrt();
+        let phi = TAU * uniform();
+        let z = (1.0 - r * r).max(0.0).sqrt();
 
-    pub fn uniform() -> f32 {
-        thread_rng().gen()
+        tangent * (r * phi.cos()) + bitangent * (r * phi.sin()) + normal * z
     }
 
-    pub fn normal() -> f32 {
-        let u: f32 = thread_rng().gen();
-        let v: f32 = thread_rng().gen();
+    /// Return the pdf of sampling `dir` with `cosine_hemisphere` around `normal`.
+    pub fn cosine_hemisphere_pdf(normal: Vector3, dir: Vector3) -> f32 {
+        normal.dot(dir).max(0.0) / PI
+    }
 
-        (-2.0 * u.ln()).sqrt() * (TAU * v).cos()
+    /// Weight a sample taken with pdf `pdf` against another strategy with pdf `other_pdf` when
+    /// combining them with multiple importance sampling.
+    pub fn power_heuristic(pdf: f32, other_pdf: f32) -> f32 {
+        let (a, b) = (pdf * pdf, other_pdf * other_pdf);
+        if a + b > 0.0 {
+            a / (a + b)
+        } else {
+            0.0
+        }
     }
 }
 
 pub mod scene {
-    use crate::image::Image;
-    use crate::object::{Intersection, Object, Renderable};
+    use crate::color;
+    use crate::environment::Environment;
+    use crate::error::{SceneError, SceneErrorKind};
+    use crate::image::{Image, ImageError};
+    use crate::integrator::{Integrator, IntegratorKind};
+    use crate::light::Light;
+    use crate::material::{Material, Scatterer};
+    use crate::medium::{Medium, MediumEvent};
+    use crate::object::{self, Emitter, Intersection, Node, Object};
+    use crate::random;
+    use crate::render::{self, Film, Progress, RenderOptions, RenderStats, Tile};
+    use crate::spectrum::Wavelengths;
+    use crate::transform::Affine;
     use crate::vector::Vector3;
     use crate::{Camera, Ray};
 
-    use rayon::prelude::*;
     use serde::{Deserialize, Serialize};
 
-    use std::fs::File;
-    use std::io::{self, BufReader};
-
-    /// A simple scene with a camera and some objects.
+    use std::collections::HashMap;
+    use std::fs;
+    use std::path::Path;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::Instant;
+
+    /// A simple scene with a camera and a graph of objects. Named `definitions` can be placed any
+    /// number of times in the graph with instance nodes. Rays that escape the scene see the
+    /// `environment`, and punctual `lights` light the scene without being visible themselves.
+    /// An optional `medium` fills the space outside of all objects, such as fog. If `spectral`
+    /// is set, light is carried at sampled wavelengths rather than as RGB. The scene is rendered
+    /// with the chosen `integrator`, which defaults to path tracing. If a `seed` is given, every
+    /// render of the scene draws the same random numbers, however many threads it runs on.
+    /// Animated values are evaluated at the scene's `time`, in frames.
     #[derive(Deserialize, Serialize)]
     pub struct Scene {
         pub camera: Camera,
-        pub objects: Vec<Object>,
+        pub objects: Vec<Node>,
+        #[serde(default)]
+        pub definitions: HashMap<String, Arc<Node>>,
+        #[serde(default)]
+        pub environment: Environment,
+        #[serde(default)]
+        pub lights: Vec<Light>,
+        #[serde(default)]
+        pub medium: Option<Medium>,
+        #[serde(default)]
+        pub spectral: bool,
+        #[serde(default)]
+        pub integrator: IntegratorKind,
+        #[serde(default)]
+        pub seed: Option<u64>,
+        #[serde(skip)]
+        pub time: f32,
     }
 
     impl Scene {
-        /// Load a scene from a JSON file.
-        pub fn from_json(path: &str) -> std::io::Result<Self> {
-            let file = File::open(path)?;
-            let reader = BufReader::new(file);
+        /// Load a scene from a JSON file, checking that it can be rendered. Errors give the
+        /// file, and where they can be found, the path and position in it of the value at fault.
+        pub fn from_json(path: &str) -> Result<Self, SceneError> {
+            let source =
+                fs::read_to_string(path).map_err(|err| SceneError::from(err).in_file(path))?;
+            let locate = |err: SceneError| err.locate(&source).in_file(path);
+
+            let mut scene: Scene =
+                serde_json::from_str(&source).map_err(|err| locate(err.into()))?;
+            scene.validate().map_err(locate)?;
+
+            let base = Path::new(path).parent().unwrap_or_else(|| Path::new(""));
+            scene.load(base).map_err(|err| locate(err.into()))?;
+            scene
+                .link()
+                .map_err(|err| locate(SceneError::new(SceneErrorKind::Link(err))))?;
+
+            Ok(scene)
+        }
 
-            serde_json::from_reader(reader)
-                .map_err(|_| io::Error::new(io::ErrorKind::Other, "Unable to load JSON."))
+        /// Check that everything in the scene can be rendered, returning an error for the first
+        /// value that cannot, such as a negative scale or a roughness above 1.
+        pub fn validate(&self) -> Result<(), SceneError> {
+            self.camera.validate("camera")?;
+
+            for (i, node) in self.objects.iter().enumerate() {
+                node.validate(&format!("objects[{}]", i))?;
+            }
+            for (name, node) in &self.definitions {
+                node.validate(&format!("definitions.{}", name))?;
+            }
+            for (i, light) in self.lights.iter().enumerate() {
+                light.validate(&format!("lights[{}]", i))?;
+            }
+            if let Some(medium) = &self.medium {
+                medium.validate("medium")?;
+            }
+            self.environment.validate("environment")
+        }
+
+        /// Load any images used by the scene, resolving paths relative to `base`. Definitions
+        /// that are already shared by linked instances are left as they are, so this should be
+        /// called before `link`.
+        pub fn load(&mut self, base: &Path) -> Result<(), ImageError> {
+            self.objects
+                .iter_mut()
+                .try_for_each(|node| node.load(base))?;
+            self.definitions
+                .values_mut()
+                .filter_map(Arc::get_mut)
+                .try_for_each(|node| node.load(base))?;
+
+            self.environment.load(base)
+        }
+
+        /// Resolve every instance in the scene graph against the scene's definitions. This must
+        /// be called after building or modifying a scene, since unresolved instances are not
+        /// rendered.
+        pub fn link(&mut self) -> Result<(), String> {
+            self.definitions = object::link_definitions(std::mem::take(&mut self.definitions))?;
+            self.objects
+                .iter_mut()
+                .try_for_each(|node| node.link(&self.definitions))
+        }
+
+        /// Collect every emissive object in the scene, placed where it is at `time`.
+        pub fn emitters(&self, time: f32) -> Vec<Emitter<'_>> {
+            let mut emitters = Vec::new();
+            for node in &self.objects {
+                node.emitters(time, &Affine::new(&[]), &mut emitters);
+            }
+            emitters
         }
 
         /// Find the closest intersection between a ray and an object in the scene
@@ -171,63 +408,487 @@ pub mod scene {
             ray: Ray,
             tmin: f32,
         ) -> Option<(f32, Intersection, &Object)> {
-            self.objects
-                .iter()
-                .map(|o| (o, o.intersection(ray)))
-                .fold(None, |c, (o, i)| match (&c, i) {
-                    (None, Some((t, int))) if t > tmin => Some((t, int, o)),
-                    (Some((t_c, _, _)), Some((t, int))) if t < *t_c && t > tmin => {
-                        Some((t, int, o))
-                    }
-                    _ => c,
-                })
+            render::count_ray();
+            object::closest(&self.objects, ray, tmin)
         }
 
+        /// Find the light arriving along a camera ray, which starts in the scene's medium.
         pub fn sample(&self, ray: Ray, tmin: f32, bounces: usize) -> Vector3 {
+            self.trace(ray, tmin, bounces, None, self.medium.as_ref())
+        }
+
+        /// Find the light arriving along a ray that travels through `medium`. If the ray was
+        /// sampled from a BSDF or phase function with the pdf `bsdf_pdf`, light from the
+        /// environment is weighted against the direct lighting that was already sampled at the
+        /// ray's origin.
+        pub fn trace(
+            &self,
+            ray: Ray,
+            tmin: f32,
+            bounces: usize,
+            bsdf_pdf: Option<f32>,
+            medium: Option<&Medium>,
+        ) -> Vector3 {
             if bounces == 0 {
-                Vector3::zeros()
-            } else {
-                let closest = self.closest_intersection(ray, tmin);
+                return Vector3::zeros();
+            }
 
-                if let Some((_t, intersection, obj)) = closest {
-                    let position = intersection.position;
-                    let normal = intersection.normal;
+            let hit = self.closest_intersection(ray, tmin);
+
+            // Light may scatter inside the medium before the ray reaches the next surface.
+            let mut weight = Vector3::ones();
+            if let Some(medium) = medium {
+                let tmax = hit.as_ref().map_or(f32::INFINITY, |(t, _, _)| *t);
+                match medium.sample(ray, tmax) {
+                    MediumEvent::Scatter {
+                        distance,
+                        weight: scatter,
+                    } => {
+                        render::count_bounce();
+                        let position = ray.at(distance);
+                        let scattered = medium.lighting(ray, position, self, bounces - 1);
+                        return scatter.cwise_mul(scattered);
+                    }
+                    MediumEvent::Pass { weight: pass } => weight = pass,
+                }
+            }
 
-                    obj.material
-                        .lighting(-ray.direction, position, normal, self, bounces - 1)
-                } else {
-                    Vector3::zeros()
+            let radiance = match hit {
+                Some((_t, intersection, obj)) => {
+                    // Interfaces only change the medium, so the ray carries on unchanged.
+                    if let Material::Interface = obj.material {
+                        let next = self.medium_across(obj, &intersection, medium);
+                        let ray = Ray {
+                            origin: intersection.position,
+                            ..ray
+                        };
+                        return weight.cwise_mul(self.trace(ray, 1.0e-3, bounces, bsdf_pdf, next));
+                    }
+
+                    let across = self.medium_across(obj, &intersection, medium);
+                    let reflected = obj.material.lighting(
+                        ray,
+                        &intersection,
+                        self,
+                        bounces - 1,
+                        medium,
+                        across,
+                    );
+                    ray.upsample(obj.emitted(&intersection, ray.time)) + reflected
+                }
+                None => {
+                    let radiance = ray.upsample(self.environment.radiance(ray.direction));
+                    match bsdf_pdf {
+                        Some(pdf) => {
+                            let light_pdf = self.environment.pdf(ray.direction);
+                            random::power_heuristic(pdf, light_pdf) * radiance
+                        }
+                        None => radiance,
+                    }
+                }
+            };
+
+            weight.cwise_mul(radiance)
+        }
+
+        /// Return the medium on the far side of the surface at `intersection` on `object` for a
+        /// ray that passes through it from `current`. Media are not tracked as a stack, so a
+        /// ray leaving an object's medium is taken to return to the scene's global medium,
+        /// even if the object sits inside another object with a medium. Media therefore should
+        /// not be nested.
+        fn medium_across<'a>(
+            &'a self,
+            object: &'a Object,
+            intersection: &Intersection,
+            current: Option<&'a Medium>,
+        ) -> Option<&'a Medium> {
+            match &object.medium {
+                Some(inside) if intersection.front_face => Some(inside),
+                Some(_) => self.medium.as_ref(),
+                None => current,
+            }
+        }
+
+        /// Return the fraction of light that travels along a `ray` with a unit direction for the
+        /// given `distance` through `medium`. Light passes through interfaces and is blocked by
+        /// any other surface.
+        pub fn transmittance(&self, ray: Ray, distance: f32, medium: Option<&Medium>) -> Vector3 {
+            let mut ray = ray;
+            let mut remaining = distance;
+            let mut medium = medium;
+            let mut tr = Vector3::ones();
+
+            loop {
+                let hit = self
+                    .closest_intersection(ray, 1.0e-3)
+                    .filter(|(t, _, _)| *t < remaining - 1.0e-3);
+                let segment = hit.as_ref().map_or(remaining, |(t, _, _)| *t);
+                if let Some(medium) = medium {
+                    tr = tr.cwise_mul(medium.transmittance(ray, segment));
                 }
+
+                match hit {
+                    Some((t, intersection, obj)) => {
+                        if !matches!(obj.material, Material::Interface) {
+                            return Vector3::zeros();
+                        }
+
+                        medium = self.medium_across(obj, &intersection, medium);
+                        ray.origin = intersection.position;
+                        remaining -= t;
+                    }
+                    None => return tr,
+                }
+            }
+        }
+
+        /// Sample the light arriving directly from the environment and the punctual lights at
+        /// `position` in `medium` and scattered by `scatterer` towards the origin of the `ray`.
+        /// If `mis` is set, the environment sample is weighted against sampling the scatterer,
+        /// since a bounce ray will also be traced.
+        pub fn direct_lighting(
+            &self,
+            scatterer: &Scatterer,
+            ray: Ray,
+            position: Vector3,
+            mis: bool,
+            medium: Option<&Medium>,
+        ) -> Vector3 {
+            let view = -ray.direction;
+            let lights = self
+                .lights
+                .iter()
+                .filter_map(|light| light.sample(position))
+                .map(|sample| {
+                    let f = scatterer.eval(view, sample.direction, ray.time);
+                    if f == Vector3::zeros() {
+                        return Vector3::zeros();
+                    }
+
+                    let shadow = Ray {
+                        origin: position,
+                        direction: sample.direction,
+                        ..ray
+                    };
+                    let tr = self.transmittance(shadow, sample.distance, medium);
+                    ray.upsample(f.cwise_mul(sample.irradiance)).cwise_mul(tr)
+                })
+                .fold(Vector3::zeros(), |a, b| a + b);
+
+            lights + self.environment_lighting(scatterer, ray, position, mis, medium)
+        }
+
+        /// Sample the light arriving directly from the environment.
+        fn environment_lighting(
+            &self,
+            scatterer: &Scatterer,
+            ray: Ray,
+            position: Vector3,
+            mis: bool,
+            medium: Option<&Medium>,
+        ) -> Vector3 {
+            let view = -ray.direction;
+            let sample = match self.environment.sample() {
+                Some(sample) => sample,
+                None => return Vector3::zeros(),
+            };
+
+            let f = scatterer.eval(view, sample.direction, ray.time);
+            if f == Vector3::zeros() {
+                return Vector3::zeros();
+            }
+
+            let shadow = Ray {
+                origin: position,
+                direction: sample.direction,
+                ..ray
+            };
+            let tr = self.transmittance(shadow, f32::INFINITY, medium);
+            if tr == Vector3::zeros() {
+                return Vector3::zeros();
             }
+
+            let weight = if mis {
+                let bsdf_pdf = scatterer.pdf(view, sample.direction);
+                random::power_heuristic(sample.pdf, bsdf_pdf)
+            } else {
+                1.0
+            };
+
+            (weight / sample.pdf) * ray.upsample(f.cwise_mul(sample.radiance)).cwise_mul(tr)
         }
 
+        /// Render the scene with its chosen integrator.
         pub fn render(&self, xres: u32, yres: u32, samples: usize) -> Image {
-            let pixels: Vec<_> = (0..xres * yres)
-                .into_par_iter()
-                .map(|i| (i % xres, i / xres))
-                .map(|(x, y)| {
-                    let ray = self.camera.ray(x, y, xres, yres);
-
-                    let mut color = Vector3::zeros();
-                    for _ in 0..samples {
-                        color = color + self.sample(ray, 0.0, 3)
+            self.render_with_options(xres, yres, samples, &RenderOptions::default())
+                .0
+        }
+
+        /// Render the scene with its chosen integrator, reporting progress and stopping early
+        /// as the `options` ask, and return the image with statistics about the render.
+        pub fn render_with_options(
+            &self,
+            xres: u32,
+            yres: u32,
+            samples: usize,
+            options: &RenderOptions,
+        ) -> (Image, RenderStats) {
+            options.install(|| {
+                let start = Instant::now();
+                let integrator = self.integrator.build(self);
+                let setup = start.elapsed();
+
+                let (image, stats) =
+                    self.render_with(integrator.as_ref(), xres, yres, samples, options);
+                (image, RenderStats { setup, ..stats })
+            })
+        }
+
+        /// Render the scene with the given `integrator`, averaging `samples` per pixel over the
+        /// region chosen in the `options`, or the whole image if there is none. Tiles are
+        /// taken in order by one worker per thread and written into the image as they finish,
+        /// and once the `options` are cancelled the remaining tiles are left black. Light that
+        /// the integrator splats onto the film is averaged over every camera ray cast and added
+        /// to the region before the image is converted to sRGB.
+        pub fn render_with(
+            &self,
+            integrator: &dyn Integrator,
+            xres: u32,
+            yres: u32,
+            samples: usize,
+            options: &RenderOptions,
+        ) -> (Image, RenderStats) {
+            let start = Instant::now();
+            let spectral = self.spectral && integrator.is_spectral();
+
+            // Only the pixels in the region are rendered, each with the same ray it gets in a
+            // full render, and the image either covers just the region or the whole frame.
+            let full = Tile {
+                x: 0,
+                y: 0,
+                width: xres,
+                height: yres,
+            };
+            let window = options
+                .region
+                .map_or(full, |region| region.window(xres, yres));
+            let frame = if options.crop { window } else { full };
+            let tiles: Vec<_> = render::tiles(
+                window.width,
+                window.height,
+                options.tile_size,
+                options.order,
+            )
+            .into_iter()
+            .map(|tile| Tile {
+                x: window.x + tile.x,
+                y: window.y + tile.y,
+                ..tile
+            })
+            .collect();
+
+            let next = AtomicUsize::new(0);
+            let completed = AtomicUsize::new(0);
+            let framebuffer = Mutex::new(vec![
+                Vector3::zeros();
+                (frame.width * frame.height) as usize
+            ]);
+            let stats = Mutex::new(RenderStats::default());
+            let film = Film::new(xres, yres, window);
+
+            let worker = || {
+                while !options.cancel.is_cancelled() {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(tile) = tiles.get(index) else {
+                        return;
+                    };
+                    if let Some(seed) = self.seed {
+                        random::reseed(seed, index as u64);
                     }
 
-                    let srgb_gamma = |u: f32, _| {
-                        if u < 0.0031308 {
-                            12.92 * u
-                        } else {
-                            1.055 * u.powf(1.0 / 2.4) - 0.055
+                    let before = render::counters();
+                    let pixels: Vec<_> = (tile.y..tile.y + tile.height)
+                        .flat_map(|y| (tile.x..tile.x + tile.width).map(move |x| (x, y)))
+                        .map(|(x, y)| {
+                            self.render_pixel(
+                                integrator, &film, x, y, xres, yres, samples, spectral,
+                            )
+                        })
+                        .collect();
+                    let after = render::counters();
+
+                    {
+                        let mut framebuffer = framebuffer.lock().unwrap();
+                        for (row, pixels) in pixels.chunks(tile.width as usize).enumerate() {
+                            let y = tile.y + row as u32 - frame.y;
+                            let offset = (y * frame.width + tile.x - frame.x) as usize;
+                            framebuffer[offset..offset + pixels.len()].copy_from_slice(pixels);
                         }
+                    }
+                    {
+                        let mut stats = stats.lock().unwrap();
+                        stats.rays += after.0 - before.0;
+                        stats.bounces += after.1 - before.1;
+                        stats.camera_rays += pixels.len() as u64 * samples as u64;
+                    }
+
+                    let done = completed.fetch_add(1, Ordering::Relaxed) + 1;
+                    if let Some(progress) = &options.progress {
+                        progress(&Progress {
+                            completed: done,
+                            total: tiles.len(),
+                            elapsed: start.elapsed(),
+                        });
+                    }
+                }
+            };
+            rayon::scope(|scope| {
+                for _ in 0..rayon::current_num_threads() {
+                    scope.spawn(|_| worker());
+                }
+            });
+
+            let stats = RenderStats {
+                render: start.elapsed(),
+                cancelled: completed.into_inner() < tiles.len(),
+                ..stats.into_inner().unwrap()
+            };
+
+            // Each camera ray traced one path for the film, wherever its splats landed.
+            let mut pixels = framebuffer.into_inner().unwrap();
+            let scale = 1.0 / stats.camera_rays.max(1) as f32;
+            for y in window.y..window.y + window.height {
+                for x in window.x..window.x + window.width {
+                    let offset = ((y - frame.y) * frame.width + x - frame.x) as usize;
+                    pixels[offset] = pixels[offset] + film.get(x, y) * scale;
+                }
+            }
+            for pixel in &mut pixels {
+                let color = pixel.cwise(Vector3::ones(), |u, _| color::linear_to_srgb(u));
+                *pixel = color.cwise(Vector3::ones(), f32::min);
+            }
+            (Image::new(pixels, frame.width, frame.height), stats)
+        }
+
+        /// Render the pixel at (`x`, `y`) with `integrator`, returning its color in linear sRGB
+        /// and splatting any light found for other pixels onto the `film`.
+        #[allow(clippy::too_many_arguments)]
+        fn render_pixel(
+            &self,
+            integrator: &dyn Integrator,
+            film: &Film,
+            x: u32,
+            y: u32,
+            xres: u32,
+            yres: u32,
+            samples: usize,
+            spectral: bool,
+        ) -> Vector3 {
+            let mut color = Vector3::zeros();
+            for _ in 0..samples {
+                // Each sample gets its own ray so that it is cast at its own time and carries its
+                // own wavelengths.
+                let mut ray = self.camera.ray(x, y, xres, yres, self.time);
+                ray.wavelengths = spectral.then(Wavelengths::sample);
+
+                let radiance = integrator.radiance_with_film(self, ray, film);
+                color = color
+                    + match &ray.wavelengths {
+                        Some(wavelengths) => wavelengths.to_rgb(radiance),
+                        None => radiance,
                     };
+            }
 
-                    color = (1.0 / samples as f32) * color;
-                    color = color.cwise(Vector3::ones(), srgb_gamma);
-                    color.cwise(Vector3::ones(), f32::min)
-                })
-                .collect();
+            (1.0 / samples as f32) * color
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::scene::Scene;
+    use super::*;
+
+    #[test]
+    fn test_shutter() {
+        let mut camera: Camera =
+            serde_json::from_str(r#"{"focal_len": 1.0, "width": 1.0}"#).unwrap();
+        camera.shutter_open = 0.25;
+        camera.shutter_close = 0.75;
+
+        random::reseed(1, 0);
+        let times: Vec<f32> = (0..1000)
+            .map(|_| camera.ray(0, 0, 1, 1, 2.0).time)
+            .collect();
+        let min = times.iter().copied().fold(f32::INFINITY, f32::min);
+        let max = times.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+        let mean = times.iter().sum::<f32>() / times.len() as f32;
+        assert!(
+            (2.25..2.3).contains(&min) && (2.7..2.75).contains(&max) && (mean - 2.5).abs() < 0.02,
+            "Camera::ray() failed on a shutter open from 2.25 to 2.75. Expected times spread \
+             over it, got times from {} to {} with mean {}.",
+            min,
+            max,
+            mean
+        );
+
+        camera.shutter_close = camera.shutter_open;
+        let time = camera.ray(0, 0, 1, 1, 2.0).time;
+        assert!(
+            time == 2.25,
+            "Camera::ray() failed on an instant shutter. Expected 2.25, got {}.",
+            time
+        );
+    }
 
-            Image::new(pixels, xres, yres)
+    #[test]
+    fn test_motion_blur() {
+        // A ball of radius 0.25 crosses the view from x = -2 to x = 2 during the frame, so the
+        // central ray only hits it for an eighth of a shutter that is open all frame.
+        let mut scene: Scene = serde_json::from_str(
+            r#"{
+                "camera": {"focal_len": 1.0, "width": 0.01},
+                "objects": [{
+                    "object": "Sphere",
+                    "material": {"Diffuse": {"color": {"x": 0.5, "y": 0.5, "z": 0.5}}},
+                    "transforms": [
+                        {"Scale": {"x": 0.25, "y": 0.25, "z": 0.25}},
+                        {"Keyframes": [
+                            {"time": 0.0, "value": {"Translate": {"x": -2.0, "y": 0.0, "z": 5.0}}},
+                            {"time": 1.0, "value": {"Translate": {"x": 2.0, "y": 0.0, "z": 5.0}}}
+                        ]}
+                    ]
+                }]
+            }"#,
+        )
+        .unwrap();
+        scene.link().unwrap();
+
+        random::reseed(1, 0);
+        let coverage = |scene: &Scene| {
+            let n = 4000;
+            let hits = (0..n)
+                .filter(|_| {
+                    let ray = scene.camera.ray(0, 0, 1, 1, scene.time);
+                    scene.closest_intersection(ray, 0.0).is_some()
+                })
+                .count();
+            hits as f32 / n as f32
+        };
+
+        for (open, close, expected) in [(0.0, 1.0, 0.125), (0.0, 0.0, 0.0), (0.5, 0.5, 1.0)] {
+            scene.camera.shutter_open = open;
+            scene.camera.shutter_close = close;
+            let covered = coverage(&scene);
+            assert!(
+                (covered - expected).abs() < 0.02,
+                "Camera::ray() failed on a shutter open from {} to {}. Expected the moving \
+                 ball to cover {} of the pixel, got {}.",
+                open,
+                close,
+                expected,
+                covered
+            );
         }
     }
 }
@@ -0,0 +1,288 @@
+use serde::{Deserialize, Serialize};
+
+use std::f32::consts::{PI, TAU};
+
+use crate::error::{self, SceneError};
+use crate::random;
+use crate::transform::deserialize_angle;
+use crate::vector::Vector3;
+
+/// An enum with punctual light sources that are not part of any surface, so they can only be
+/// reached by sampling them directly. Available lights are:
+/// - Point: Emits light of the given `color` and `intensity` equally in all directions from
+///   `position`, dimming with distance according to `falloff`.
+/// - Spot: A point light that only shines within `angle` of its `direction`, fading out over the
+///   outermost `soft_edge` of the cone.
+/// - Directional: A light infinitely far away in the direction `direction`, such as the sun,
+///   that gives `intensity` irradiance to a surface facing it. A non-zero `angular_diameter`
+///   spreads the light over a disk in the sky, which softens shadows.
+///
+/// Angles are in radians, or in degrees when written as `{"degrees": 30.0}` in JSON.
+#[derive(Serialize, Deserialize)]
+pub enum Light {
+    Point {
+        position: Vector3,
+        color: Vector3,
+        intensity: f32,
+        #[serde(default)]
+        falloff: Falloff,
+    },
+    Spot {
+        position: Vector3,
+        direction: Vector3,
+        color: Vector3,
+        intensity: f32,
+        #[serde(deserialize_with = "deserialize_angle")]
+        angle: f32,
+        #[serde(default, deserialize_with = "deserialize_angle")]
+        soft_edge: f32,
+        #[serde(default)]
+        falloff: Falloff,
+    },
+    Directional {
+        direction: Vector3,
+        color: Vector3,
+        intensity: f32,
+        #[serde(default, deserialize_with = "deserialize_angle")]
+        angular_diameter: f32,
+    },
+}
+
+/// How the light from a point or spot light dims with the distance `d` from the light.
+/// - InverseSquare: Physically based `1 / d^2` falloff.
+/// - Linear: `1 / d` falloff.
+/// - None: No falloff.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum Falloff {
+    #[default]
+    InverseSquare,
+    Linear,
+    None,
+}
+
+impl Falloff {
+    /// Return the factor by which light dims at `distance` from the light.
+    fn attenuation(self, distance: f32) -> f32 {
+        match self {
+            Falloff::InverseSquare => 1.0 / (distance * distance),
+            Falloff::Linear => 1.0 / distance,
+            Falloff::None => 1.0,
+        }
+    }
+}
+
+/// A direction sampled towards a light from a point in the scene, together with the distance to
+/// the light and the irradiance it gives to a surface facing it.
+pub struct LightSample {
+    pub direction: Vector3,
+    pub distance: f32,
+    pub irradiance: Vector3,
+}
+
+impl Light {
+    /// Check that the color and intensity of this light at `path` are finite, that its
+    /// direction has a length, and that its angles are between 0 and π.
+    pub fn validate(&self, path: &str) -> Result<(), SceneError> {
+        let (name, color, intensity) = match self {
+            Light::Point {
+                color, intensity, ..
+            } => ("Point", color, intensity),
+            Light::Spot {
+                color, intensity, ..
+            } => ("Spot", color, intensity),
+            Light::Directional {
+                color, intensity, ..
+            } => ("Directional", color, intensity),
+        };
+        let path = format!("{}.{}", path, name);
+        error::check_color(&format!("{}.color", path), *color)?;
+        if !(intensity.is_finite() && *intensity >= 0.0) {
+            return Err(SceneError::invalid(
+                format!("{}.intensity", path),
+                format!("intensity {} must be finite and not negative", intensity),
+            ));
+        }
+
+        let check_direction = |direction: &Vector3| {
+            if direction.norm().is_normal() {
+                Ok(())
+            } else {
+                Err(SceneError::invalid(
+                    format!("{}.direction", path),
+                    format!(
+                        "direction {} must have a finite, non-zero length",
+                        direction
+                    ),
+                ))
+            }
+        };
+        let check_angle = |field: &str, angle: f32| {
+            if (0.0..=PI).contains(&angle) {
+                Ok(())
+            } else {
+                Err(SceneError::invalid(
+                    format!("{}.{}", path, field),
+                    format!("{} {} must be between 0 and π radians", field, angle),
+                ))
+            }
+        };
+        match self {
+            Light::Point { .. } => Ok(()),
+            Light::Spot {
+                direction,
+                angle,
+                soft_edge,
+                ..
+            } => {
+                check_direction(direction)?;
+                check_angle("angle", *angle)?;
+                check_angle("soft_edge", *soft_edge)
+            }
+            Light::Directional {
+                direction,
+                angular_diameter,
+                ..
+            } => {
+                check_direction(direction)?;
+                check_angle("angular_diameter", *angular_diameter)
+            }
+        }
+    }
+
+    /// Sample the light arriving at `position` from this light. Returns `None` if the light
+    /// does not reach `position`.
+    pub fn sample(&self, position: Vector3) -> Option<LightSample> {
+        match self {
+            Light::Point {
+                position: light,
+                color,
+                intensity,
+                falloff,
+            } => {
+                let (direction, distance) = towards(position, *light)?;
+                Some(LightSample {
+                    direction,
+                    distance,
+                    irradiance: (intensity * falloff.attenuation(distance)) * color,
+                })
+            }
+            Light::Spot {
+                position: light,
+                direction: axis,
+                color,
+                intensity,
+                angle,
+                soft_edge,
+                falloff,
+            } => {
+                let (direction, distance) = towards(position, *light)?;
+
+                // Fade smoothly from the inner cone to the outer cone.
+                let cos_theta = -direction.dot(axis.normalized());
+                let cos_outer = angle.cos();
+                let cos_inner = (angle - soft_edge).max(0.0).cos();
+                let cone = if cos_theta >= cos_inner {
+                    1.0
+                } else if cos_theta <= cos_outer {
+                    return None;
+                } else {
+                    let t = (cos_theta - cos_outer) / (cos_inner - cos_outer);
+                    t * t * (3.0 - 2.0 * t)
+                };
+
+                Some(LightSample {
+                    direction,
+                    distance,
+                    irradiance: (intensity * falloff.attenuation(distance) * cone) * color,
+                })
+            }
+            Light::Directional {
+                direction,
+                color,
+                intensity,
+                angular_diameter,
+            } => {
+                let axis = direction.normalized();
+                let direction = if *angular_diameter > 0.0 {
+                    // Sample the disk of the light uniformly by solid angle.
+                    let cos_max = (angular_diameter / 2.0).cos();
+                    let cos_theta = 1.0 - random::uniform() * (1.0 - cos_max);
+                    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+                    let phi = TAU * random::uniform();
+                    let (tangent, bitangent) = axis.basis();
+
+                    tangent * (sin_theta * phi.cos())
+                        + bitangent * (sin_theta * phi.sin())
+                        + axis * cos_theta
+                } else {
+                    axis
+                };
+
+                Some(LightSample {
+                    direction,
+                    distance: f32::INFINITY,
+                    irradiance: *intensity * color,
+                })
+            }
+        }
+    }
+}
+
+/// Return the unit direction and distance from `position` to `light`, or `None` if they
+/// coincide.
+fn towards(position: Vector3, light: Vector3) -> Option<(Vector3, f32)> {
+    let delta = light - position;
+    let distance = delta.norm();
+    if distance > 0.0 {
+        Some((delta * (1.0 / distance), distance))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sample() {
+        let color = Vector3::new(1.0, 1.0, 1.0);
+        let point = Light::Point {
+            position: Vector3::new(0.0, 2.0, 0.0),
+            color,
+            intensity: 8.0,
+            falloff: Falloff::InverseSquare,
+        };
+        let sample = point
+            .sample(Vector3::zeros())
+            .expect("Point light should reach.");
+        let expected = Vector3::new(2.0, 2.0, 2.0);
+        assert!(
+            sample.distance == 2.0 && sample.irradiance == expected,
+            "Light::sample() failed on a point light. Expected {}, got {}.",
+            expected,
+            sample.irradiance
+        );
+
+        let spot = Light::Spot {
+            position: Vector3::new(0.0, 1.0, 0.0),
+            direction: Vector3::new(0.0, -1.0, 0.0),
+            color,
+            intensity: 1.0,
+            angle: 0.5,
+            soft_edge: 0.2,
+            falloff: Falloff::None,
+        };
+        let center = spot.sample(Vector3::zeros()).map(|s| s.irradiance.x());
+        let edge = spot
+            .sample(Vector3::new(0.4f32.tan(), 0.0, 0.0))
+            .map(|s| s.irradiance.x());
+        let outside = spot.sample(Vector3::new(1.0, 0.0, 0.0));
+        assert!(
+            center == Some(1.0) && edge.is_some_and(|e| e > 0.0 && e < 1.0) && outside.is_none(),
+            "Light::sample() failed on a spot light. Got {:?} at the center and {:?} at the edge.",
+            center,
+            edge
+        );
+    }
+}
@@ -1,29 +1,607 @@
+use raytracer::compare::{self, Metric};
+use raytracer::image::Image;
+use raytracer::integrator::IntegratorKind;
+use raytracer::render::{Progress, Region, RenderOptions, TileOrder};
 use raytracer::scene::Scene;
+use raytracer::Camera;
+
+use serde_json::Value;
+
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::io::{self, Write};
+use std::process::ExitCode;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
 use std::{env, path::Path};
 
-const USAGE_STRING: &'static str =
-    "Usage: raytracer scene_file output_file [xres] [yres] [samples]";
+const USAGE_STRING: &str = "\
+Usage: raytracer <command> [options]
+
+Commands:
+  render <scene> <output>     Render a scene to a PNG image. This is the default command.
+  validate <scene>...         Check that scene files load.
+  info <scene>                Describe what a scene contains.
+  diff <reference> <test>     Compare a PNG, HDR or EXR image against a reference.
+  help                        Show this message.
+
+Render options:
+  --width <pixels>            Width of the image (default 500).
+  --height <pixels>           Height of the image (default 500).
+  --spp <samples>             Samples per pixel (default 500).
+  --depth <bounces>           Maximum path depth of the integrator.
+  --seed <seed>               Seed the random numbers so that renders are repeatable.
+  --threads <count>           Threads to render on (default one per core).
+  --integrator <name>         One of path, bdpt, photons, whitted, ao, direct, normals, depth,
+                              uv and bounces, overriding the integrator in the scene file.
+  --frames <first>[..<last>]  Frame or range of frames to render. A range needs a numbered
+                              output file such as out_%04d.png.
+  --tile-size <pixels>        Size of the square tiles the image is rendered in (default 32).
+  --tile-order <order>        One of spiral (default), hilbert and scanline.
+  --region <x,y,w,h><unit>    Render only part of the image, given in pixels such as
+                              16,16,64,32px or in percent such as 25,25,50,50%, leaving the
+                              rest black.
+  --crop                      Write only the region rather than the full image.
+  --camera.<field> <value>    Override a camera field with a JSON value, such as
+                              --camera.focal_len 50.
+
+Diff options:
+  --output <path>             Where to write a false-color image of the error (default
+                              diff.png).
+  --metric <name>             One of flip (default), mse, relmse, psnr and ssim, checked
+                              against the threshold.
+  --threshold <value>         Fail if the metric is worse than this, which is below it for
+                              psnr and ssim and above it for the others.";
+
+/// An error from the command line, which is either a mistake in the arguments or a failure
+/// while carrying out the command.
+#[derive(Debug)]
+enum CliError {
+    Usage(String),
+    Failed(String),
+}
+
+impl Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CliError::Usage(message) | CliError::Failed(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+/// The arguments of a command: its positional arguments, the options given with a value and
+/// the flags given without one, in the order they appeared.
+struct Arguments {
+    positional: Vec<String>,
+    options: Vec<(String, String)>,
+    flags: Vec<String>,
+}
+
+/// Options that take no value.
+const FLAGS: [&str; 2] = ["--crop", "--help"];
+
+impl Arguments {
+    /// Split the arguments after the command name into positional arguments and options,
+    /// which are given either as `--name value` or `--name=value`.
+    fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, CliError> {
+        let mut arguments = Arguments {
+            positional: Vec::new(),
+            options: Vec::new(),
+            flags: Vec::new(),
+        };
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            if arg == "-h" || FLAGS.contains(&arg.as_str()) {
+                arguments.flags.push(arg);
+            } else if let Some((name, value)) = arg.split_once('=').filter(|_| is_option(&arg)) {
+                arguments
+                    .options
+                    .push((name.to_string(), value.to_string()));
+            } else if is_option(&arg) {
+                let value = args
+                    .next()
+                    .ok_or_else(|| CliError::Usage(format!("Missing value for {}.", arg)))?;
+                arguments.options.push((arg, value));
+            } else {
+                arguments.positional.push(arg);
+            }
+        }
+
+        Ok(arguments)
+    }
+
+    /// Return whether the flag `name` was given.
+    fn flag(&self, name: &str) -> bool {
+        self.flags.iter().any(|flag| flag == name)
+    }
+
+    /// Remove the option `name` and parse its value, using the last one if it was given more
+    /// than once.
+    fn take<T>(&mut self, name: &str) -> Result<Option<T>, CliError>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        let mut value = None;
+        self.options.retain(|(option, v)| {
+            if option == name {
+                value = Some(v.clone());
+            }
+            option != name
+        });
+
+        value
+            .map(|value| {
+                value.parse().map_err(|err| {
+                    CliError::Usage(format!("Invalid value '{}' for {}: {}", value, name, err))
+                })
+            })
+            .transpose()
+    }
+
+    /// Fail if any options remain that the command does not understand.
+    fn finish(&self) -> Result<(), CliError> {
+        match self.options.first() {
+            Some((name, _)) => Err(CliError::Usage(format!("Unknown option {}.", name))),
+            None => Ok(()),
+        }
+    }
+}
+
+fn is_option(arg: &str) -> bool {
+    arg.starts_with("--") && arg.len() > 2
+}
+
+/// A frame number or an inclusive range of them, given as `first..last`.
+struct Frames(i64, i64);
+
+impl FromStr for Frames {
+    type Err = String;
+
+    fn from_str(frames: &str) -> Result<Self, Self::Err> {
+        let parse = |frame: &str| {
+            frame
+                .trim()
+                .parse()
+                .map_err(|_| format!("expected a frame number, got '{}'", frame))
+        };
+        match frames.split_once("..") {
+            Some((first, last)) => {
+                let (first, last) = (parse(first)?, parse(last)?);
+                if last < first {
+                    return Err(format!("the last frame {} is before the first", last));
+                }
+                Ok(Frames(first, last))
+            }
+            None => parse(frames).map(|frame| Frames(frame, frame)),
+        }
+    }
+}
+
+/// Substitute the frame number into a `printf`-style pattern such as `out_%04d.png`, returning
+/// `None` if the pattern has no frame number.
+fn frame_path(pattern: &str, frame: i64) -> Option<String> {
+    let start = pattern.find('%')?;
+    let rest = &pattern[start + 1..];
+    let end = rest.find('d')?;
+    let spec = &rest[..end];
+
+    let number = if spec.is_empty() {
+        frame.to_string()
+    } else if spec.starts_with('0') {
+        let width: usize = spec.parse().ok()?;
+        format!("{:0width$}", frame, width = width)
+    } else {
+        let width: usize = spec.parse().ok()?;
+        format!("{:width$}", frame, width = width)
+    };
+
+    Some(format!(
+        "{}{}{}",
+        &pattern[..start],
+        number,
+        &rest[end + 1..]
+    ))
+}
+
+/// Format a duration in minutes and seconds, such as `3m 07s`.
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    format!("{}m {:02}s", seconds / 60, seconds % 60)
+}
+
+/// Draw a progress bar over the current line of standard error.
+fn print_progress(progress: &Progress) {
+    const WIDTH: usize = 40;
+    let filled = (progress.fraction() * WIDTH as f32) as usize;
+    let eta = progress
+        .eta()
+        .map_or_else(|| "?".to_string(), format_duration);
+
+    let mut stderr = io::stderr().lock();
+    let _ = write!(
+        stderr,
+        "\r[{}{}] {:3.0}% {}/{} tiles, ETA {}",
+        "#".repeat(filled),
+        " ".repeat(WIDTH - filled),
+        100.0 * progress.fraction(),
+        progress.completed,
+        progress.total,
+        eta
+    );
+    let _ = stderr.flush();
+}
+
+fn load_scene(path: &str) -> Result<Scene, CliError> {
+    Scene::from_json(path).map_err(|err| CliError::Failed(err.to_string()))
+}
+
+/// Set the camera field at the dotted `path`, such as `focal_len`, to `value`, which is read as
+/// JSON if it can be and as a string otherwise.
+fn override_camera(camera: &mut Camera, path: &str, value: &str) -> Result<(), CliError> {
+    let error = |message: String| {
+        CliError::Usage(format!(
+            "Invalid value '{}' for --camera.{}: {}",
+            value, path, message
+        ))
+    };
 
-fn main() -> Result<(), &'static str> {
-    // TODO: Implement main method.
-    let args: Vec<String> = env::args().collect();
+    let mut json = serde_json::to_value(&*camera).map_err(|err| error(err.to_string()))?;
+    let mut field = &mut json;
+    for key in path.split('.') {
+        field = field
+            .as_object_mut()
+            .ok_or_else(|| error(format!("{} is not an object", key)))?
+            .entry(key)
+            .or_insert(Value::Null);
+    }
+    *field = serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.to_string()));
 
-    if args.len() < 3 {
-        return Err(USAGE_STRING)
-    } 
+    let overridden: Camera = serde_json::from_value(json).map_err(|err| error(err.to_string()))?;
+    // The scene was validated as it was loaded, so the overridden camera must be checked again.
+    overridden
+        .validate("camera")
+        .map_err(|err| error(err.to_string()))?;
+    *camera = overridden;
+    Ok(())
+}
+
+fn render(mut args: Arguments) -> Result<(), CliError> {
+    let width: u32 = args.take("--width")?.unwrap_or(500);
+    let height: u32 = args.take("--height")?.unwrap_or(500);
+    let samples: usize = args.take("--spp")?.unwrap_or(500);
+    let depth: Option<usize> = args.take("--depth")?;
+    let seed: Option<u64> = args.take("--seed")?;
+    let threads: Option<usize> = args.take("--threads")?;
+    let integrator: Option<IntegratorKind> = args.take("--integrator")?;
+    let Frames(first_frame, last_frame) = args.take("--frames")?.unwrap_or(Frames(0, 0));
+    let tile_size: Option<u32> = args.take("--tile-size")?;
+    let order: Option<TileOrder> = args.take("--tile-order")?;
+    let region: Option<Region> = args.take("--region")?;
+
+    let camera: Vec<_> = args
+        .options
+        .iter()
+        .filter_map(|(name, value)| Some((name.strip_prefix("--camera.")?, value.as_str())))
+        .map(|(path, value)| (path.to_string(), value.to_string()))
+        .collect();
+    args.options
+        .retain(|(name, _)| !name.starts_with("--camera."));
+    args.finish()?;
+
+    let [scene_path, output_path] = &args.positional[..] else {
+        return Err(CliError::Usage(
+            "render takes a scene file and an output file.".to_string(),
+        ));
+    };
+    if width == 0 || height == 0 {
+        return Err(CliError::Usage(format!(
+            "Image size {}x{} is empty.",
+            width, height
+        )));
+    }
+    if last_frame > first_frame && frame_path(output_path, first_frame).is_none() {
+        return Err(CliError::Usage(format!(
+            "Rendering frames {} to {} needs a numbered output file such as out_%04d.png, got {}.",
+            first_frame, last_frame, output_path
+        )));
+    }
+
+    let mut scene = load_scene(scene_path)?;
+    for (path, value) in &camera {
+        override_camera(&mut scene.camera, path, value)?;
+    }
+    if let Some(integrator) = integrator {
+        scene.integrator = integrator;
+    }
+    if let Some(depth) = depth {
+        scene.integrator = scene.integrator.with_max_depth(depth);
+    }
+    if seed.is_some() {
+        scene.seed = seed;
+    }
+
+    let defaults = RenderOptions::default();
+    let options = RenderOptions {
+        region,
+        crop: args.flag("--crop"),
+        tile_size: tile_size.unwrap_or(defaults.tile_size),
+        order: order.unwrap_or(defaults.order),
+        threads,
+        progress: Some(Box::new(print_progress)),
+        ..defaults
+    };
+
+    for frame in first_frame..=last_frame {
+        let path = frame_path(output_path, frame).unwrap_or_else(|| output_path.clone());
+
+        scene.time = frame as f32;
+        let (image, stats) = scene.render_with_options(width, height, samples, &options);
+        // Finish the line of the progress bar.
+        eprintln!();
+
+        let start = Instant::now();
+        image.save(Path::new(&path)).map_err(|err| {
+            CliError::Failed(format!("Unable to save the image: {}", describe(&err)))
+        })?;
+        eprintln!(
+            "Wrote {} in {:.2}s\n{}",
+            path,
+            start.elapsed().as_secs_f64(),
+            stats
+        );
+    }
+
+    Ok(())
+}
+
+fn validate(args: Arguments) -> Result<(), CliError> {
+    args.finish()?;
+    if args.positional.is_empty() {
+        return Err(CliError::Usage(
+            "validate takes one or more scene files.".to_string(),
+        ));
+    }
+
+    let mut failed = 0;
+    for path in &args.positional {
+        match load_scene(path) {
+            Ok(_) => println!("{}: ok", path),
+            Err(err) => {
+                println!("{}", err);
+                failed += 1;
+            }
+        }
+    }
+
+    match failed {
+        0 => Ok(()),
+        _ => Err(CliError::Failed(format!(
+            "{} of {} scenes failed to load.",
+            failed,
+            args.positional.len()
+        ))),
+    }
+}
+
+/// Describe an error followed by the errors that caused it, which std errors only give through
+/// `source()`.
+fn describe(err: &dyn Error) -> String {
+    let mut message = err.to_string();
+    let mut source = err.source();
+    while let Some(err) = source {
+        message = format!("{}: {}", message, err);
+        source = err.source();
+    }
+    message
+}
 
-    let scene_path = &args[1];
-    let output_path = &args[2];
+/// Load an image to compare, converting HDR and EXR images from linear values to sRGB.
+fn load_image(path: &str) -> Result<Image, CliError> {
+    let image = Image::load(path).map_err(|err| CliError::Failed(describe(&err)))?;
+    let path = path.to_ascii_lowercase();
+    if path.ends_with(".hdr") || path.ends_with(".exr") {
+        Ok(image.to_srgb())
+    } else {
+        Ok(image)
+    }
+}
+
+fn diff(mut args: Arguments) -> Result<(), CliError> {
+    let output: String = args.take("--output")?.unwrap_or("diff.png".to_string());
+    let metric: Metric = args.take("--metric")?.unwrap_or(Metric::Flip);
+    let threshold: Option<f32> = args.take("--threshold")?;
+    args.finish()?;
+
+    let [reference_path, test_path] = &args.positional[..] else {
+        return Err(CliError::Usage(
+            "diff takes a reference image and a test image.".to_string(),
+        ));
+    };
+    let (reference, test) = (load_image(reference_path)?, load_image(test_path)?);
+    if (reference.width(), reference.height()) != (test.width(), test.height()) {
+        return Err(CliError::Failed(format!(
+            "{} is {}x{} pixels but {} is {}x{}.",
+            reference_path,
+            reference.width(),
+            reference.height(),
+            test_path,
+            test.width(),
+            test.height()
+        )));
+    }
+
+    let comparison = compare::compare(&reference, &test);
+    println!("{}", comparison);
+    comparison
+        .false_color()
+        .save(Path::new(&output))
+        .map_err(|err| CliError::Failed(format!("Unable to save the image: {}", describe(&err))))?;
+
+    match threshold {
+        Some(threshold) if comparison.exceeds(metric, threshold) => Err(CliError::Failed(format!(
+            "{} of {} is worse than the threshold {}.",
+            metric,
+            comparison.get(metric),
+            threshold
+        ))),
+        _ => Ok(()),
+    }
+}
 
-    let x_res = args.get(3).and_then(|s| s.parse().ok()).unwrap_or(500);
-    let y_res = args.get(4).and_then(|s| s.parse().ok()).unwrap_or(500);
-    let samples = args.get(5).and_then(|s| s.parse().ok()).unwrap_or(500);
+/// Return the name of the variant of an enum serialized as JSON, such as `Constant`.
+fn variant_name(value: Value) -> String {
+    match value {
+        Value::String(name) => name,
+        Value::Object(map) => map.keys().next().cloned().unwrap_or_default(),
+        _ => "?".to_string(),
+    }
+}
 
-    let scene = Scene::from_json(scene_path).map_err(|_| USAGE_STRING)?;
+fn info(args: Arguments) -> Result<(), CliError> {
+    args.finish()?;
+    let [path] = &args.positional[..] else {
+        return Err(CliError::Usage("info takes one scene file.".to_string()));
+    };
 
-    let image = scene.render(x_res, y_res, samples);
+    let scene = load_scene(path)?;
+    let camera = &scene.camera;
+    let environment =
+        serde_json::to_value(&scene.environment).map_or_else(|_| "?".to_string(), variant_name);
 
-    image.save(Path::new(output_path)).map_err(|_| USAGE_STRING)?;
+    println!("{}", path);
+    println!(
+        "  camera: focal length {}, width {}, shutter {} to {}",
+        camera.focal_len.at(0.0),
+        camera.width.at(0.0),
+        camera.shutter_open,
+        camera.shutter_close
+    );
+    println!(
+        "  objects: {} top-level nodes, {} definitions, {} emitters",
+        scene.objects.len(),
+        scene.definitions.len(),
+        scene.emitters(scene.time).len()
+    );
+    println!("  lights: {}", scene.lights.len());
+    println!("  environment: {}", environment);
+    println!(
+        "  medium: {}",
+        if scene.medium.is_some() { "yes" } else { "no" }
+    );
+    println!("  spectral: {}", scene.spectral);
+    println!("  integrator: {}", scene.integrator.name());
+    match scene.seed {
+        Some(seed) => println!("  seed: {}", seed),
+        None => println!("  seed: none"),
+    }
 
     Ok(())
 }
+
+fn run(args: Vec<String>) -> Result<(), CliError> {
+    let mut args = args.into_iter().skip(1).peekable();
+    let command = match args.peek().map(String::as_str) {
+        Some("render" | "validate" | "info" | "diff" | "help") => args.next(),
+        Some(_) => None,
+        None => Some("help".to_string()),
+    };
+
+    let arguments = Arguments::parse(args)?;
+    if arguments.flag("--help") || arguments.flag("-h") {
+        println!("{}", USAGE_STRING);
+        return Ok(());
+    }
+
+    match command.as_deref() {
+        Some("validate") => validate(arguments),
+        Some("info") => info(arguments),
+        Some("diff") => diff(arguments),
+        Some("help") => {
+            println!("{}", USAGE_STRING);
+            Ok(())
+        }
+        _ => render(arguments),
+    }
+}
+
+fn main() -> ExitCode {
+    match run(env::args().collect()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(CliError::Usage(message)) => {
+            eprintln!("error: {}\n\n{}", message, USAGE_STRING);
+            ExitCode::from(2)
+        }
+        Err(CliError::Failed(message)) => {
+            eprintln!("error: {}", message);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_arguments() {
+        let args = [
+            "scene.json",
+            "--spp=16",
+            "--crop",
+            "--camera.focal_len",
+            "50",
+            "out.png",
+        ];
+        let mut arguments = Arguments::parse(args.map(String::from)).ok().unwrap();
+
+        let spp: Option<usize> = arguments.take("--spp").ok().unwrap();
+        let expected = vec!["scene.json".to_string(), "out.png".to_string()];
+        assert!(
+            spp == Some(16)
+                && arguments.flag("--crop")
+                && arguments.positional == expected
+                && arguments.options == [("--camera.focal_len".to_string(), "50".to_string())],
+            "Arguments::parse() failed on {:?}. Expected 16 samples, --crop, {:?} and a camera \
+             override, got {:?}, {:?}, {:?} and {:?}.",
+            args,
+            expected,
+            spp,
+            arguments.flags,
+            arguments.positional,
+            arguments.options
+        );
+    }
+
+    #[test]
+    fn test_override_camera() {
+        let mut camera: Camera =
+            serde_json::from_str(r#"{"focal_len": 1.0, "width": 1.0}"#).unwrap();
+        for (path, value) in [
+            ("focal_len", "-1"),
+            ("width", "0"),
+            (
+                "transforms",
+                r#"[{"Scale": {"x": 0.0, "y": 1.0, "z": 1.0}}]"#,
+            ),
+        ] {
+            let result = override_camera(&mut camera, path, value);
+            assert!(
+                matches!(result, Err(CliError::Usage(_))),
+                "override_camera() failed on --camera.{} {}. Expected a usage error, got {:?}.",
+                path,
+                value,
+                result
+            );
+        }
+
+        override_camera(&mut camera, "focal_len", "2").unwrap();
+        let focal_len = camera.focal_len.at(0.0);
+        assert!(
+            focal_len == 2.0,
+            "override_camera() failed on --camera.focal_len 2. Expected 2, got {}.",
+            focal_len
+        );
+    }
+}
@@ -1,67 +1,781 @@
+use crate::animation::Animated;
+use crate::error::{self, SceneError};
+use crate::image::ImageError;
+use crate::medium::Medium;
+use crate::object::Intersection;
 use crate::scene::Scene;
+use crate::spectrum::REFERENCE_WAVELENGTH;
+use crate::texture::Texture;
 use crate::vector::Vector3;
-use crate::{random, Ray};
+use crate::{random, render, Ray};
 
 use serde::{Deserialize, Serialize};
 
+use std::f32::consts::{PI, TAU};
+use std::path::Path;
+
 #[derive(Serialize, Deserialize)]
 /// An enum with a variety of different materials for rendering. Available materials are:
-/// - Emissive: A light source. Emits light of the given `color` with the given `intensity`.
+/// - Emissive: An area light. Emits light of the given `color` scaled by `intensity`, which is
+///   either the emitted radiance or the total power in watts spread evenly over the surface,
+///   depending on `units`. Light is emitted from both faces unless `two_sided` is `false`, in
+///   which case only the face the outward normal points to emits. An optional `texture`
+///   modulates the emitted color.
 /// - Diffuse: A Lambertian diffuse material with the given `color`.
-/// - Specular: A glossy material with the given `color` and roughness.
+/// - Specular: A glossy metal-like material with the given `color` and `roughness` between 0
+///   for a mirror and 1 for a broad highlight, modelled with GGX microfacets.
+/// - Dielectric: A smooth glass-like surface tinted by `color` that reflects and refracts light
+///   according to its index of refraction `ior`. In spectral mode, indices that vary with
+///   wavelength split white light into its colors.
+/// - Interface: An invisible surface that light passes straight through, which marks the
+///   boundary of an object's `medium`.
+///
+/// Colors may be animated with keyframe tracks.
 pub enum Material {
-    Emissive { color: Vector3, intensity: f32 },
-    Diffuse { color: Vector3 },
-    Specular { color: Vector3, roughness: f32 },
+    Emissive {
+        color: Animated<Vector3>,
+        intensity: f32,
+        #[serde(default)]
+        units: EmissionUnits,
+        #[serde(default = "default_two_sided")]
+        two_sided: bool,
+        #[serde(default)]
+        texture: Option<Texture>,
+    },
+    Diffuse {
+        color: Animated<Vector3>,
+    },
+    Specular {
+        color: Animated<Vector3>,
+        roughness: f32,
+    },
+    Dielectric {
+        #[serde(default = "default_color")]
+        color: Animated<Vector3>,
+        ior: Ior,
+    },
+    Interface,
+}
+
+fn default_color() -> Animated<Vector3> {
+    Vector3::ones().into()
+}
+
+/// The index of refraction of a dielectric, which may vary with the wavelength `lambda`.
+/// - Constant: The same index at every wavelength.
+/// - Cauchy: Cauchy's equation `n = a + b / lambda^2` with `lambda` in micrometers.
+/// - Sellmeier: The Sellmeier equation `n^2 = 1 + sum(b[i] lambda^2 / (lambda^2 - c[i]))` with
+///   `lambda` in micrometers, as listed for optical glasses such as BK7.
+///
+/// Outside of spectral mode, indices are evaluated at the sodium D line of 589.3 nm.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Ior {
+    Constant(f32),
+    Cauchy { a: f32, b: f32 },
+    Sellmeier { b: [f32; 3], c: [f32; 3] },
+}
+
+impl Ior {
+    /// Return the index of refraction at `lambda` nanometers.
+    pub fn at(&self, lambda: f32) -> f32 {
+        let um2 = (lambda / 1000.0).powi(2);
+        match self {
+            Ior::Constant(n) => *n,
+            Ior::Cauchy { a, b } => a + b / um2,
+            Ior::Sellmeier { b, c } => (1.0
+                + (0..3).map(|i| b[i] * um2 / (um2 - c[i])).sum::<f32>())
+            .max(0.0)
+            .sqrt(),
+        }
+    }
+
+    /// Return whether the index of refraction varies with wavelength.
+    pub fn is_dispersive(&self) -> bool {
+        !matches!(self, Ior::Constant(_))
+    }
+}
+
+/// The units of the `intensity` of an emissive material.
+/// - Radiance: The radiance leaving the surface, in watts per steradian per square meter.
+/// - Power: The total power leaving the surface, in watts.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum EmissionUnits {
+    #[default]
+    Radiance,
+    Power,
+}
+
+fn default_two_sided() -> bool {
+    true
+}
+
+/// Something that scatters light at a vertex of a path: either a surface with a `material` and
+/// a `normal`, or a point inside a participating medium.
+pub enum Scatterer<'a> {
+    Surface {
+        material: &'a Material,
+        normal: Vector3,
+    },
+    Medium(&'a Medium),
+}
+
+impl Scatterer<'_> {
+    /// Evaluate the scattering function for light arriving from the direction `dir` and
+    /// leaving in the `view` direction, including the cosine term at surfaces.
+    pub fn eval(&self, view: Vector3, dir: Vector3, time: f32) -> Vector3 {
+        match self {
+            Scatterer::Surface { material, normal } => {
+                material.eval(view, dir, *normal, time) * dir.dot(*normal).max(0.0)
+            }
+            Scatterer::Medium(medium) => Vector3::ones() * medium.phase(view, dir),
+        }
+    }
+
+    /// Return the solid-angle pdf with which this scatterer samples the direction `dir`.
+    pub fn pdf(&self, view: Vector3, dir: Vector3) -> f32 {
+        match self {
+            Scatterer::Surface { material, normal } => material.pdf(view, dir, *normal),
+            Scatterer::Medium(medium) => medium.phase(view, dir),
+        }
+    }
+}
+
+/// A direction sampled from a material's scattering distribution, together with the pdf with
+/// which it was sampled and the sample `weight`, which is the BSDF times the cosine of the
+/// sampled direction divided by the pdf. Directions sampled from a `specular` distribution
+/// cannot be found by sampling lights, so their pdf is the discrete probability of the choice.
+pub struct BsdfSample {
+    pub direction: Vector3,
+    pub weight: Vector3,
+    pub pdf: f32,
+    pub specular: bool,
 }
 
 impl Material {
-    /// Compute the surface color for this material at the given `position` given the `normal`
-    /// and the given `scene` with a given `view` direction.
+    /// Compute the light reflected by this material towards the origin of the `ray` at its
+    /// `intersection` with the surface in the given `scene`. Light arriving directly from the
+    /// scene's light sources is sampled explicitly, and `bounces` more indirect bounces are
+    /// traced. Light emitted by the surface itself is not included, and emitters do not trace
+    /// any further rays. Reflected light travels through the `medium` the ray came through,
+    /// and transmitted light through the medium `across` the surface.
     pub fn lighting(
         &self,
-        view: Vector3,
-        position: Vector3,
-        normal: Vector3,
+        ray: Ray,
+        intersection: &Intersection,
         scene: &Scene,
         bounces: usize,
+        medium: Option<&Medium>,
+        across: Option<&Medium>,
     ) -> Vector3 {
+        if let Material::Emissive { .. } | Material::Interface = self {
+            return Vector3::zeros();
+        }
+
+        let Intersection {
+            position, normal, ..
+        } = *intersection;
 
-        let dir = {
-            let mut dir = Vector3::unit(
-                random::normal(),
-                random::normal(),
-                random::normal(),
-            ).normalized();
-            
-            // Ensure it's in the same hemisphere as the normal
-            if dir.dot(normal) < 0.0 {
-                dir = -dir;
-            }
-            dir
+        // Only weight direct lighting against the bounce ray if the bounce ray can see lights.
+        let scatterer = Scatterer::Surface {
+            material: self,
+            normal,
         };
+        let direct = scene.direct_lighting(&scatterer, ray, position, bounces > 0, medium);
 
+        let indirect = match self.bounce(ray, intersection) {
+            Some((bounce, sample, weight)) if bounces > 0 => {
+                let medium = if sample.direction.dot(normal) < 0.0 {
+                    across
+                } else {
+                    medium
+                };
+                let pdf = (!sample.specular).then_some(sample.pdf);
+                weight.cwise_mul(scene.trace(bounce, 1.0e-3, bounces, pdf, medium))
+            }
+            _ => Vector3::zeros(),
+        };
 
-        let incoming = scene.sample(
-            Ray { origin: position, direction: dir }, 1.0e-3, bounces
-        );
+        direct + indirect
+    }
 
-        match *self {
-            Material::Emissive { color, intensity } => {
-                color * intensity
+    /// Sample the direction in which the `ray` that hit this material at `intersection`
+    /// continues, returning the bounce ray, the sample it was chosen with, and the weight of the
+    /// light it carries. Rays that meet a dispersive surface in spectral mode only carry on with
+    /// their hero wavelength.
+    pub fn bounce(
+        &self,
+        ray: Ray,
+        intersection: &Intersection,
+    ) -> Option<(Ray, BsdfSample, Vector3)> {
+        let mut ray = ray;
+        let mut weight = Vector3::ones();
+        if self.is_dispersive() {
+            if let Some(wavelengths) = &mut ray.wavelengths {
+                weight = wavelengths.terminate_secondary();
             }
-            Material::Diffuse { color } => {
-                let brdf = (dir.dot(normal) / std::f32::consts::PI) * color;
-                2.0 * std::f32::consts::PI * brdf.cwise_mul(incoming)
+        }
+        let lambda = ray
+            .wavelengths
+            .map_or(REFERENCE_WAVELENGTH, |wavelengths| wavelengths.hero());
+
+        let sample = self.sample(
+            -ray.direction,
+            intersection.normal,
+            intersection.front_face,
+            ray.time,
+            lambda,
+        )?;
+        render::count_bounce();
+        let weight = weight.cwise_mul(ray.upsample(sample.weight));
+        let bounce = Ray {
+            origin: intersection.position,
+            direction: sample.direction,
+            ..ray
+        };
+
+        Some((bounce, sample, weight))
+    }
+
+    /// Return the weight of a `sample` from `bounce` for a path traced from a light source,
+    /// given its `weight` for a path traced from the camera. Such paths carry importance rather
+    /// than radiance, so the BSDF is evaluated with its directions swapped, and refraction does
+    /// not compress it.
+    pub fn importance_weight(
+        &self,
+        ray: &Ray,
+        intersection: &Intersection,
+        sample: &BsdfSample,
+        weight: Vector3,
+    ) -> Vector3 {
+        let normal = intersection.normal;
+        match self {
+            Material::Dielectric { ior, .. } if sample.direction.dot(normal) < 0.0 => {
+                let lambda = ray
+                    .wavelengths
+                    .map_or(REFERENCE_WAVELENGTH, |wavelengths| wavelengths.hero());
+                let n = ior.at(lambda);
+                let eta = if intersection.front_face { 1.0 / n } else { n };
+                weight * (1.0 / (eta * eta))
+            }
+            _ if sample.specular => weight,
+            _ => {
+                let f = self.eval(sample.direction, -ray.direction, normal, ray.time);
+                ray.upsample(f) * (sample.direction.dot(normal).abs() / sample.pdf)
+            }
+        }
+    }
+
+    /// Return whether light leaves this material in directions that depend on its wavelength.
+    pub fn is_dispersive(&self) -> bool {
+        matches!(self, Material::Dielectric { ior, .. } if ior.is_dispersive())
+    }
+
+    /// Check that this material at `path` can be rendered: colors must be finite, emission must
+    /// be finite and not negative, and roughness must be between 0 and 1.
+    pub fn validate(&self, path: &str) -> Result<(), SceneError> {
+        let (name, color) = match self {
+            Material::Emissive { color, .. } => ("Emissive", color),
+            Material::Diffuse { color } => ("Diffuse", color),
+            Material::Specular { color, .. } => ("Specular", color),
+            Material::Dielectric { color, .. } => ("Dielectric", color),
+            Material::Interface => return Ok(()),
+        };
+        let path = format!("{}.{}", path, name);
+        color.try_each(&format!("{}.color", path), |path, color| {
+            error::check_color(path, *color)
+        })?;
+
+        match self {
+            Material::Emissive {
+                intensity, texture, ..
+            } => {
+                if !(intensity.is_finite() && *intensity >= 0.0) {
+                    return Err(SceneError::invalid(
+                        format!("{}.intensity", path),
+                        format!("intensity {} must be finite and not negative", intensity),
+                    ));
+                }
+                match texture {
+                    Some(texture) => texture.validate(&format!("{}.texture", path)),
+                    None => Ok(()),
+                }
+            }
+            Material::Specular { roughness, .. } if !(0.0..=1.0).contains(roughness) => {
+                Err(SceneError::invalid(
+                    format!("{}.roughness", path),
+                    format!("roughness {} must be between 0 and 1", roughness),
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Load any images used by this material, resolving paths relative to `base`.
+    pub fn load(&mut self, base: &Path) -> Result<(), ImageError> {
+        match self {
+            Material::Emissive {
+                texture: Some(texture),
+                ..
+            } => texture.load(base),
+            _ => Ok(()),
+        }
+    }
+
+    /// Return whether this material emits a total power that depends on the surface area.
+    pub fn emits_power(&self) -> bool {
+        matches!(
+            self,
+            Material::Emissive {
+                units: EmissionUnits::Power,
+                ..
+            }
+        )
+    }
+
+    /// Return the radiance emitted by this material at the `intersection` at the given `time`.
+    /// The surface `area` is only computed for materials that emit a total power.
+    pub fn emitted(
+        &self,
+        intersection: &Intersection,
+        area: impl FnOnce() -> f32,
+        time: f32,
+    ) -> Vector3 {
+        let Material::Emissive {
+            color,
+            intensity,
+            units,
+            two_sided,
+            texture,
+        } = self
+        else {
+            return Vector3::zeros();
+        };
+
+        if !two_sided && !intersection.front_face {
+            return Vector3::zeros();
+        }
+
+        let mut radiance = color.at(time) * *intensity;
+        if let Some(texture) = texture {
+            radiance = radiance.cwise_mul(texture.at(intersection.uv));
+        }
+
+        match units {
+            EmissionUnits::Radiance => radiance,
+            EmissionUnits::Power => {
+                // A Lambertian emitter sends out pi times its radiance per unit area per side.
+                let sides = if *two_sided { 2.0 } else { 1.0 };
+                radiance * (1.0 / (sides * PI * area()))
+            }
+        }
+    }
+
+    /// Evaluate the BSDF for light arriving from the direction `dir` and leaving in the `view`
+    /// direction at a surface with the given `normal`.
+    pub fn eval(&self, view: Vector3, dir: Vector3, normal: Vector3, time: f32) -> Vector3 {
+        let cos_theta = dir.dot(normal);
+        if cos_theta <= 0.0 {
+            return Vector3::zeros();
+        }
+
+        match self {
+            Material::Emissive { .. } | Material::Dielectric { .. } | Material::Interface => {
+                Vector3::zeros()
             }
+            Material::Diffuse { color } => color.at(time) * (1.0 / PI),
             Material::Specular { color, roughness } => {
+                let cos_view = view.dot(normal);
+                if cos_view <= 0.0 {
+                    return Vector3::zeros();
+                }
+
+                // The height-correlated Smith masking term is symmetric in both directions, so
+                // the BSDF is reciprocal.
+                let alpha = ggx_alpha(*roughness);
+                let halfway = (view + dir).normalized();
+                let d = ggx_distribution(normal.dot(halfway), alpha);
+                let g =
+                    1.0 / (1.0 + smith_lambda(cos_view, alpha) + smith_lambda(cos_theta, alpha));
+
+                color.at(time) * (d * g / (4.0 * cos_view * cos_theta))
+            }
+        }
+    }
+
+    /// Return the solid-angle pdf with which `sample` picks the direction `dir`.
+    pub fn pdf(&self, view: Vector3, dir: Vector3, normal: Vector3) -> f32 {
+        if dir.dot(normal) <= 0.0 {
+            return 0.0;
+        }
+
+        match self {
+            Material::Emissive { .. } | Material::Dielectric { .. } | Material::Interface => 0.0,
+            Material::Diffuse { .. } => random::cosine_hemisphere_pdf(normal, dir),
+            Material::Specular { roughness, .. } => {
+                // Microfacet normals are sampled in proportion to their projected area, and the
+                // reflection about them stretches the pdf by the Jacobian `1 / 4(v.h)`.
                 let halfway = (view + dir).normalized();
-                let d = (normal.dot(halfway).powi(2) / (roughness * roughness - 2.0 * roughness + std::f32::consts::PI)).clamp(0.0, 1.0);
-                let g = 1.0 / (4.0 * (view.dot(halfway)).powi(2)).clamp(0.0, 1.0);
-                let brdf = (d * g * color).cwise_mul(incoming);
+                let (cos_halfway, cos_view) = (normal.dot(halfway), view.dot(halfway));
+                if cos_view <= 0.0 {
+                    return 0.0;
+                }
+                ggx_distribution(cos_halfway, ggx_alpha(*roughness)) * cos_halfway
+                    / (4.0 * cos_view)
+            }
+        }
+    }
 
-                2.0 * std::f32::consts::PI * brdf
+    /// Sample a direction for light at the wavelength `lambda` arriving at a surface with the
+    /// given `normal` that leaves in the `view` direction. The ray that hit the surface came
+    /// from outside if `front_face` is set. Returns `None` if the material does not scatter
+    /// light.
+    pub fn sample(
+        &self,
+        view: Vector3,
+        normal: Vector3,
+        front_face: bool,
+        time: f32,
+        lambda: f32,
+    ) -> Option<BsdfSample> {
+        let direction = match self {
+            Material::Emissive { .. } | Material::Interface => return None,
+            Material::Dielectric { color, ior } => {
+                let n = ior.at(lambda);
+                let (eta_i, eta_t) = if front_face { (1.0, n) } else { (n, 1.0) };
+                return Some(sample_dielectric(
+                    view,
+                    normal,
+                    eta_i,
+                    eta_t,
+                    color.at(time),
+                ));
             }
+            Material::Diffuse { .. } => random::cosine_hemisphere(normal),
+            Material::Specular { roughness, .. } => sample_ggx(view, normal, ggx_alpha(*roughness)),
+        };
+
+        let pdf = self.pdf(view, direction, normal);
+        if pdf <= 0.0 {
+            return None;
         }
+
+        let f = self.eval(view, direction, normal, time);
+        Some(BsdfSample {
+            direction,
+            weight: f * (direction.dot(normal) / pdf),
+            pdf,
+            specular: false,
+        })
+    }
+}
+
+/// Return the GGX width of a material with the given `roughness`, kept above zero so that a
+/// mirror is a very narrow lobe rather than a singular one.
+fn ggx_alpha(roughness: f32) -> f32 {
+    (roughness * roughness).max(1.0e-3)
+}
+
+/// Return the GGX density of microfacet normals at an angle with cosine `cos_theta` to the
+/// surface normal.
+fn ggx_distribution(cos_theta: f32, alpha: f32) -> f32 {
+    if cos_theta <= 0.0 {
+        return 0.0;
+    }
+    let a2 = alpha * alpha;
+    let denom = cos_theta * cos_theta * (a2 - 1.0) + 1.0;
+    a2 / (PI * denom * denom)
+}
+
+/// Return the Smith auxiliary function for GGX, which measures how much of the surface is
+/// hidden from a direction with cosine `cos_theta` to the normal.
+fn smith_lambda(cos_theta: f32, alpha: f32) -> f32 {
+    let cos2 = cos_theta * cos_theta;
+    let tan2 = (1.0 - cos2).max(0.0) / cos2;
+    0.5 * ((1.0 + alpha * alpha * tan2).sqrt() - 1.0)
+}
+
+/// Sample a microfacet normal from the GGX distribution around `normal` and return the
+/// reflection of `view` about it, which may point below the surface.
+fn sample_ggx(view: Vector3, normal: Vector3, alpha: f32) -> Vector3 {
+    let (tangent, bitangent) = normal.basis();
+    let u = random::uniform();
+    let phi = TAU * random::uniform();
+    let cos2 = (1.0 - u) / (1.0 + (alpha * alpha - 1.0) * u);
+    let sin = (1.0 - cos2).max(0.0).sqrt();
+
+    let halfway =
+        tangent * (sin * phi.cos()) + bitangent * (sin * phi.sin()) + normal * cos2.sqrt();
+    halfway * (2.0 * view.dot(halfway)) - view
+}
+
+/// Sample reflection or refraction at a smooth surface between media with the indices of
+/// refraction `eta_i` on the `view` side of the surface and `eta_t` on the other side, choosing
+/// between them by the Fresnel reflectance.
+fn sample_dielectric(
+    view: Vector3,
+    normal: Vector3,
+    eta_i: f32,
+    eta_t: f32,
+    color: Vector3,
+) -> BsdfSample {
+    let eta = eta_i / eta_t;
+    let cos_i = view.dot(normal).clamp(0.0, 1.0);
+    let sin2_t = eta * eta * (1.0 - cos_i * cos_i);
+
+    let reflectance = if sin2_t >= 1.0 {
+        // Total internal reflection.
+        1.0
+    } else {
+        let cos_t = (1.0 - sin2_t).sqrt();
+        let parallel = (eta_t * cos_i - eta_i * cos_t) / (eta_t * cos_i + eta_i * cos_t);
+        let perpendicular = (eta_i * cos_i - eta_t * cos_t) / (eta_i * cos_i + eta_t * cos_t);
+        0.5 * (parallel * parallel + perpendicular * perpendicular)
+    };
+
+    if random::uniform() < reflectance {
+        BsdfSample {
+            direction: normal * (2.0 * cos_i) - view,
+            weight: color,
+            pdf: reflectance,
+            specular: true,
+        }
+    } else {
+        // Radiance is compressed into a smaller solid angle as it enters a denser medium.
+        let cos_t = (1.0 - sin2_t).sqrt();
+        BsdfSample {
+            direction: -view * eta + normal * (eta * cos_i - cos_t),
+            weight: color * (eta * eta),
+            pdf: 1.0 - reflectance,
+            specular: true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Return a material with the given `color` for each kind that scatters light, with
+    /// specular materials from a mirror to fully rough.
+    fn materials(color: Vector3) -> Vec<Material> {
+        let mut materials = vec![Material::Diffuse {
+            color: color.into(),
+        }];
+        for roughness in [0.0, 0.05, 0.3, 0.6, 1.0] {
+            materials.push(Material::Specular {
+                color: color.into(),
+                roughness,
+            });
+        }
+        materials
+    }
+
+    /// Return the view directions to test, from straight above the surface to grazing.
+    fn views(normal: Vector3) -> Vec<Vector3> {
+        [1.0f32, 0.7, 0.3, 0.05]
+            .iter()
+            .map(|&cos| Vector3::new((1.0 - cos * cos).sqrt(), 0.0, cos))
+            .inspect(|view| debug_assert!(view.dot(normal) > 0.0))
+            .collect()
+    }
+
+    /// Return whether the lobe of `material` is too narrow to integrate by sampling directions
+    /// uniformly.
+    fn is_narrow(material: &Material) -> bool {
+        matches!(material, Material::Specular { roughness, .. } if *roughness < 0.3)
+    }
+
+    /// Estimate the mean and its standard error of `n` draws of `f`.
+    fn estimate(n: usize, mut f: impl FnMut() -> f32) -> (f32, f32) {
+        let (mut sum, mut sum2) = (0.0f64, 0.0f64);
+        for _ in 0..n {
+            let x = f() as f64;
+            sum += x;
+            sum2 += x * x;
+        }
+        let mean = sum / n as f64;
+        let variance = (sum2 / n as f64 - mean * mean).max(0.0);
+        (mean as f32, (variance / n as f64).sqrt() as f32)
+    }
+
+    #[test]
+    fn test_furnace() {
+        // Under a uniform white environment of radiance 1, the light reflected after one bounce
+        // is the mean sample weight, which is the fraction of energy the material keeps.
+        random::reseed(1, 0);
+        let normal = Vector3::new(0.0, 0.0, 1.0);
+        let n = 40_000;
+        let furnace = |material: &Material, view: Vector3| {
+            estimate(n, || {
+                material
+                    .sample(view, normal, true, 0.0, REFERENCE_WAVELENGTH)
+                    .map_or(0.0, |sample| sample.weight.x())
+            })
+        };
+
+        // Materials must never reflect more than they receive, and the same energy must be
+        // found by integrating the BSDF directly, without importance sampling.
+        let white = materials(Vector3::ones());
+        for material in &white {
+            for view in views(normal) {
+                let (energy, error) = furnace(material, view);
+                let (integral, integral_error) = if is_narrow(material) {
+                    (energy, 0.0)
+                } else {
+                    estimate(n, || {
+                        let dir = random::hemisphere(normal);
+                        material.eval(view, dir, normal, 0.0).x() * dir.dot(normal) * 2.0 * PI
+                    })
+                };
+                let tolerance = 4.0 * (error + integral_error) + 1.0e-3;
+                assert!(
+                    energy <= 1.0 + 4.0 * error + 1.0e-3 && (integral - energy).abs() <= tolerance,
+                    "Material::sample() failed the furnace test on {} at {}. Expected at most 1 \
+                     and the integral {}, got {} +- {}.",
+                    serde_json::to_string(material).unwrap(),
+                    view,
+                    integral,
+                    energy,
+                    error
+                );
+            }
+        }
+
+        // White diffuse surfaces, mirrors and glass lose nothing. Light refracted into glass is
+        // compressed into a smaller solid angle, which is undone to count its energy.
+        let glass = Material::Dielectric {
+            color: Vector3::ones().into(),
+            ior: Ior::Constant(1.5),
+        };
+        let lossless = [&white[0], &white[1], &glass];
+        for material in lossless {
+            let view = views(normal)[1];
+            let (energy, error) = estimate(n, || {
+                material
+                    .sample(view, normal, true, 0.0, REFERENCE_WAVELENGTH)
+                    .map_or(0.0, |sample| {
+                        let compression = if sample.direction.dot(normal) < 0.0 {
+                            1.5 * 1.5
+                        } else {
+                            1.0
+                        };
+                        sample.weight.x() * compression
+                    })
+            });
+            assert!(
+                (energy - 1.0).abs() <= 4.0 * error + 1.0e-3,
+                "Material::sample() failed the furnace test on {}. Expected 1, got {} +- {}.",
+                serde_json::to_string(material).unwrap(),
+                energy,
+                error
+            );
+        }
+    }
+
+    #[test]
+    fn test_reciprocity() {
+        random::reseed(2, 0);
+        let normal = Vector3::new(0.0, 0.0, 1.0);
+        for material in materials(Vector3::new(0.9, 0.5, 0.1)) {
+            for _ in 0..1000 {
+                let (view, dir) = (random::hemisphere(normal), random::hemisphere(normal));
+                let (forward, backward) = (
+                    material.eval(view, dir, normal, 0.0),
+                    material.eval(dir, view, normal, 0.0),
+                );
+                assert!(
+                    (forward - backward).norm() <= 1.0e-3 * forward.norm().max(1.0),
+                    "Material::eval() failed reciprocity on {} between {} and {}. Expected {}, \
+                     got {}.",
+                    serde_json::to_string(&material).unwrap(),
+                    view,
+                    dir,
+                    forward,
+                    backward
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_pdf() {
+        // The pdf integrated over the hemisphere is the chance that `sample` gives a direction
+        // at all, which is 1 unless rough reflections fall below the surface.
+        random::reseed(3, 0);
+        let normal = Vector3::new(0.0, 0.0, 1.0);
+        let n = 40_000;
+        let materials = materials(Vector3::ones());
+        for material in materials.iter().filter(|material| !is_narrow(material)) {
+            for view in views(normal) {
+                let (integral, error) = estimate(n, || {
+                    material.pdf(view, random::hemisphere(normal), normal) * 2.0 * PI
+                });
+                let (sampled, _) = estimate(n, || {
+                    let sample = material.sample(view, normal, true, 0.0, REFERENCE_WAVELENGTH);
+                    sample.is_some() as u8 as f32
+                });
+                assert!(
+                    integral <= 1.0 + 4.0 * error + 1.0e-3
+                        && (integral - sampled).abs() <= 4.0 * error + 5.0e-3,
+                    "Material::pdf() failed on {} at {}. Expected it to integrate to {}, got {} \
+                     +- {}.",
+                    serde_json::to_string(material).unwrap(),
+                    view,
+                    sampled,
+                    integral,
+                    error
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_ior() {
+        // Schott N-BK7, which has a refractive index of 1.5168 at the sodium D line.
+        let bk7 = Ior::Sellmeier {
+            b: [1.039_612, 0.231_792_3, 1.010_469_5],
+            c: [0.006_000_699, 0.020_017_914, 103.560_66],
+        };
+        let n = bk7.at(REFERENCE_WAVELENGTH);
+        assert!(
+            (n - 1.5168).abs() < 1.0e-3,
+            "Ior::at() failed on BK7. Expected {}, got {}.",
+            1.5168,
+            n
+        );
+
+        let cauchy = Ior::Cauchy { a: 1.5, b: 0.005 };
+        assert!(
+            cauchy.at(400.0) > cauchy.at(700.0) && bk7.at(400.0) > bk7.at(700.0),
+            "Ior::at() should give a higher index for blue light than for red light."
+        );
+    }
+
+    #[test]
+    fn test_dielectric() {
+        // At normal incidence on glass with index 1.5, 4% of the light is reflected.
+        let normal = Vector3::new(0.0, 0.0, 1.0);
+        let n = 2000;
+        let reflected = (0..n)
+            .map(|_| sample_dielectric(normal, normal, 1.0, 1.5, Vector3::ones()))
+            .filter(|sample| sample.direction.dot(normal) > 0.0)
+            .count() as f32
+            / n as f32;
+        assert!(
+            (reflected - 0.04).abs() < 0.02,
+            "sample_dielectric() failed at normal incidence. Expected 0.04 reflected, got {}.",
+            reflected
+        );
+
+        // Past the critical angle, light inside the glass is always reflected.
+        let view = Vector3::new(0.8, 0.0, 0.6);
+        let sample = sample_dielectric(view, normal, 1.5, 1.0, Vector3::ones());
+        let expected = Vector3::new(-0.8, 0.0, 0.6);
+        assert!(
+            (sample.direction - expected).norm() < 1.0e-4 && sample.pdf == 1.0,
+            "sample_dielectric() failed on total internal reflection. Expected {}, got {}.",
+            expected,
+            sample.direction
+        );
     }
 }
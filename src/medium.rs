@@ -0,0 +1,522 @@
+use serde::{Deserialize, Serialize};
+
+use std::f32::consts::{PI, TAU};
+
+use crate::error::SceneError;
+use crate::material::Scatterer;
+use crate::random;
+use crate::scene::Scene;
+use crate::vector::Vector3;
+use crate::Ray;
+
+/// A participating medium such as fog, smoke or the inside of a translucent object. Light
+/// travelling through the medium is absorbed and scattered in proportion to the `absorption`
+/// and `scattering` coefficients, given per unit of distance. Scattered light leaves in a
+/// direction chosen by the Henyey-Greenstein phase function with the given `anisotropy`, which
+/// ranges from back-scattering at `-1` through isotropic at `0` to forward-scattering at `1`.
+///
+/// A medium is homogeneous unless it has a `density` grid, in which case both coefficients are
+/// scaled by the density at each point and the medium is sampled with delta tracking.
+#[derive(Serialize, Deserialize)]
+pub struct Medium {
+    pub absorption: Vector3,
+    pub scattering: Vector3,
+    #[serde(default)]
+    pub anisotropy: f32,
+    #[serde(default)]
+    pub density: Option<DensityGrid>,
+}
+
+/// A grid of densities with `resolution` samples along `x`, `y` and `z` that spans the box from
+/// `min` to `max` in world space. The `values` are stored with `x` varying fastest, then `y`,
+/// then `z`, and are interpolated between sample points. The density is zero outside the box.
+#[derive(Serialize, Deserialize)]
+#[serde(try_from = "RawDensityGrid")]
+pub struct DensityGrid {
+    resolution: [usize; 3],
+    values: Vec<f32>,
+    min: Vector3,
+    max: Vector3,
+    #[serde(skip)]
+    max_density: f32,
+}
+
+/// The on-disk form of a `DensityGrid`, which is checked before the grid is built.
+#[derive(Deserialize)]
+struct RawDensityGrid {
+    resolution: [usize; 3],
+    values: Vec<f32>,
+    min: Vector3,
+    max: Vector3,
+}
+
+impl TryFrom<RawDensityGrid> for DensityGrid {
+    type Error = String;
+
+    fn try_from(raw: RawDensityGrid) -> Result<Self, Self::Error> {
+        DensityGrid::new(raw.resolution, raw.values, raw.min, raw.max)
+    }
+}
+
+impl DensityGrid {
+    /// Create a new `DensityGrid`, failing if the number of `values` does not match the
+    /// `resolution` or any value is negative.
+    pub fn new(
+        resolution: [usize; 3],
+        values: Vec<f32>,
+        min: Vector3,
+        max: Vector3,
+    ) -> Result<Self, String> {
+        let count: usize = resolution.iter().product();
+        if count == 0 || values.len() != count {
+            return Err(format!(
+                "A density grid with resolution {:?} needs {} values, found {}.",
+                resolution,
+                count,
+                values.len()
+            ));
+        }
+        if values.iter().any(|v| v.is_nan() || *v < 0.0) {
+            return Err("Densities must be non-negative numbers.".to_string());
+        }
+
+        let max_density = values.iter().copied().fold(0.0, f32::max);
+        Ok(DensityGrid {
+            resolution,
+            values,
+            min,
+            max,
+            max_density,
+        })
+    }
+
+    /// Return the density at the world-space `point`, interpolating trilinearly between the
+    /// sample points.
+    pub fn density(&self, point: Vector3) -> f32 {
+        let extent = self.max - self.min;
+        let local = (point - self.min).cwise_div(extent);
+        if [local.x(), local.y(), local.z()]
+            .iter()
+            .any(|c| !(0.0..=1.0).contains(c))
+        {
+            return 0.0;
+        }
+
+        // Find the sample points around the point and the offsets between them on each axis.
+        let [nx, ny, nz] = self.resolution;
+        let cell = |c: f32, n: usize| {
+            let x = (c * n as f32 - 0.5).clamp(0.0, (n - 1) as f32);
+            let i = (x as usize).min(n.saturating_sub(2));
+            (i, (i + 1).min(n - 1), x - i as f32)
+        };
+        let (x0, x1, fx) = cell(local.x(), nx);
+        let (y0, y1, fy) = cell(local.y(), ny);
+        let (z0, z1, fz) = cell(local.z(), nz);
+
+        let at = |x: usize, y: usize, z: usize| self.values[(z * ny + y) * nx + x];
+        let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+        let plane = |z: usize| {
+            lerp(
+                lerp(at(x0, y0, z), at(x1, y0, z), fx),
+                lerp(at(x0, y1, z), at(x1, y1, z), fx),
+                fy,
+            )
+        };
+
+        lerp(plane(z0), plane(z1), fz)
+    }
+
+    /// Return the range of distances along a `ray` with a unit direction, between 0 and `tmax`,
+    /// over which it is inside the box of this grid, or `None` if it misses the box.
+    fn overlap(&self, ray: Ray, tmax: f32) -> Option<(f32, f32)> {
+        let axes = |v: Vector3| [v.x(), v.y(), v.z()];
+        let (origin, direction) = (axes(ray.origin), axes(ray.direction));
+        let (min, max) = (axes(self.min), axes(self.max));
+
+        let (mut near, mut far) = (0.0f32, tmax);
+        for i in 0..3 {
+            if direction[i] == 0.0 {
+                if origin[i] < min[i] || origin[i] > max[i] {
+                    return None;
+                }
+                continue;
+            }
+            let a = (min[i] - origin[i]) / direction[i];
+            let b = (max[i] - origin[i]) / direction[i];
+            near = near.max(a.min(b));
+            far = far.min(a.max(b));
+        }
+
+        (near < far).then_some((near, far))
+    }
+}
+
+/// The outcome of sampling the distance a ray travels through a medium before it interacts.
+/// - Scatter: The ray scatters at `distance` along it.
+/// - Pass: The ray reaches the end of the medium without scattering.
+///
+/// In both cases, `weight` is the transmittance, and scattering coefficient if the ray
+/// scattered, divided by the pdf of the event.
+pub enum MediumEvent {
+    Scatter { distance: f32, weight: Vector3 },
+    Pass { weight: Vector3 },
+}
+
+impl Medium {
+    /// Check that this medium at `path` can be rendered: its coefficients must be finite and
+    /// not negative, and its anisotropy must be strictly between -1 and 1.
+    pub fn validate(&self, path: &str) -> Result<(), SceneError> {
+        for (name, coefficients) in [
+            ("absorption", self.absorption),
+            ("scattering", self.scattering),
+        ] {
+            if ![coefficients.x(), coefficients.y(), coefficients.z()]
+                .iter()
+                .all(|c| c.is_finite() && *c >= 0.0)
+            {
+                return Err(SceneError::invalid(
+                    format!("{}.{}", path, name),
+                    format!("{} {} must be finite and not negative", name, coefficients),
+                ));
+            }
+        }
+
+        if self.anisotropy.abs() < 1.0 {
+            Ok(())
+        } else {
+            Err(SceneError::invalid(
+                format!("{}.anisotropy", path),
+                format!("anisotropy {} must be between -1 and 1", self.anisotropy),
+            ))
+        }
+    }
+
+    /// Return the extinction coefficient, which is the sum of absorption and scattering.
+    pub fn extinction(&self) -> Vector3 {
+        self.absorption + self.scattering
+    }
+
+    /// Sample where a ray with a unit direction first interacts with this medium before it
+    /// leaves the medium after `tmax`.
+    pub fn sample(&self, ray: Ray, tmax: f32) -> MediumEvent {
+        let sigma_t = ray.upsample(self.extinction());
+        let scattering = ray.upsample(self.scattering);
+        match &self.density {
+            None => {
+                // Sample the distance with the extinction of a randomly chosen channel, and
+                // weight it by the average pdf over all channels.
+                let channel = ((3.0 * random::uniform()) as usize).min(2);
+                let sigma = [sigma_t.x(), sigma_t.y(), sigma_t.z()][channel];
+                let distance = if sigma > 0.0 {
+                    -(1.0 - random::uniform()).ln() / sigma
+                } else {
+                    f32::INFINITY
+                };
+
+                if distance < tmax {
+                    let tr = transmittance(sigma_t, distance);
+                    let pdf = average(sigma_t.cwise_mul(tr));
+                    MediumEvent::Scatter {
+                        distance,
+                        weight: tr.cwise_mul(scattering) * (1.0 / pdf),
+                    }
+                } else {
+                    let tr = transmittance(sigma_t, tmax);
+                    let pdf = average(tr);
+                    let weight = if pdf > 0.0 {
+                        tr * (1.0 / pdf)
+                    } else {
+                        Vector3::zeros()
+                    };
+                    MediumEvent::Pass { weight }
+                }
+            }
+            Some(grid) => {
+                // Delta tracking against the largest extinction anywhere in the grid. Tentative
+                // collisions are real in proportion to the average extinction at that point,
+                // and the weights correct for extinction that differs between channels.
+                // Tracking is clipped to the box, since the density is zero outside it and the
+                // ray may never leave the medium.
+                let majorant = max_channel(sigma_t) * grid.max_density;
+                let overlap = grid.overlap(ray, tmax);
+                let (Some((start, end)), true) = (overlap, majorant > 0.0) else {
+                    return MediumEvent::Pass {
+                        weight: Vector3::ones(),
+                    };
+                };
+
+                let mut weight = Vector3::ones();
+                let mut t = start;
+                loop {
+                    t -= (1.0 - random::uniform()).ln() / majorant;
+                    if t >= end {
+                        return MediumEvent::Pass { weight };
+                    }
+
+                    let density = grid.density(ray.at(t));
+                    let p_real = average(sigma_t) * density / majorant;
+                    if random::uniform() < p_real {
+                        return MediumEvent::Scatter {
+                            distance: t,
+                            weight: weight.cwise_mul(scattering) * (density / (majorant * p_real)),
+                        };
+                    }
+
+                    let null = Vector3::ones() * majorant - sigma_t * density;
+                    weight = weight.cwise_mul(null) * (1.0 / (majorant * (1.0 - p_real)));
+                }
+            }
+        }
+    }
+
+    /// Return the fraction of light that passes through this medium along a ray with a unit
+    /// direction for the given `distance`.
+    pub fn transmittance(&self, ray: Ray, distance: f32) -> Vector3 {
+        let sigma_t = ray.upsample(self.extinction());
+        match &self.density {
+            None => transmittance(sigma_t, distance),
+            Some(grid) => {
+                // Ratio tracking against the same majorant as delta tracking.
+                let majorant = max_channel(sigma_t) * grid.max_density;
+                let overlap = grid.overlap(ray, distance);
+                let (Some((start, end)), true) = (overlap, majorant > 0.0) else {
+                    return Vector3::ones();
+                };
+
+                let mut tr = Vector3::ones();
+                let mut t = start;
+                loop {
+                    t -= (1.0 - random::uniform()).ln() / majorant;
+                    if t >= end {
+                        return tr;
+                    }
+
+                    let null = Vector3::ones() - sigma_t * (grid.density(ray.at(t)) / majorant);
+                    tr = tr.cwise_mul(null);
+                }
+            }
+        }
+    }
+
+    /// Evaluate the phase function for light arriving from the direction `dir` and leaving in
+    /// the `view` direction. This is also the pdf with which `sample_phase` picks `dir`.
+    pub fn phase(&self, view: Vector3, dir: Vector3) -> f32 {
+        let g = self.anisotropy.clamp(-0.99, 0.99);
+        let cos_theta = -view.dot(dir);
+        let denom = 1.0 + g * g - 2.0 * g * cos_theta;
+
+        (1.0 - g * g) / (4.0 * PI * denom * denom.max(0.0).sqrt())
+    }
+
+    /// Sample a direction for light arriving at a point in the medium that leaves in the `view`
+    /// direction, in proportion to the phase function.
+    pub fn sample_phase(&self, view: Vector3) -> Vector3 {
+        let g = self.anisotropy.clamp(-0.99, 0.99);
+        let u = random::uniform();
+        let cos_theta = if g.abs() < 1.0e-3 {
+            1.0 - 2.0 * u
+        } else {
+            let s = (1.0 - g * g) / (1.0 - g + 2.0 * g * u);
+            (1.0 + g * g - s * s) / (2.0 * g)
+        };
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = TAU * random::uniform();
+
+        // Angles are measured from the direction the light was travelling in.
+        let forward = -view;
+        let (tangent, bitangent) = forward.basis();
+        tangent * (sin_theta * phi.cos())
+            + bitangent * (sin_theta * phi.sin())
+            + forward * cos_theta
+    }
+
+    /// Compute the light scattered towards the origin of the `ray` at `position` inside this
+    /// medium. Light arriving directly from the scene's light sources is sampled explicitly,
+    /// and `bounces` more indirect bounces are traced.
+    pub fn lighting(&self, ray: Ray, position: Vector3, scene: &Scene, bounces: usize) -> Vector3 {
+        let view = -ray.direction;
+        let scatterer = Scatterer::Medium(self);
+        let direct = scene.direct_lighting(&scatterer, ray, position, bounces > 0, Some(self));
+
+        let indirect = if bounces > 0 {
+            let direction = self.sample_phase(view);
+            let bounce = Ray {
+                origin: position,
+                direction,
+                ..ray
+            };
+            let pdf = self.phase(view, direction);
+            scene.trace(bounce, 0.0, bounces, Some(pdf), Some(self))
+        } else {
+            Vector3::zeros()
+        };
+
+        direct + indirect
+    }
+}
+
+/// Return the transmittance through a homogeneous medium with extinction `sigma_t` over
+/// `distance`. Channels without extinction transmit everything, even over infinite distances.
+fn transmittance(sigma_t: Vector3, distance: f32) -> Vector3 {
+    let channel = |sigma: f32| {
+        if sigma > 0.0 {
+            (-sigma * distance).exp()
+        } else {
+            1.0
+        }
+    };
+
+    Vector3::new(
+        channel(sigma_t.x()),
+        channel(sigma_t.y()),
+        channel(sigma_t.z()),
+    )
+}
+
+fn average(v: Vector3) -> f32 {
+    (v.x() + v.y() + v.z()) / 3.0
+}
+
+fn max_channel(v: Vector3) -> f32 {
+    v.x().max(v.y()).max(v.z())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_transmittance() {
+        let coefficients = |density| Medium {
+            absorption: Vector3::new(0.2, 0.5, 1.0),
+            scattering: Vector3::new(0.3, 0.5, 0.0),
+            anisotropy: 0.0,
+            density,
+        };
+        let homogeneous = coefficients(None);
+        let grid = DensityGrid::new(
+            [2, 2, 2],
+            vec![1.0; 8],
+            Vector3::new(-10.0, -10.0, -10.0),
+            Vector3::new(10.0, 10.0, 10.0),
+        )
+        .expect("Density grid should be valid.");
+        let heterogeneous = coefficients(Some(grid));
+
+        let ray = Ray::new(Vector3::zeros(), Vector3::new(1.0, 0.0, 0.0));
+        let distance = 2.0;
+        let expected = transmittance(homogeneous.extinction(), distance);
+
+        // Delta and ratio tracking estimate the same transmittance as the homogeneous medium.
+        let n = 100000;
+        for medium in [&homogeneous, &heterogeneous] {
+            let passed = (0..n)
+                .map(|_| match medium.sample(ray, distance) {
+                    MediumEvent::Pass { weight } => weight,
+                    MediumEvent::Scatter { .. } => Vector3::zeros(),
+                })
+                .fold(Vector3::zeros(), |a, b| a + b)
+                * (1.0 / n as f32);
+            let ratio = (0..n)
+                .map(|_| medium.transmittance(ray, distance))
+                .fold(Vector3::zeros(), |a, b| a + b)
+                * (1.0 / n as f32);
+            assert!(
+                (passed - expected).norm() < 0.03 && (ratio - expected).norm() < 0.03,
+                "Medium::sample() failed to estimate the transmittance. Expected {}, got {}.",
+                expected,
+                passed
+            );
+        }
+    }
+
+    #[test]
+    fn test_infinite_distance() {
+        // Rays that never leave a grid medium only cross its box, which is 5 units away.
+        let grid = DensityGrid::new(
+            [2, 2, 2],
+            vec![1.0; 8],
+            Vector3::new(-5.0, -5.0, -5.0),
+            Vector3::new(5.0, 5.0, 5.0),
+        )
+        .expect("Density grid should be valid.");
+        let medium = Medium {
+            absorption: Vector3::ones() * 0.2,
+            scattering: Vector3::zeros(),
+            anisotropy: 0.0,
+            density: Some(grid),
+        };
+
+        let expected = (-0.2f32 * 5.0).exp();
+        let n = 20000;
+        for ray in [
+            Ray::new(Vector3::zeros(), Vector3::new(0.0, 1.0, 0.0)),
+            Ray::new(Vector3::new(0.0, -5.0, 20.0), Vector3::new(0.0, 0.0, -1.0)),
+        ] {
+            let tr = (0..n)
+                .map(|_| medium.transmittance(ray, f32::INFINITY).x())
+                .sum::<f32>()
+                / n as f32;
+            let passed = (0..n)
+                .map(|_| match medium.sample(ray, f32::INFINITY) {
+                    MediumEvent::Pass { weight } => weight.x(),
+                    MediumEvent::Scatter { .. } => 0.0,
+                })
+                .sum::<f32>()
+                / n as f32;
+            let expected = if ray.origin.z() > 0.0 {
+                expected * expected
+            } else {
+                expected
+            };
+            assert!(
+                (tr - expected).abs() < 0.02 && (passed - expected).abs() < 0.02,
+                "Medium::transmittance() failed over an infinite distance from {}. Expected {}, \
+                 got {} and {}.",
+                ray.origin,
+                expected,
+                tr,
+                passed
+            );
+        }
+
+        // A ray that misses the box passes straight through.
+        let ray = Ray::new(Vector3::new(0.0, 10.0, 0.0), Vector3::new(1.0, 0.0, 0.0));
+        let tr = medium.transmittance(ray, f32::INFINITY);
+        assert!(
+            tr == Vector3::ones(),
+            "Medium::transmittance() failed on a ray that misses the grid. Expected {}, got {}.",
+            Vector3::ones(),
+            tr
+        );
+    }
+
+    #[test]
+    fn test_phase() {
+        // The phase function must integrate to one over the sphere for any anisotropy.
+        let view = Vector3::new(0.0, 0.0, 1.0);
+        let n = 400;
+        for anisotropy in [-0.5, 0.0, 0.8] {
+            let medium = Medium {
+                absorption: Vector3::zeros(),
+                scattering: Vector3::ones(),
+                anisotropy,
+                density: None,
+            };
+            let integral: f32 = (0..n)
+                .map(|i| {
+                    let cos_theta = 1.0 - 2.0 * (i as f32 + 0.5) / n as f32;
+                    let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+                    let dir = Vector3::new(sin_theta, 0.0, cos_theta);
+                    medium.phase(view, dir) * 4.0 * PI / n as f32
+                })
+                .sum();
+            assert!(
+                (integral - 1.0).abs() < 1.0e-2,
+                "Medium::phase() failed with anisotropy {}. Expected an integral of 1, got {}.",
+                anisotropy,
+                integral
+            );
+        }
+    }
+}
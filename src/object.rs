@@ -1,74 +1,528 @@
+use crate::error::SceneError;
+use crate::image::ImageError;
 use crate::material::Material;
-use crate::transform::Transform;
+use crate::medium::Medium;
+use crate::random;
+use crate::transform::{Affine, TransformStack};
 use crate::vector::Vector3;
 use crate::Ray;
 
 use serde::{Deserialize, Serialize};
 
-/// A simple struct representing an intersection between a ray and a shape.
+use std::collections::HashMap;
+use std::f32::consts::{PI, TAU};
+use std::path::Path;
+use std::sync::Arc;
+
+/// A simple struct representing an intersection between a ray and a shape. The `normal` faces
+/// the side of the surface the ray came from, the `uv` are the surface coordinates of the hit,
+/// and `front_face` is whether the ray hit the side of the surface that its outward normal
+/// points to.
 pub struct Intersection {
     pub position: Vector3,
     pub normal: Vector3,
+    pub uv: (f32, f32),
+    pub front_face: bool,
 }
 
 /// A trait that represents a shape that can be intersected by a ray.
 pub trait Renderable: Send + Sync {
-    /// Attempt to find the intersection point of the ray and the shape, returning `None` if
-    /// an intersection cannot be found, and returning an intersection otherwise.
-    fn intersection(&self, ray: Ray) -> Option<(f32, Intersection)>;
+    /// Attempt to find the closest intersection point of the ray and the shape further than
+    /// `tmin` along the ray, returning `None` if an intersection cannot be found, and returning
+    /// an intersection otherwise.
+    fn intersection(&self, ray: Ray, tmin: f32) -> Option<(f32, Intersection)>;
 }
 
 /// A struct that allows the internal shape to be transformed by an arbitrary list of
-/// transformations.
+/// transformations. A closed shape may be filled with a participating `medium`, which rays
+/// enter when they pass through its surface. Objects with a medium should not overlap or sit
+/// inside each other, since rays leaving one return to the scene's global medium.
 #[derive(Serialize, Deserialize)]
 pub struct Object {
     pub object: Shape,
     pub material: Material,
-    pub transforms: Vec<Transform>,
+    pub transforms: TransformStack,
+    #[serde(default)]
+    pub medium: Option<Medium>,
+    /// The surface area, computed once when the object is built if its transforms are not
+    /// animated and its material emits a total power.
+    #[serde(skip)]
+    area: Option<f32>,
 }
 
 impl Renderable for Object {
-    fn intersection(&self, ray: Ray) -> Option<(f32, Intersection)> {
-        // Transform the ray by the inverse of the Object's transforms.
-        let ray_t = self
-            .transforms
-            .iter()
-            .rev()
-            .fold(ray, |r, t| t.inverse().transform_ray(r));
+    fn intersection(&self, ray: Ray, tmin: f32) -> Option<(f32, Intersection)> {
+        // Transform the ray by the inverse of the Object's transforms at the ray's time.
+        let affine = self.transforms.at(ray.time);
+        let ray_t = affine.ray_to_local(ray);
 
         // Find the ray-object intersection in the internal object's local object-space.
-        let (t, local) = self.object.intersection(ray_t)?;
+        let (t, local) = self.object.intersection(ray_t, tmin)?;
+
+        Some((t, to_world(&affine, local)))
+    }
+}
+
+impl Object {
+    pub fn new(
+        object: Shape,
+        material: Material,
+        transforms: TransformStack,
+        medium: Option<Medium>,
+    ) -> Self {
+        let area = (material.emits_power() && !transforms.is_animated())
+            .then(|| object.area(&transforms.at(0.0)));
+        Object {
+            object,
+            material,
+            transforms,
+            medium,
+            area,
+        }
+    }
+
+    /// Return the radiance emitted by this object at the `intersection` at the given `time`.
+    pub fn emitted(&self, intersection: &Intersection, time: f32) -> Vector3 {
+        self.material.emitted(
+            intersection,
+            || {
+                self.area
+                    .unwrap_or_else(|| self.object.area(&self.transforms.at(time)))
+            },
+            time,
+        )
+    }
+}
+
+/// An emissive object placed in the world by `affine`, from which light paths can start.
+pub struct Emitter<'a> {
+    pub object: &'a Object,
+    pub affine: Affine,
+}
+
+impl Emitter<'_> {
+    /// Sample a point on the surface of the emitter, returning the point, its outward normal,
+    /// its surface coordinates and the pdf per unit area with which it was sampled.
+    pub fn sample(&self) -> (Vector3, Vector3, (f32, f32), f32) {
+        let (local, normal, pdf) = self.object.object.sample();
+        (
+            self.affine.point_to_world(local),
+            self.affine.normal_to_world(normal),
+            self.object.object.uv(local),
+            pdf / area_scale(&self.affine, normal),
+        )
+    }
+
+    /// Return the pdf per unit area with which `sample` picks `position`, which is zero if it
+    /// does not lie on the surface of the emitter.
+    pub fn pdf(&self, position: Vector3) -> f32 {
+        let local = self.affine.inverse().transform_point(position);
+        let shape = &self.object.object;
+        let pdf = shape.pdf(local);
+        if pdf > 0.0 {
+            pdf / area_scale(&self.affine, shape.outward(local))
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Return the factor by which `affine` scales areas on a surface with the local `normal`.
+fn area_scale(affine: &Affine, normal: Vector3) -> f32 {
+    let inverse_transpose = affine.inverse().transpose();
+    affine.matrix().determinant().abs() * inverse_transpose.transform_vector(normal).norm()
+}
+
+/// Transform an intersection from the local space of a set of transforms into their parent
+/// space.
+fn to_world(affine: &Affine, local: Intersection) -> Intersection {
+    Intersection {
+        position: affine.point_to_world(local.position),
+        normal: affine.normal_to_world(local.normal),
+        ..local
+    }
+}
+
+/// A node in the scene graph. A node is either a single object, a group of child nodes sharing
+/// a transform stack, or a reference to a named definition in the scene.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged, try_from = "RawNode")]
+pub enum Node {
+    Object(Object),
+    Group(Group),
+    Instance(Instance),
+}
+
+/// A list of child nodes that are transformed together by the group's own transforms, which are
+/// applied after the transforms of each child.
+#[derive(Serialize, Deserialize)]
+pub struct Group {
+    pub children: Vec<Node>,
+    #[serde(default)]
+    pub transforms: TransformStack,
+}
+
+/// A placement of a named definition from the scene. All instances of a definition share the
+/// same node, so its data is only stored once.
+#[derive(Serialize, Deserialize)]
+pub struct Instance {
+    pub instance: String,
+    #[serde(default)]
+    pub transforms: TransformStack,
+    #[serde(skip)]
+    pub target: Option<Arc<Node>>,
+}
+
+impl Node {
+    /// Find the closest intersection further than `tmin` along the ray with this node or any of
+    /// its children, returning the distance, the world-space intersection, and the object hit.
+    pub fn hit(&self, ray: Ray, tmin: f32) -> Option<(f32, Intersection, &Object)> {
+        match self {
+            Node::Object(object) => object
+                .intersection(ray, tmin)
+                .map(|(t, int)| (t, int, object)),
+            Node::Group(group) => {
+                let affine = group.transforms.at(ray.time);
+                let (t, local, object) = closest(&group.children, affine.ray_to_local(ray), tmin)?;
+                Some((t, to_world(&affine, local), object))
+            }
+            Node::Instance(instance) => {
+                // Instances that were never linked to their definition are invisible.
+                let target = instance.target.as_ref()?;
+                let affine = instance.transforms.at(ray.time);
+                let (t, local, object) = target.hit(affine.ray_to_local(ray), tmin)?;
+                Some((t, to_world(&affine, local), object))
+            }
+        }
+    }
+
+    /// Collect every emissive object inside this node, placed in the world at `time` by the
+    /// transforms of the node and then `parent`.
+    pub fn emitters<'a>(&'a self, time: f32, parent: &Affine, emitters: &mut Vec<Emitter<'a>>) {
+        match self {
+            Node::Object(object) => {
+                if let Material::Emissive { .. } = object.material {
+                    emitters.push(Emitter {
+                        object,
+                        affine: object.transforms.at(time).then(parent),
+                    });
+                }
+            }
+            Node::Group(group) => {
+                let affine = group.transforms.at(time).then(parent);
+                for child in &group.children {
+                    child.emitters(time, &affine, emitters);
+                }
+            }
+            Node::Instance(instance) => {
+                if let Some(target) = &instance.target {
+                    target.emitters(time, &instance.transforms.at(time).then(parent), emitters);
+                }
+            }
+        }
+    }
+
+    /// Resolve every instance inside this node against the given `definitions`.
+    pub fn link(&mut self, definitions: &HashMap<String, Arc<Node>>) -> Result<(), String> {
+        match self {
+            Node::Object(_) => Ok(()),
+            Node::Group(group) => group
+                .children
+                .iter_mut()
+                .try_for_each(|child| child.link(definitions)),
+            Node::Instance(instance) => {
+                let target = definitions
+                    .get(&instance.instance)
+                    .ok_or_else(|| format!("Unknown definition `{}`.", instance.instance))?;
+                instance.target = Some(Arc::clone(target));
+                Ok(())
+            }
+        }
+    }
+
+    /// Load any images used by the materials in this node, resolving paths relative to `base`.
+    /// Definitions are loaded on their own, so instances are left as they are.
+    pub fn load(&mut self, base: &Path) -> Result<(), ImageError> {
+        match self {
+            Node::Object(object) => object.material.load(base),
+            Node::Group(group) => group
+                .children
+                .iter_mut()
+                .try_for_each(|child| child.load(base)),
+            Node::Instance(_) => Ok(()),
+        }
+    }
+
+    /// Check that every object in this node at `path` and its children can be rendered.
+    pub fn validate(&self, path: &str) -> Result<(), SceneError> {
+        match self {
+            Node::Object(object) => {
+                object
+                    .transforms
+                    .validate(&format!("{}.transforms", path))?;
+                if let Some(medium) = &object.medium {
+                    medium.validate(&format!("{}.medium", path))?;
+                }
+                object.material.validate(&format!("{}.material", path))
+            }
+            Node::Group(group) => {
+                group.transforms.validate(&format!("{}.transforms", path))?;
+                group
+                    .children
+                    .iter()
+                    .enumerate()
+                    .try_for_each(|(i, child)| child.validate(&format!("{}.children[{}]", path, i)))
+            }
+            Node::Instance(instance) => instance
+                .transforms
+                .validate(&format!("{}.transforms", path)),
+        }
+    }
+
+    /// Return the names of all definitions referenced directly by this node or its children.
+    fn references(&self) -> Vec<&str> {
+        match self {
+            Node::Object(_) => Vec::new(),
+            Node::Group(group) => group.children.iter().flat_map(Node::references).collect(),
+            Node::Instance(instance) => vec![instance.instance.as_str()],
+        }
+    }
+}
+
+/// Find the closest intersection further than `tmin` between a ray and a list of nodes.
+///
+/// Every node is tested, as there is no acceleration structure yet. Nodes have no bounds
+/// either, so a bounding volume hierarchy would first need bounds that enclose each animated
+/// node over the whole shutter interval, since rays are cast at different times.
+pub fn closest(nodes: &[Node], ray: Ray, tmin: f32) -> Option<(f32, Intersection, &Object)> {
+    nodes
+        .iter()
+        .filter_map(|n| n.hit(ray, tmin))
+        .fold(None, |c, (t, int, o)| match &c {
+            Some((t_c, _, _)) if *t_c <= t => c,
+            _ => Some((t, int, o)),
+        })
+}
+
+/// Link a set of named definitions, which may themselves contain instances of other
+/// definitions, so that every instance refers to its shared node. Definitions that are already
+/// shared were linked before and are kept as they are. Fails if a definition is unknown or
+/// instances itself.
+pub fn link_definitions(
+    mut definitions: HashMap<String, Arc<Node>>,
+) -> Result<HashMap<String, Arc<Node>>, String> {
+    fn resolve(
+        name: &str,
+        pending: &mut HashMap<String, Arc<Node>>,
+        linked: &mut HashMap<String, Arc<Node>>,
+        stack: &mut Vec<String>,
+    ) -> Result<(), String> {
+        if linked.contains_key(name) {
+            return Ok(());
+        }
+        if stack.iter().any(|s| s == name) {
+            return Err(format!("Definition `{}` instances itself.", name));
+        }
+
+        let mut node = pending
+            .remove(name)
+            .ok_or_else(|| format!("Unknown definition `{}`.", name))?;
+
+        if let Some(inner) = Arc::get_mut(&mut node) {
+            stack.push(name.to_string());
+            for reference in inner.references() {
+                resolve(reference, pending, linked, stack)?;
+            }
+            stack.pop();
+
+            inner.link(linked)?;
+        }
+
+        linked.insert(name.to_string(), node);
+        Ok(())
+    }
+
+    let names: Vec<String> = definitions.keys().cloned().collect();
+    let mut linked = HashMap::new();
+    for name in names {
+        resolve(&name, &mut definitions, &mut linked, &mut Vec::new())?;
+    }
+
+    Ok(linked)
+}
 
-        // Transform the local object-space position to world space.
-        let position = self
-            .transforms
-            .iter()
-            .fold(local.position, |p, t| t.transform(p));
+/// The on-disk form of a `Node`, which is told apart by which of its keys are present.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawNode {
+    object: Option<Shape>,
+    material: Option<Material>,
+    medium: Option<Medium>,
+    children: Option<Vec<Node>>,
+    instance: Option<String>,
+    #[serde(default)]
+    transforms: TransformStack,
+}
+
+impl TryFrom<RawNode> for Node {
+    type Error = String;
 
-        // Transform the local object-space normal to world space.
-        let normal = self
-            .transforms
-            .iter()
-            .fold(local.normal, |p, t| match t {
-                Transform::Translate(_) => p,
-                Transform::Rotate(_, _) => t.transform(p),
-                Transform::Scale(_) => t.inverse().transform(p),
-            })
-            .normalized();
+    fn try_from(raw: RawNode) -> Result<Self, Self::Error> {
+        let RawNode {
+            object,
+            material,
+            medium,
+            children,
+            instance,
+            transforms,
+        } = raw;
 
-        Some((t, Intersection { position, normal }))
+        if medium.is_some() && !matches!(object, Some(Shape::Sphere)) {
+            return Err("Only closed objects such as spheres can contain a `medium`.".to_string());
+        }
+
+        match (object, material, children, instance) {
+            (Some(object), Some(material), None, None) => Ok(Node::Object(Object::new(
+                object, material, transforms, medium,
+            ))),
+            (Some(_), None, None, None) => Err("Object is missing a `material`.".to_string()),
+            (None, None, Some(children), None) => Ok(Node::Group(Group {
+                children,
+                transforms,
+            })),
+            (None, None, None, Some(instance)) => Ok(Node::Instance(Instance {
+                instance,
+                transforms,
+                target: None,
+            })),
+            _ => Err(
+                "A node must have exactly one of `object` (with a `material`), `children` or `instance`."
+                    .to_string(),
+            ),
+        }
     }
 }
 
 /// An enum containing unit-size shapes that have analytical line-shape intersections.
+/// - Sphere: The unit sphere around the origin, with `u` running around the `y` axis from `-z`
+///   and `v` running from the bottom pole to the top pole.
+/// - Plane: The square from `(-1, -1)` to `(1, 1)` in the `z = 0` plane, facing `+z`, with
+///   `(u, v)` running from zero to one across it.
 #[derive(Serialize, Deserialize)]
 pub enum Shape {
     Sphere,
     Plane,
 }
 
+impl Shape {
+    /// Return the surface area of this shape after it is placed by `affine`.
+    pub fn area(&self, affine: &Affine) -> f32 {
+        match self {
+            Shape::Sphere => ellipsoid_area(affine),
+            Shape::Plane => 4.0 * area_scale(affine, Vector3::new(0.0, 0.0, 1.0)),
+        }
+    }
+
+    /// Return the surface coordinates of the `point` on this shape.
+    pub fn uv(&self, point: Vector3) -> (f32, f32) {
+        match self {
+            Shape::Sphere => {
+                let outward = point.normalized();
+                (
+                    0.5 + outward.x().atan2(outward.z()) / TAU,
+                    0.5 + outward.y().clamp(-1.0, 1.0).asin() / PI,
+                )
+            }
+            Shape::Plane => (0.5 * (point.x() + 1.0), 0.5 * (point.y() + 1.0)),
+        }
+    }
+
+    /// Return the outward normal of this shape at the `point` on its surface.
+    pub fn outward(&self, point: Vector3) -> Vector3 {
+        match self {
+            Shape::Sphere => point.normalized(),
+            Shape::Plane => Vector3::new(0.0, 0.0, 1.0),
+        }
+    }
+
+    /// Sample a point uniformly on the surface of this shape, returning the point, its outward
+    /// normal and the pdf per unit area with which it was sampled.
+    pub fn sample(&self) -> (Vector3, Vector3, f32) {
+        let point = match self {
+            Shape::Sphere => random::unit_sphere(),
+            Shape::Plane => Vector3::new(
+                2.0 * random::uniform() - 1.0,
+                2.0 * random::uniform() - 1.0,
+                0.0,
+            ),
+        };
+
+        (point, self.outward(point), self.pdf(point))
+    }
+
+    /// Return the pdf per unit area with which `sample` picks the `point`, which is zero if it
+    /// does not lie on the surface.
+    pub fn pdf(&self, point: Vector3) -> f32 {
+        let tolerance = 1.0e-3;
+        match self {
+            Shape::Sphere if (point.norm() - 1.0).abs() < tolerance => 1.0 / (4.0 * PI),
+            Shape::Plane
+                if point.z().abs() < tolerance
+                    && point.x().abs() <= 1.0 + tolerance
+                    && point.y().abs() <= 1.0 + tolerance =>
+            {
+                0.25
+            }
+            _ => 0.0,
+        }
+    }
+}
+
+/// Approximate the surface area of the unit sphere placed by `affine` with Thomsen's formula,
+/// which is within about one percent for any ellipsoid.
+fn ellipsoid_area(affine: &Affine) -> f32 {
+    // The semi-axes of the ellipsoid are the singular values of the linear part of the matrix,
+    // which are the square roots of the eigenvalues of `M^T M`.
+    let m = affine.matrix().rows();
+    let mut g = [[0.0f32; 3]; 3];
+    for (i, row) in g.iter_mut().enumerate() {
+        for (j, entry) in row.iter_mut().enumerate() {
+            *entry = (0..3).map(|k| m[k][i] * m[k][j]).sum();
+        }
+    }
+
+    let [a, b, c] = symmetric_eigenvalues(g).map(|e| e.max(0.0).sqrt());
+    let p = 1.6075;
+    let mean = ((a * b).powf(p) + (a * c).powf(p) + (b * c).powf(p)) / 3.0;
+
+    4.0 * PI * mean.powf(1.0 / p)
+}
+
+/// Return the eigenvalues of a symmetric 3x3 matrix using the closed-form trigonometric
+/// solution of its characteristic polynomial.
+fn symmetric_eigenvalues(g: [[f32; 3]; 3]) -> [f32; 3] {
+    let off = g[0][1].powi(2) + g[0][2].powi(2) + g[1][2].powi(2);
+    let q = (g[0][0] + g[1][1] + g[2][2]) / 3.0;
+    let p = (((g[0][0] - q).powi(2) + (g[1][1] - q).powi(2) + (g[2][2] - q).powi(2) + 2.0 * off)
+        / 6.0)
+        .sqrt();
+    if p <= f32::EPSILON * q.abs() {
+        return [g[0][0], g[1][1], g[2][2]];
+    }
+
+    let b = |i: usize, j: usize| (g[i][j] - if i == j { q } else { 0.0 }) / p;
+    let det = b(0, 0) * (b(1, 1) * b(2, 2) - b(1, 2) * b(2, 1))
+        - b(0, 1) * (b(1, 0) * b(2, 2) - b(1, 2) * b(2, 0))
+        + b(0, 2) * (b(1, 0) * b(2, 1) - b(1, 1) * b(2, 0));
+    let phi = (det / 2.0).clamp(-1.0, 1.0).acos() / 3.0;
+
+    let largest = q + 2.0 * p * phi.cos();
+    let smallest = q + 2.0 * p * (phi + TAU / 3.0).cos();
+    [largest, 3.0 * q - largest - smallest, smallest]
+}
+
 impl Renderable for Shape {
-    fn intersection(&self, ray: Ray) -> Option<(f32, Intersection)> {
+    fn intersection(&self, ray: Ray, tmin: f32) -> Option<(f32, Intersection)> {
         match *self {
             Shape::Sphere => {
                 let a = ray.direction.squared_norm();
@@ -81,12 +535,29 @@ impl Renderable for Shape {
                     return None;
                 }
 
-                let t: f32 = (-b - d.sqrt()) / (2.0 * a);
+                // Rays starting inside the sphere hit it from the inside at the far root.
+                let near = (-b - d.sqrt()) / (2.0 * a);
+                let far = (-b + d.sqrt()) / (2.0 * a);
+                let t = if near > tmin { near } else { far };
+                if t <= tmin {
+                    return None;
+                }
 
                 let position = ray.origin + (ray.direction * t);
-                let normal = position.normalized();
+                let outward = position.normalized();
+                let uv = self.uv(position);
+                let front_face = ray.direction.dot(outward) < 0.0;
+                let normal = if front_face { outward } else { -outward };
 
-                Some((t, Intersection { position, normal }))
+                Some((
+                    t,
+                    Intersection {
+                        position,
+                        normal,
+                        uv,
+                        front_face,
+                    },
+                ))
             }
             Shape::Plane => {
                 let n = Vector3::new(0.0, 0.0, 1.0);
@@ -98,19 +569,258 @@ impl Renderable for Shape {
                 }
 
                 let t = a / b;
-                if t < 0.0 {
+                if t <= tmin {
                     return None;
                 }
 
                 let position = ray.origin + (ray.direction * t);
-                if position.x() > 1.0 || position.y() > 1.0 {
+                if position.x().abs() > 1.0 || position.y().abs() > 1.0 {
                     return None;
                 }
 
                 let normal = if b < 0.0 { n } else { -n };
+                let uv = self.uv(position);
 
-                Some((t, Intersection { position, normal }))
+                Some((
+                    t,
+                    Intersection {
+                        position,
+                        normal,
+                        uv,
+                        front_face: b < 0.0,
+                    },
+                ))
             }
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::transform::Transform;
+
+    fn sphere(transforms: Vec<Transform>) -> Node {
+        Node::Object(Object::new(
+            Shape::Sphere,
+            Material::Diffuse {
+                color: Vector3::ones().into(),
+            },
+            transforms.into(),
+            None,
+        ))
+    }
+
+    #[test]
+    fn test_nested_groups() {
+        let offset = Vector3::new(1.0, 0.0, 0.0);
+        let inner = Node::Group(Group {
+            children: vec![sphere(vec![Transform::Translate(offset)])],
+            transforms: vec![Transform::Scale(Vector3::new(2.0, 2.0, 2.0))].into(),
+        });
+        let outer = Node::Group(Group {
+            children: vec![inner],
+            transforms: vec![Transform::Translate(Vector3::new(0.0, 0.0, 10.0))].into(),
+        });
+
+        // The sphere has radius 2 and is centered at (2, 0, 10) after all transforms.
+        let ray = Ray::new(Vector3::new(2.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 1.0));
+        let (t, int, _) = outer
+            .hit(ray, 0.0)
+            .expect("Ray should hit the nested sphere.");
+        let expected = Vector3::new(2.0, 0.0, 8.0);
+        assert!(
+            (t - 8.0).abs() < 1.0e-4 && (int.position - expected).norm() < 1.0e-4,
+            "Node::hit() failed through nested groups. Expected {} at t = {}, got {} at t = {}.",
+            expected,
+            8.0,
+            int.position,
+            t
+        );
+    }
+
+    #[test]
+    fn test_instances() {
+        let mut definitions = HashMap::new();
+        definitions.insert("ball".to_string(), Arc::new(sphere(Vec::new())));
+        definitions.insert(
+            "pair".to_string(),
+            Arc::new(Node::Group(Group {
+                children: vec![Node::Instance(Instance {
+                    instance: "ball".to_string(),
+                    transforms: vec![Transform::Translate(Vector3::new(0.0, 0.0, 5.0))].into(),
+                    target: None,
+                })],
+                transforms: TransformStack::default(),
+            })),
+        );
+        let definitions = link_definitions(definitions).expect("Definitions should link.");
+
+        let mut node = Node::Instance(Instance {
+            instance: "pair".to_string(),
+            transforms: vec![Transform::Translate(Vector3::new(0.0, 3.0, 0.0))].into(),
+            target: None,
+        });
+        node.link(&definitions).expect("Instance should link.");
+
+        let ray = Ray::new(Vector3::new(0.0, 3.0, 0.0), Vector3::new(0.0, 0.0, 1.0));
+        let (t, _, _) = node
+            .hit(ray, 0.0)
+            .expect("Ray should hit the instanced sphere.");
+        assert!(
+            (t - 4.0).abs() < 1.0e-4,
+            "Node::hit() failed through instances. Expected t = {}, got t = {}.",
+            4.0,
+            t
+        );
+
+        let mut cyclic = HashMap::new();
+        cyclic.insert(
+            "loop".to_string(),
+            Arc::new(Node::Instance(Instance {
+                instance: "loop".to_string(),
+                transforms: TransformStack::default(),
+                target: None,
+            })),
+        );
+        assert!(
+            link_definitions(cyclic).is_err(),
+            "link_definitions() should reject a definition that instances itself."
+        );
+    }
+
+    #[test]
+    fn test_area() {
+        let sphere = |transforms: Vec<Transform>| Shape::Sphere.area(&Affine::new(&transforms));
+
+        let expected = 16.0 * PI;
+        let area = sphere(vec![Transform::Scale(Vector3::new(2.0, 2.0, 2.0))]);
+        assert!(
+            (area - expected).abs() < 1.0e-3 * expected,
+            "Shape::area() failed on a scaled sphere. Expected {}, got {}.",
+            expected,
+            area
+        );
+
+        // A prolate spheroid with semi-axes 1, 1 and 2, turned so its long axis is diagonal.
+        let e: f32 = 0.75f32.sqrt();
+        let expected = 2.0 * PI * (1.0 + 2.0 / e * e.asin());
+        let area = sphere(vec![
+            Transform::Scale(Vector3::new(1.0, 1.0, 2.0)),
+            Transform::Rotate(Vector3::new(1.0, 1.0, 0.0).normalized(), 0.7),
+            Transform::Translate(Vector3::new(3.0, 0.0, 0.0)),
+        ]);
+        assert!(
+            (area - expected).abs() < 0.015 * expected,
+            "Shape::area() failed on a rotated spheroid. Expected {}, got {}.",
+            expected,
+            area
+        );
+    }
+
+    #[test]
+    fn test_plane_sampling() {
+        // Every sample on a plane scaled to 4 by 2 lands on it, with a uniform pdf.
+        let affine =
+            TransformStack::from(vec![Transform::Scale(Vector3::new(2.0, 1.0, 1.0))]).at(0.0);
+        let object = Object::new(
+            Shape::Plane,
+            Material::Emissive {
+                color: Vector3::ones().into(),
+                intensity: 1.0,
+                units: Default::default(),
+                two_sided: true,
+                texture: None,
+            },
+            TransformStack::default(),
+            None,
+        );
+        let emitter = Emitter {
+            object: &object,
+            affine,
+        };
+        for _ in 0..1000 {
+            let (point, _, _, pdf) = emitter.sample();
+            assert!(
+                point.x().abs() <= 2.0 && point.y().abs() <= 1.0 && pdf == 0.125,
+                "Emitter::sample() failed on a plane. Expected a point on the plane with pdf {}, \
+                 got {} with pdf {}.",
+                0.125,
+                point,
+                pdf
+            );
+        }
+    }
+
+    #[test]
+    fn test_emission() {
+        let light: Node = serde_json::from_str(
+            r#"{
+                "object": "Sphere",
+                "material": {"Emissive": {
+                    "color": {"x": 1.0, "y": 1.0, "z": 1.0},
+                    "intensity": 10.0,
+                    "units": "Power",
+                    "two_sided": false
+                }},
+                "transforms": [{"Scale": {"x": 2.0, "y": 2.0, "z": 2.0}}]
+            }"#,
+        )
+        .expect("Emissive sphere should parse.");
+        let Node::Object(light) = light else {
+            panic!("Emissive sphere should parse as an object.");
+        };
+
+        let outside = Ray::new(Vector3::new(0.0, 0.0, -5.0), Vector3::new(0.0, 0.0, 1.0));
+        let (_, int) = light
+            .intersection(outside, 0.0)
+            .expect("Ray should hit the light.");
+        let expected = 10.0 / (PI * 16.0 * PI);
+        let radiance = light.emitted(&int, 0.0).x();
+        assert!(
+            (radiance - expected).abs() < 1.0e-3 * expected,
+            "Object::emitted() failed on a power light. Expected {}, got {}.",
+            expected,
+            radiance
+        );
+
+        let back = Intersection {
+            front_face: false,
+            ..int
+        };
+        let radiance = light.emitted(&back, 0.0);
+        assert_eq!(
+            Vector3::zeros(),
+            radiance,
+            "Object::emitted() failed on the back of a one-sided light. Expected {}, got {}.",
+            Vector3::zeros(),
+            radiance
+        );
+
+        // A two-sided square light four units on a side, turned to face down.
+        let plane: Node = serde_json::from_str(
+            r#"{"object": "Plane", "material": {"Emissive": {
+                "color": {"x": 1.0, "y": 1.0, "z": 1.0}, "intensity": 8.0, "units": "Power"
+            }}, "transforms": [
+                {"Scale": {"x": 2.0, "y": 2.0, "z": 1.0}},
+                {"Rotate": [{"x": 1.0, "y": 0.0, "z": 0.0}, {"degrees": 90.0}]}
+            ]}"#,
+        )
+        .expect("Emissive plane should parse.");
+        let Node::Object(plane) = plane else {
+            panic!("Emissive plane should parse as an object.");
+        };
+        let below = Ray::new(Vector3::new(0.5, -3.0, 0.5), Vector3::new(0.0, 1.0, 0.0));
+        let (_, int) = plane
+            .intersection(below, 0.0)
+            .expect("Ray should hit the light.");
+        let expected = 8.0 / (2.0 * PI * 16.0);
+        let radiance = plane.emitted(&int, 0.0).x();
+        assert!(
+            (radiance - expected).abs() < 1.0e-3 * expected,
+            "Object::emitted() failed on a power plane. Expected {}, got {}.",
+            expected,
+            radiance
+        );
+    }
+}
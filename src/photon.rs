@@ -0,0 +1,259 @@
+use rayon::prelude::*;
+
+use std::f32::consts::PI;
+
+use crate::integrator::{first_hit, Integrator};
+use crate::kdtree::KdTree;
+use crate::material::{Material, Scatterer};
+use crate::object::{Emitter, Intersection};
+use crate::random;
+use crate::scene::Scene;
+use crate::spectrum::Wavelengths;
+use crate::vector::Vector3;
+use crate::Ray;
+
+/// The first random stream used for tracing photons in a seeded scene.
+const PHOTON_STREAMS: u64 = 1 << 32;
+
+/// A photon mapper. Before rendering, `photons` photons are emitted from the emissive objects
+/// in the scene and followed for up to `max_depth` bounces, and every photon that lands on a
+/// surface that is not perfectly specular is stored in a kd-tree. Camera rays then follow
+/// perfectly specular bounces until they reach another surface, where the light arriving from
+/// emissive objects is estimated from the density of photons within `radius`. This finds
+/// caustics seen through or cast by glass, which camera paths alone cannot.
+///
+/// Punctual lights and the environment do not emit photons, so they only light surfaces
+/// directly. Photons are emitted at the scene's time, ignoring the camera shutter.
+pub struct PhotonMapper {
+    pub radius: f32,
+    pub max_depth: usize,
+    map: KdTree<Photon>,
+}
+
+/// A photon stored where it landed, with the unit `direction` it arrived from and the linear
+/// sRGB `power` it carries.
+struct Photon {
+    direction: Vector3,
+    power: Vector3,
+}
+
+impl PhotonMapper {
+    /// Emit `photons` photons into the `scene` and store them in a map for rendering.
+    pub fn new(scene: &Scene, photons: usize, radius: f32, max_depth: usize) -> Self {
+        let emitters = scene.emitters(scene.time);
+        let stored = (0..photons)
+            .into_par_iter()
+            .flat_map_iter(|i| {
+                // Photons draw from their own streams, after those of the image's tiles.
+                if let Some(seed) = scene.seed {
+                    random::reseed(seed, PHOTON_STREAMS + i as u64);
+                }
+                trace_photon(scene, &emitters, photons, max_depth)
+            })
+            .collect();
+
+        PhotonMapper {
+            radius,
+            max_depth,
+            map: KdTree::new(stored),
+        }
+    }
+
+    /// Return the number of photons stored in the map.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Return whether no photons were stored in the map.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Estimate the light from emissive objects that `material` at `intersection` scatters
+    /// towards the origin of `ray`, from the photons near it.
+    fn estimate(&self, ray: &Ray, intersection: &Intersection, material: &Material) -> Vector3 {
+        let view = -ray.direction;
+        let mut flux = Vector3::zeros();
+        self.map
+            .within(intersection.position, self.radius, |_, photon| {
+                let f = material.eval(view, photon.direction, intersection.normal, ray.time);
+                flux = flux + f.cwise_mul(photon.power);
+            });
+
+        ray.upsample(flux) * (1.0 / (PI * self.radius * self.radius))
+    }
+}
+
+/// Emit one of `photons` photons from one of the `emitters` in the `scene`, returning where it
+/// was stored on each of up to `max_depth` bounces.
+fn trace_photon(
+    scene: &Scene,
+    emitters: &[Emitter],
+    photons: usize,
+    max_depth: usize,
+) -> Vec<(Vector3, Photon)> {
+    if emitters.is_empty() {
+        return Vec::new();
+    }
+
+    let n = emitters.len();
+    let emitter = &emitters[((random::uniform() * n as f32) as usize).min(n - 1)];
+    let (position, normal, uv, pdf) = emitter.sample();
+    if pdf <= 0.0 || !pdf.is_finite() {
+        return Vec::new();
+    }
+
+    // Leave the emitter in a cosine-weighted direction from one of its emitting faces.
+    let two_sided = matches!(
+        emitter.object.material,
+        Material::Emissive {
+            two_sided: true,
+            ..
+        }
+    );
+    let (side, sides) = match two_sided {
+        true if random::uniform() < 0.5 => (-normal, 2.0),
+        true => (normal, 2.0),
+        false => (normal, 1.0),
+    };
+    let direction = random::cosine_hemisphere(side);
+    let mut ray = Ray {
+        origin: position,
+        direction,
+        time: scene.time,
+        wavelengths: scene.spectral.then(Wavelengths::sample),
+    };
+
+    let intersection = Intersection {
+        position,
+        normal,
+        uv,
+        front_face: direction.dot(normal) > 0.0,
+    };
+    let emitted = ray.upsample(emitter.object.emitted(&intersection, scene.time));
+
+    // The cosine of the direction cancels against its pdf, leaving the area of the hemisphere.
+    let pdf = pdf / n as f32;
+    let mut power = emitted * (sides * PI / (pdf * photons as f32));
+
+    let mut stored = Vec::new();
+    for _ in 0..max_depth {
+        let Some((_, intersection, object)) = first_hit(scene, ray, 1.0e-3) else {
+            break;
+        };
+        let material = &object.material;
+        let Some((bounce, sample, weight)) = material.bounce(ray, &intersection) else {
+            break;
+        };
+
+        if !sample.specular {
+            let rgb = match &ray.wavelengths {
+                Some(wavelengths) => wavelengths.to_rgb(power),
+                None => power,
+            };
+            let photon = Photon {
+                direction: -ray.direction,
+                power: rgb,
+            };
+            stored.push((intersection.position, photon));
+        }
+
+        power = power.cwise_mul(material.importance_weight(&ray, &intersection, &sample, weight));
+        if power == Vector3::zeros() {
+            break;
+        }
+        ray = bounce;
+    }
+
+    stored
+}
+
+impl Integrator for PhotonMapper {
+    fn radiance(&self, scene: &Scene, ray: Ray) -> Vector3 {
+        let mut ray = ray;
+        let mut tmin = 0.0;
+        let mut beta = Vector3::ones();
+        let mut radiance = Vector3::zeros();
+
+        for _ in 0..=self.max_depth {
+            let Some((_, intersection, object)) = first_hit(scene, ray, tmin) else {
+                let escaped = ray.upsample(scene.environment.radiance(ray.direction));
+                return radiance + beta.cwise_mul(escaped);
+            };
+
+            let emitted = ray.upsample(object.emitted(&intersection, ray.time));
+            radiance = radiance + beta.cwise_mul(emitted);
+
+            let material = &object.material;
+            match material.bounce(ray, &intersection) {
+                Some((bounce, sample, weight)) if sample.specular => {
+                    beta = beta.cwise_mul(weight);
+                    ray = bounce;
+                    tmin = 1.0e-3;
+                }
+                Some(_) => {
+                    let scatterer = Scatterer::Surface {
+                        material,
+                        normal: intersection.normal,
+                    };
+                    let direct =
+                        scene.direct_lighting(&scatterer, ray, intersection.position, false, None);
+                    let photons = self.estimate(&ray, &intersection, material);
+                    return radiance + beta.cwise_mul(direct + photons);
+                }
+                None => break,
+            }
+        }
+
+        radiance
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_irradiance() {
+        // A small emissive ball high above a large white floor gives it a known irradiance
+        // near the point below it.
+        let mut scene: Scene = serde_json::from_str(
+            r#"{
+                "camera": {"focal_len": 1.0, "width": 1.0},
+                "objects": [
+                    {
+                        "object": "Sphere",
+                        "material": {"Diffuse": {"color": {"x": 1.0, "y": 1.0, "z": 1.0}}},
+                        "transforms": [
+                            {"Scale": {"x": 100.0, "y": 100.0, "z": 100.0}},
+                            {"Translate": {"x": 0.0, "y": -100.0, "z": 0.0}}
+                        ]
+                    },
+                    {
+                        "object": "Sphere",
+                        "material": {"Emissive": {"color": {"x": 1.0, "y": 1.0, "z": 1.0}, "intensity": 1.0}},
+                        "transforms": [
+                            {"Scale": {"x": 0.5, "y": 0.5, "z": 0.5}},
+                            {"Translate": {"x": 0.0, "y": 4.0, "z": 0.0}}
+                        ]
+                    }
+                ]
+            }"#,
+        )
+        .unwrap();
+        scene.link().unwrap();
+
+        // A sphere of radiance L with radius r at distance d gives irradiance pi L r^2 / d^2
+        // to a surface facing it, which a white Lambertian surface reflects as E / pi.
+        let expected = 0.25 / 16.0;
+        let mapper = PhotonMapper::new(&scene, 400000, 0.5, 1);
+        let ray = Ray::new(Vector3::new(0.0, 1.0, 0.0), Vector3::new(0.0, -1.0, 0.0));
+        let radiance = mapper.radiance(&scene, ray).x();
+        assert!(
+            (radiance - expected).abs() < 0.1 * expected,
+            "PhotonMapper::radiance() failed below a light. Expected {}, got {}.",
+            expected,
+            radiance
+        );
+    }
+}
@@ -0,0 +1,675 @@
+use rayon::ThreadPoolBuilder;
+
+use std::cell::Cell;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::vector::Vector3;
+
+/// A callback that is told how far a render has got.
+pub type ProgressCallback = Box<dyn Fn(&Progress) + Send + Sync>;
+
+/// Options that control a render beyond its resolution and sample count. If a `region` is
+/// given, only the pixels inside it are rendered, and the image is either `crop`ped to the
+/// region or left at full size with black outside it. The image is split into square tiles of
+/// `tile_size` pixels, which are handed out in the given `order` to
+/// `threads` workers, or one per core if it is `None`. `progress` is called from the workers
+/// each time a tile is finished, and the render stops early once `cancel` is cancelled.
+pub struct RenderOptions {
+    pub region: Option<Region>,
+    pub crop: bool,
+    pub tile_size: u32,
+    pub order: TileOrder,
+    pub threads: Option<usize>,
+    pub progress: Option<ProgressCallback>,
+    pub cancel: CancelToken,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        RenderOptions {
+            region: None,
+            crop: false,
+            tile_size: 32,
+            order: TileOrder::default(),
+            threads: None,
+            progress: None,
+            cancel: CancelToken::default(),
+        }
+    }
+}
+
+impl RenderOptions {
+    /// Run `f` on a thread pool with the chosen number of threads.
+    pub(crate) fn install<R: Send>(&self, f: impl FnOnce() -> R + Send) -> R {
+        match self.threads {
+            Some(threads) => match ThreadPoolBuilder::new().num_threads(threads).build() {
+                Ok(pool) => pool.install(f),
+                Err(_) => f(),
+            },
+            None => f(),
+        }
+    }
+}
+
+/// The order in which tiles are rendered.
+///
+/// - Scanline: Row by row from the top left.
+/// - Spiral: Outwards from the center of the image, where the subject usually is.
+/// - Hilbert: Along a Hilbert curve, so that consecutive tiles are mostly neighbours.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TileOrder {
+    Scanline,
+    #[default]
+    Spiral,
+    Hilbert,
+}
+
+impl FromStr for TileOrder {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "scanline" => Ok(TileOrder::Scanline),
+            "spiral" => Ok(TileOrder::Spiral),
+            "hilbert" => Ok(TileOrder::Hilbert),
+            _ => Err(format!("Unknown tile order {}.", name)),
+        }
+    }
+}
+
+/// A rectangle of pixels with its top left corner at (`x`, `y`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Tile {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// A rectangle of an image to render, either in pixels or as fractions of the image's width and
+/// height, with its top left corner at (`x`, `y`).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Region {
+    Pixels {
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    },
+    Normalized {
+        x: f32,
+        y: f32,
+        width: f32,
+        height: f32,
+    },
+}
+
+impl Region {
+    /// Return the pixels covered by the region in an image of `xres` by `yres` pixels, clipped
+    /// to the image. Normalized regions cover every pixel they overlap.
+    pub fn window(&self, xres: u32, yres: u32) -> Tile {
+        let (x0, y0, x1, y1) = match *self {
+            Region::Pixels {
+                x,
+                y,
+                width,
+                height,
+            } => (x, y, x.saturating_add(width), y.saturating_add(height)),
+            Region::Normalized {
+                x,
+                y,
+                width,
+                height,
+            } => {
+                let to_pixels = |u: f32, res: u32| (u.clamp(0.0, 1.0) * res as f32) as u32;
+                let to_pixels_up =
+                    |u: f32, res: u32| (u.clamp(0.0, 1.0) * res as f32).ceil() as u32;
+                (
+                    to_pixels(x, xres),
+                    to_pixels(y, yres),
+                    to_pixels_up(x + width, xres),
+                    to_pixels_up(y + height, yres),
+                )
+            }
+        };
+
+        let (x0, y0) = (x0.min(xres), y0.min(yres));
+        Tile {
+            x: x0,
+            y: y0,
+            width: x1.min(xres).saturating_sub(x0),
+            height: y1.min(yres).saturating_sub(y0),
+        }
+    }
+}
+
+impl FromStr for Region {
+    type Err = String;
+
+    /// Parse a region given as `x,y,width,height` followed by its unit, either `px` for pixels
+    /// such as `16,16,64,32px` or `%` for percentages of the image such as `25,25,50,50%`.
+    fn from_str(region: &str) -> Result<Self, Self::Err> {
+        let error = || {
+            format!(
+                "Invalid region {}. Expected x,y,width,height followed by px or %, such as \
+                 16,16,64,32px or 25,25,50,50%.",
+                region
+            )
+        };
+        let (values, pixels) = match (region.strip_suffix("px"), region.strip_suffix('%')) {
+            (Some(values), _) => (values, true),
+            (_, Some(values)) => (values, false),
+            _ => return Err(error()),
+        };
+        let values: Vec<&str> = values.split(',').map(str::trim).collect();
+        let [x, y, width, height] = values[..] else {
+            return Err(error());
+        };
+
+        if pixels {
+            let parse = |value: &str| value.parse().map_err(|_| error());
+            Ok(Region::Pixels {
+                x: parse(x)?,
+                y: parse(y)?,
+                width: parse(width)?,
+                height: parse(height)?,
+            })
+        } else {
+            let parse = |value: &str| {
+                value
+                    .parse::<f32>()
+                    .map(|percent| percent / 100.0)
+                    .map_err(|_| error())
+            };
+            Ok(Region::Normalized {
+                x: parse(x)?,
+                y: parse(y)?,
+                width: parse(width)?,
+                height: parse(height)?,
+            })
+        }
+    }
+}
+
+/// Split an image of `xres` by `yres` pixels into square tiles of `size` pixels, clipped at the
+/// right and bottom edges, and return them in the given `order`.
+pub fn tiles(xres: u32, yres: u32, size: u32, order: TileOrder) -> Vec<Tile> {
+    let size = size.max(1);
+    let columns = xres.div_ceil(size);
+    let rows = yres.div_ceil(size);
+
+    let grid: Vec<(u32, u32)> = match order {
+        TileOrder::Scanline => (0..rows)
+            .flat_map(|row| (0..columns).map(move |column| (column, row)))
+            .collect(),
+        TileOrder::Spiral => spiral(columns, rows),
+        TileOrder::Hilbert => {
+            let n = columns.max(rows).next_power_of_two();
+            let mut grid: Vec<_> = (0..rows)
+                .flat_map(|row| (0..columns).map(move |column| (column, row)))
+                .collect();
+            grid.sort_by_key(|&(column, row)| hilbert_index(n, column, row));
+            grid
+        }
+    };
+
+    grid.into_iter()
+        .map(|(column, row)| Tile {
+            x: column * size,
+            y: row * size,
+            width: size.min(xres - column * size),
+            height: size.min(yres - row * size),
+        })
+        .collect()
+}
+
+/// Walk a square spiral outwards from the center of a grid of `columns` by `rows` cells,
+/// returning the cells that lie inside the grid in the order they are reached.
+fn spiral(columns: u32, rows: u32) -> Vec<(u32, u32)> {
+    let total = (columns * rows) as usize;
+    let mut cells = Vec::with_capacity(total);
+    let (mut x, mut y) = ((columns as i64 - 1) / 2, (rows as i64 - 1) / 2);
+    let directions = [(1, 0), (0, 1), (-1, 0), (0, -1)];
+
+    let visit = |x: i64, y: i64, cells: &mut Vec<(u32, u32)>| {
+        if (0..columns as i64).contains(&x) && (0..rows as i64).contains(&y) {
+            cells.push((x as u32, y as u32));
+        }
+    };
+
+    visit(x, y, &mut cells);
+    // The legs of the spiral grow by one cell every second turn.
+    let mut leg = 1;
+    let mut turn = 0;
+    while cells.len() < total {
+        let (dx, dy) = directions[turn % 4];
+        for _ in 0..leg {
+            x += dx;
+            y += dy;
+            visit(x, y, &mut cells);
+        }
+        turn += 1;
+        if turn % 2 == 0 {
+            leg += 1;
+        }
+    }
+
+    cells
+}
+
+/// Return the distance along a Hilbert curve filling an `n` by `n` grid, where `n` is a power
+/// of two, of the cell at (`x`, `y`).
+fn hilbert_index(n: u32, x: u32, y: u32) -> u64 {
+    let (mut x, mut y) = (x, y);
+    let mut index = 0;
+    let mut s = n / 2;
+    while s > 0 {
+        let rx = (x & s > 0) as u32;
+        let ry = (y & s > 0) as u32;
+        index += s as u64 * s as u64 * ((3 * rx) ^ ry) as u64;
+
+        // Rotate the quadrant so that the curve within it starts and ends in the right place.
+        if ry == 0 {
+            if rx == 1 {
+                x = s - 1 - (x & (s - 1));
+                y = s - 1 - (y & (s - 1));
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        s /= 2;
+    }
+
+    index
+}
+
+/// How far a render has got: `completed` of its `total` tiles are finished after `elapsed`.
+#[derive(Clone, Copy, Debug)]
+pub struct Progress {
+    pub completed: usize,
+    pub total: usize,
+    pub elapsed: Duration,
+}
+
+impl Progress {
+    /// Return the fraction of the render that is finished.
+    pub fn fraction(&self) -> f32 {
+        if self.total == 0 {
+            1.0
+        } else {
+            self.completed as f32 / self.total as f32
+        }
+    }
+
+    /// Estimate the time left, assuming the remaining tiles take as long as the finished ones.
+    pub fn eta(&self) -> Option<Duration> {
+        if self.completed == 0 {
+            return None;
+        }
+
+        let remaining = (self.total - self.completed) as f64 / self.completed as f64;
+        Some(self.elapsed.mul_f64(remaining))
+    }
+}
+
+/// A handle for stopping a render from another thread. Clones share the same state, so a clone
+/// can be kept to cancel a render that was given the original.
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Ask any render using this token to stop.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Statistics gathered over a render. `rays` counts every ray cast into the scene, including
+/// shadow rays, and `bounces` counts the scattering events along the paths traced for each
+/// pixel. `setup` is the time spent preparing the integrator, such as tracing photons, and
+/// `render` the time spent on the image itself. If the render was `cancelled`, the tiles that
+/// were not reached are black.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RenderStats {
+    pub rays: u64,
+    pub camera_rays: u64,
+    pub bounces: u64,
+    pub setup: Duration,
+    pub render: Duration,
+    pub cancelled: bool,
+}
+
+impl RenderStats {
+    /// Return the rays cast per second of rendering.
+    pub fn rays_per_second(&self) -> f64 {
+        let seconds = self.render.as_secs_f64();
+        if seconds > 0.0 {
+            self.rays as f64 / seconds
+        } else {
+            0.0
+        }
+    }
+
+    /// Return the average number of scattering events per camera ray.
+    pub fn average_path_length(&self) -> f64 {
+        if self.camera_rays > 0 {
+            self.bounces as f64 / self.camera_rays as f64
+        } else {
+            0.0
+        }
+    }
+}
+
+impl fmt::Display for RenderStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} rays ({:.2}M rays/s), {} camera rays, {:.2} bounces per path",
+            self.rays,
+            self.rays_per_second() / 1.0e6,
+            self.camera_rays,
+            self.average_path_length()
+        )?;
+        write!(
+            f,
+            "setup {:.2}s, render {:.2}s",
+            self.setup.as_secs_f64(),
+            self.render.as_secs_f64()
+        )?;
+        if self.cancelled {
+            write!(f, " (cancelled)")?;
+        }
+        Ok(())
+    }
+}
+
+/// Light that integrators find for pixels other than the one they are estimating, such as
+/// light subpaths connected straight to the camera, in an image of `width` by `height` pixels.
+/// Only splats inside the `window` being rendered are kept. Splats are summed in linear sRGB
+/// over every sample of the render, and the renderer divides the sums by the number of camera
+/// rays it cast before adding them to the image.
+pub struct Film {
+    pub width: u32,
+    pub height: u32,
+    window: Tile,
+    splats: Vec<[AtomicU32; 3]>,
+}
+
+impl Film {
+    pub fn new(width: u32, height: u32, window: Tile) -> Self {
+        let splats = (0..window.width * window.height)
+            .map(|_| [0.0f32; 3].map(|value| AtomicU32::new(value.to_bits())))
+            .collect();
+        Film {
+            width,
+            height,
+            window,
+            splats,
+        }
+    }
+
+    /// Add the linear sRGB `color` to the pixel at (`x`, `y`) if it is inside the window.
+    /// Threads add to the film at once, so each channel is updated atomically.
+    pub fn splat(&self, x: u32, y: u32, color: Vector3) {
+        let window = &self.window;
+        if !(window.x..window.x + window.width).contains(&x)
+            || !(window.y..window.y + window.height).contains(&y)
+        {
+            return;
+        }
+
+        let index = ((y - window.y) * window.width + x - window.x) as usize;
+        for (channel, value) in self.splats[index]
+            .iter()
+            .zip([color.x(), color.y(), color.z()])
+        {
+            if value != 0.0 {
+                let _ = channel.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| {
+                    Some((f32::from_bits(bits) + value).to_bits())
+                });
+            }
+        }
+    }
+
+    /// Return the sum of the splats at the pixel at (`x`, `y`) in the window.
+    pub fn get(&self, x: u32, y: u32) -> Vector3 {
+        let window = &self.window;
+        let index = ((y - window.y) * window.width + x - window.x) as usize;
+        let [r, g, b] = self.splats[index]
+            .each_ref()
+            .map(|channel| f32::from_bits(channel.load(Ordering::Relaxed)));
+        Vector3::new(r, g, b)
+    }
+}
+
+thread_local! {
+    /// The rays cast and scattering events on this thread, which the renderer reads before and
+    /// after each tile so that the threads never share a counter.
+    static COUNTERS: Cell<(u64, u64)> = const { Cell::new((0, 0)) };
+}
+
+/// Count a ray cast into the scene.
+pub(crate) fn count_ray() {
+    COUNTERS.with(|counters| {
+        let (rays, bounces) = counters.get();
+        counters.set((rays + 1, bounces));
+    });
+}
+
+/// Count a ray scattering off a surface or in a medium.
+pub(crate) fn count_bounce() {
+    COUNTERS.with(|counters| {
+        let (rays, bounces) = counters.get();
+        counters.set((rays, bounces + 1));
+    });
+}
+
+/// Return the rays cast and scattering events counted on this thread so far.
+pub(crate) fn counters() -> (u64, u64) {
+    COUNTERS.with(Cell::get)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::scene::Scene;
+    use crate::vector::Vector3;
+
+    #[test]
+    fn test_eta() {
+        let progress = Progress {
+            completed: 25,
+            total: 100,
+            elapsed: Duration::from_secs(10),
+        };
+        let expected = Some(Duration::from_secs(30));
+        let eta = progress.eta();
+        assert_eq!(
+            expected, eta,
+            "Progress::eta() failed on {:?}. Expected {:?}, got {:?}.",
+            progress, expected, eta
+        );
+    }
+
+    #[test]
+    fn test_tiles() {
+        for order in [TileOrder::Scanline, TileOrder::Spiral, TileOrder::Hilbert] {
+            let (xres, yres) = (70, 45);
+            let mut covered = vec![0; (xres * yres) as usize];
+            for tile in tiles(xres, yres, 16, order) {
+                for y in tile.y..tile.y + tile.height {
+                    for x in tile.x..tile.x + tile.width {
+                        covered[(y * xres + x) as usize] += 1;
+                    }
+                }
+            }
+
+            assert!(
+                covered.iter().all(|count| *count == 1),
+                "tiles() failed on {:?}. Expected every pixel covered once, got {:?}.",
+                order,
+                covered
+            );
+        }
+    }
+
+    #[test]
+    fn test_region_from_str() {
+        let pixels = Region::Pixels {
+            x: 0,
+            y: 0,
+            width: 1,
+            height: 1,
+        };
+        let normalized = Region::Normalized {
+            x: 0.25,
+            y: 0.0,
+            width: 0.5,
+            height: 1.0,
+        };
+        for (region, expected) in [
+            ("0,0,1,1px", Some(pixels)),
+            ("25, 0, 50, 100%", Some(normalized)),
+            ("0,0,1,1", None),
+            ("0.5,0,1,1px", None),
+            ("0,0,1%", None),
+        ] {
+            let parsed = region.parse::<Region>();
+            assert!(
+                parsed.as_ref().ok() == expected.as_ref(),
+                "Region::from_str() failed on {}. Expected {:?}, got {:?}.",
+                region,
+                expected,
+                parsed
+            );
+        }
+    }
+
+    #[test]
+    fn test_region() {
+        let mut scene: Scene = serde_json::from_str(
+            r#"{
+                "camera": {"focal_len": 1.0, "width": 1.0},
+                "objects": [
+                    {
+                        "object": "Sphere",
+                        "material": {"Diffuse": {"color": {"x": 1.0, "y": 1.0, "z": 1.0}}},
+                        "transforms": [{"Translate": {"x": 0.0, "y": 0.0, "z": 3.0}}]
+                    }
+                ],
+                "integrator": "Normals"
+            }"#,
+        )
+        .unwrap();
+        scene.link().unwrap();
+
+        let (xres, yres) = (20, 16);
+        let full = scene.render(xres, yres, 1);
+        let region = Region::Normalized {
+            x: 0.12,
+            y: 0.3,
+            width: 0.5,
+            height: 0.45,
+        };
+        let window = region.window(xres, yres);
+        let expected = Tile {
+            x: 2,
+            y: 4,
+            width: 11,
+            height: 8,
+        };
+        assert_eq!(
+            expected, window,
+            "Region::window() failed on {:?}. Expected {:?}, got {:?}.",
+            region, expected, window
+        );
+
+        for crop in [true, false] {
+            let options = RenderOptions {
+                region: Some(region),
+                crop,
+                tile_size: 3,
+                ..RenderOptions::default()
+            };
+            let (image, _) = scene.render_with_options(xres, yres, 1, &options);
+
+            for y in 0..yres {
+                for x in 0..xres {
+                    let inside = (window.x..window.x + window.width).contains(&x)
+                        && (window.y..window.y + window.height).contains(&y);
+                    let (expected, got) = match (inside, crop) {
+                        (true, true) => {
+                            let i = (y - window.y) * window.width + x - window.x;
+                            (
+                                full.pixels()[(y * xres + x) as usize],
+                                image.pixels()[i as usize],
+                            )
+                        }
+                        (true, false) => (
+                            full.pixels()[(y * xres + x) as usize],
+                            image.pixels()[(y * xres + x) as usize],
+                        ),
+                        (false, true) => continue,
+                        (false, false) => {
+                            (Vector3::zeros(), image.pixels()[(y * xres + x) as usize])
+                        }
+                    };
+                    assert_eq!(
+                        expected, got,
+                        "Scene::render_with_options() failed on ({}, {}) with crop {}. \
+                         Expected {}, got {}.",
+                        x, y, crop, expected, got
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_cancel() {
+        let mut scene: Scene = serde_json::from_str(
+            r#"{
+                "camera": {"focal_len": 1.0, "width": 1.0},
+                "objects": [],
+                "environment": {"Constant": {"x": 1.0, "y": 1.0, "z": 1.0}}
+            }"#,
+        )
+        .unwrap();
+        scene.link().unwrap();
+
+        // Cancelling from the progress callback stops the render after the tiles already begun.
+        let cancel = CancelToken::new();
+        let token = cancel.clone();
+        let options = RenderOptions {
+            tile_size: 1,
+            progress: Some(Box::new(move |_| token.cancel())),
+            cancel,
+            ..RenderOptions::default()
+        };
+        let (image, stats) = scene.render_with_options(1, 1024, 1, &options);
+
+        let black = image
+            .pixels()
+            .iter()
+            .filter(|pixel| **pixel == Vector3::zeros())
+            .count();
+        assert!(
+            stats.cancelled && black > 0,
+            "Scene::render_with_options() failed to cancel. Expected black tiles, got {} black \
+             pixels and {:?}.",
+            black,
+            stats
+        );
+    }
+}
@@ -0,0 +1,211 @@
+use std::sync::OnceLock;
+
+use crate::color;
+use crate::random;
+use crate::vector::Vector3;
+
+/// The shortest wavelength sampled in spectral mode, in nanometers.
+pub const MIN_WAVELENGTH: f32 = 380.0;
+
+/// The longest wavelength sampled in spectral mode, in nanometers.
+pub const MAX_WAVELENGTH: f32 = 720.0;
+
+/// The wavelength used for dispersive materials outside of spectral mode, which is the sodium
+/// D line that refractive indices are usually quoted at.
+pub const REFERENCE_WAVELENGTH: f32 = 589.3;
+
+/// The wavelengths carried by a path in spectral mode, in nanometers. The first is the hero
+/// wavelength, and the others are spread evenly over the visible range from it, so the three
+/// components of a `Vector3` hold the values at each wavelength instead of RGB.
+///
+/// Once a path meets a dispersive surface, only the hero wavelength can follow it, so the
+/// secondary wavelengths are terminated.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Wavelengths {
+    lambda: [f32; 3],
+    terminated: bool,
+}
+
+impl Wavelengths {
+    /// Sample a hero wavelength uniformly over the visible range together with two secondary
+    /// wavelengths.
+    pub fn sample() -> Self {
+        let u = random::uniform();
+        let range = MAX_WAVELENGTH - MIN_WAVELENGTH;
+        let lambda = [0.0, 1.0, 2.0].map(|i| MIN_WAVELENGTH + ((u + i / 3.0) % 1.0) * range);
+
+        Wavelengths {
+            lambda,
+            terminated: false,
+        }
+    }
+
+    /// Return the hero wavelength.
+    pub fn hero(&self) -> f32 {
+        self.lambda[0]
+    }
+
+    /// Terminate the secondary wavelengths, returning the weight that keeps the estimate of
+    /// the remaining hero wavelength unbiased.
+    pub fn terminate_secondary(&mut self) -> Vector3 {
+        if self.terminated {
+            return Vector3::new(1.0, 0.0, 0.0);
+        }
+
+        self.terminated = true;
+        Vector3::new(3.0, 0.0, 0.0)
+    }
+
+    /// Return the values at these wavelengths of the spectrum upsampled from the linear sRGB
+    /// color `rgb`.
+    pub fn upsample(&self, rgb: Vector3) -> Vector3 {
+        let [a, b, c] = self.lambda.map(|lambda| upsample(rgb, lambda));
+        Vector3::new(a, b, c)
+    }
+
+    /// Convert the `values` of a spectrum at these wavelengths into an estimate of its linear
+    /// sRGB color. The conversion is balanced so that a constant spectrum of one is white.
+    pub fn to_rgb(&self, values: Vector3) -> Vector3 {
+        let values = [values.x(), values.y(), values.z()];
+        let xyz = self
+            .lambda
+            .iter()
+            .zip(values)
+            .fold(Vector3::zeros(), |xyz, (&lambda, value)| {
+                xyz + cie_xyz(lambda) * value
+            });
+
+        // Each wavelength was sampled uniformly over the range, and the channels are averaged.
+        let xyz = xyz * ((MAX_WAVELENGTH - MIN_WAVELENGTH) / 3.0);
+        color::xyz_to_linear_srgb(xyz).cwise_div(white_balance())
+    }
+}
+
+/// Return the linear sRGB color of a constant spectrum of one, used to balance the conversion
+/// from spectra to colors.
+fn white_balance() -> Vector3 {
+    static WHITE: OnceLock<Vector3> = OnceLock::new();
+
+    *WHITE.get_or_init(|| {
+        let n = 1000;
+        let step = (MAX_WAVELENGTH - MIN_WAVELENGTH) / n as f32;
+        let xyz = (0..n)
+            .map(|i| cie_xyz(MIN_WAVELENGTH + (i as f32 + 0.5) * step) * step)
+            .fold(Vector3::zeros(), |a, b| a + b);
+
+        color::xyz_to_linear_srgb(xyz)
+    })
+}
+
+/// Evaluate the CIE 1931 color matching functions at `lambda` nanometers with the multi-lobe
+/// fit from Wyman et al., "Simple Analytic Approximations to the CIE XYZ Color Matching
+/// Functions".
+pub fn cie_xyz(lambda: f32) -> Vector3 {
+    let g = |mu: f32, below: f32, above: f32| {
+        let sigma = if lambda < mu { below } else { above };
+        let t = (lambda - mu) / sigma;
+        (-0.5 * t * t).exp()
+    };
+
+    Vector3::new(
+        1.056 * g(599.8, 37.9, 31.0) + 0.362 * g(442.0, 16.0, 26.7) - 0.065 * g(501.1, 20.4, 26.2),
+        0.821 * g(568.8, 46.9, 40.5) + 0.286 * g(530.9, 16.3, 31.1),
+        1.217 * g(437.0, 11.8, 36.0) + 0.681 * g(459.0, 26.0, 13.8),
+    )
+}
+
+/// Smits' basis spectra, sampled at ten evenly spaced bins over the visible range.
+const WHITE: [f32; 10] = [
+    1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000,
+];
+const CYAN: [f32; 10] = [
+    0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000,
+];
+const MAGENTA: [f32; 10] = [
+    1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959,
+];
+const YELLOW: [f32; 10] = [
+    0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840,
+];
+const RED: [f32; 10] = [
+    0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149,
+];
+const GREEN: [f32; 10] = [
+    0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025,
+];
+const BLUE: [f32; 10] = [
+    1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496,
+];
+
+/// Upsample the linear sRGB color `rgb` to a smooth spectrum with Smits' method, "An RGB to
+/// Spectrum Conversion for Reflectances", and return its value at `lambda` nanometers.
+pub fn upsample(rgb: Vector3, lambda: f32) -> f32 {
+    // Interpolate each basis spectrum between the centers of its bins.
+    let x = (lambda - MIN_WAVELENGTH) / (MAX_WAVELENGTH - MIN_WAVELENGTH) * 10.0 - 0.5;
+    let x = x.clamp(0.0, 9.0);
+    let i = (x as usize).min(8);
+    let t = x - i as f32;
+    let basis = |spectrum: &[f32; 10]| spectrum[i] + (spectrum[i + 1] - spectrum[i]) * t;
+
+    let (r, g, b) = (rgb.x().max(0.0), rgb.y().max(0.0), rgb.z().max(0.0));
+    if r <= g && r <= b {
+        let rest = if g <= b {
+            (g - r) * basis(&CYAN) + (b - g) * basis(&BLUE)
+        } else {
+            (b - r) * basis(&CYAN) + (g - b) * basis(&GREEN)
+        };
+        r * basis(&WHITE) + rest
+    } else if g <= r && g <= b {
+        let rest = if r <= b {
+            (r - g) * basis(&MAGENTA) + (b - r) * basis(&BLUE)
+        } else {
+            (b - g) * basis(&MAGENTA) + (r - b) * basis(&RED)
+        };
+        g * basis(&WHITE) + rest
+    } else {
+        let rest = if r <= g {
+            (r - b) * basis(&YELLOW) + (g - r) * basis(&GREEN)
+        } else {
+            (g - b) * basis(&YELLOW) + (r - g) * basis(&RED)
+        };
+        b * basis(&WHITE) + rest
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        // Averaging many wavelength samples of an upsampled color should give the color back.
+        let n = 20000;
+        for rgb in [
+            Vector3::new(1.0, 1.0, 1.0),
+            Vector3::new(0.8, 0.2, 0.1),
+            Vector3::new(0.1, 0.5, 0.2),
+            Vector3::new(0.2, 0.3, 0.9),
+        ] {
+            let estimate = (0..n)
+                .map(|i| {
+                    let wavelengths = Wavelengths {
+                        lambda: [0.0, 1.0, 2.0].map(|k| {
+                            let u = (i as f32 + 0.5) / n as f32;
+                            MIN_WAVELENGTH
+                                + ((u + k / 3.0) % 1.0) * (MAX_WAVELENGTH - MIN_WAVELENGTH)
+                        }),
+                        terminated: false,
+                    };
+                    wavelengths.to_rgb(wavelengths.upsample(rgb))
+                })
+                .fold(Vector3::zeros(), |a, b| a + b)
+                * (1.0 / n as f32);
+            assert!(
+                (estimate - rgb).norm() < 0.1,
+                "Wavelengths::to_rgb() failed to round trip {}. Got {}.",
+                rgb,
+                estimate
+            );
+        }
+    }
+}
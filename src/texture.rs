@@ -0,0 +1,78 @@
+use serde::{Deserialize, Serialize};
+
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::error::{self, SceneError};
+use crate::image::{Image, ImageError};
+use crate::vector::Vector3;
+
+/// An enum with textures that vary a color over the `(u, v)` coordinates of a surface, which
+/// repeat outside of `[0, 1)`. Available textures are:
+/// - Checker: Alternates between the `even` and `odd` colors in a checkerboard with `scale`
+///   squares along each of `u` and `v`.
+/// - Image: An HDR image loaded from `path`, relative to the scene file, with `v = 0` at the
+///   bottom of the image.
+#[derive(Serialize, Deserialize)]
+pub enum Texture {
+    Checker {
+        even: Vector3,
+        odd: Vector3,
+        #[serde(default = "default_scale")]
+        scale: f32,
+    },
+    Image {
+        path: String,
+        #[serde(skip)]
+        image: Option<Arc<Image>>,
+    },
+}
+
+fn default_scale() -> f32 {
+    8.0
+}
+
+impl Texture {
+    /// Check that the colors of this texture at `path` are finite.
+    pub fn validate(&self, path: &str) -> Result<(), SceneError> {
+        if let Texture::Checker { even, odd, .. } = self {
+            error::check_color(&format!("{}.Checker.even", path), *even)?;
+            error::check_color(&format!("{}.Checker.odd", path), *odd)?;
+        }
+
+        Ok(())
+    }
+
+    /// Load any images used by this texture, resolving paths relative to `base`.
+    pub fn load(&mut self, base: &Path) -> Result<(), ImageError> {
+        if let Texture::Image { path, image } = self {
+            *image = Some(Arc::new(Image::load_hdr(base.join(path.as_str()))?));
+        }
+
+        Ok(())
+    }
+
+    /// Return the color of this texture at the surface coordinates `(u, v)`.
+    pub fn at(&self, (u, v): (f32, f32)) -> Vector3 {
+        match self {
+            Texture::Checker { even, odd, scale } => {
+                let square = (u * scale).floor() + (v * scale).floor();
+                if square.rem_euclid(2.0) < 1.0 {
+                    *even
+                } else {
+                    *odd
+                }
+            }
+            Texture::Image { image, .. } => match image {
+                Some(image) => {
+                    let (width, height) = (image.width() as usize, image.height() as usize);
+                    let x = ((u.rem_euclid(1.0) * width as f32) as usize).min(width - 1);
+                    let y = (((1.0 - v.rem_euclid(1.0)) * height as f32) as usize).min(height - 1);
+
+                    image.pixels()[y * width + x]
+                }
+                None => Vector3::zeros(),
+            },
+        }
+    }
+}
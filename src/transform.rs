@@ -1,29 +1,148 @@
-use std::iter::Scan;
-
+use serde::de::{self, Deserializer, MapAccess, Visitor};
 use serde::{Deserialize, Serialize};
 
-use crate::vector::Vector3;
+use std::fmt;
+
+use crate::animation::{Animated, Interpolate};
+use crate::error::SceneError;
+use crate::vector::{Matrix4, Quaternion, Vector3};
 use crate::Ray;
 
 #[derive(Clone, Copy, Debug, Serialize, Deserialize)]
-/// An enum representing various transformations that can be applied to a 3D vector.
+/// An enum representing various transformations that can be applied to a 3D vector. Angles are
+/// in radians, or in degrees when written as `{"degrees": 90.0}` in JSON. Rotation axes and
+/// quaternions are normalized when they are loaded, and are rejected if they have zero length.
+/// - Rotate: Rotates by the given angle around the given axis.
+/// - Quaternion: Rotates by the given quaternion.
+/// - Euler: Rotates around the `x`, `y` and `z` axes in the given `order`.
+/// - Matrix: A general affine transform given by the rows of its 4x4 matrix.
+/// - Shear: Moves each coordinate by the given multiples of the other two, so `xy` is the amount
+///   `x` moves per unit of `y`.
+/// - LookAt: Places the local origin at `eye` with the local `+z` axis pointing towards `target`
+///   and the local `+y` axis as close to `up` as possible.
 pub enum Transform {
     Scale(Vector3),
     Translate(Vector3),
-    Rotate(Vector3, f32),
+    Rotate(
+        #[serde(deserialize_with = "deserialize_axis")] Vector3,
+        #[serde(deserialize_with = "deserialize_angle")] f32,
+    ),
+    Quaternion(#[serde(deserialize_with = "deserialize_quaternion")] Quaternion),
+    Euler {
+        #[serde(default, deserialize_with = "deserialize_angle")]
+        x: f32,
+        #[serde(default, deserialize_with = "deserialize_angle")]
+        y: f32,
+        #[serde(default, deserialize_with = "deserialize_angle")]
+        z: f32,
+        #[serde(default)]
+        order: EulerOrder,
+    },
+    Matrix([[f32; 4]; 4]),
+    Shear {
+        #[serde(default)]
+        xy: f32,
+        #[serde(default)]
+        xz: f32,
+        #[serde(default)]
+        yx: f32,
+        #[serde(default)]
+        yz: f32,
+        #[serde(default)]
+        zx: f32,
+        #[serde(default)]
+        zy: f32,
+    },
+    LookAt {
+        eye: Vector3,
+        target: Vector3,
+        up: Vector3,
+    },
 }
 
 impl Transform {
+    /// Check that this transform at `path` can be rendered: scales must be positive, since
+    /// negative scales turn surfaces inside out and zero scales flatten them, rotation axes
+    /// must have a length, matrices must be invertible, and a look-at `up` must not be parallel
+    /// to the view direction.
+    pub fn validate(&self, path: &str) -> Result<(), SceneError> {
+        match self {
+            Transform::Scale(scale) => {
+                if [scale.x(), scale.y(), scale.z()]
+                    .iter()
+                    .all(|s| s.is_finite() && *s > 0.0)
+                {
+                    Ok(())
+                } else {
+                    Err(SceneError::invalid(
+                        format!("{}.Scale", path),
+                        format!("scale {} must be positive in every axis", scale),
+                    ))
+                }
+            }
+            Transform::Rotate(axis, _) if !axis.norm().is_normal() => Err(SceneError::invalid(
+                format!("{}.Rotate[0]", path),
+                format!("rotation axis {} must have a finite, non-zero length", axis),
+            )),
+            Transform::Matrix(_) | Transform::Shear { .. } => {
+                let invertible = self
+                    .matrix()
+                    .inverse()
+                    .is_some_and(|inverse| inverse.rows().iter().flatten().all(|v| v.is_finite()));
+                if invertible {
+                    return Ok(());
+                }
+                let name = match self {
+                    Transform::Matrix(_) => "Matrix",
+                    _ => "Shear",
+                };
+                Err(SceneError::invalid(
+                    format!("{}.{}", path, name),
+                    "matrix must be finite and invertible",
+                ))
+            }
+            Transform::LookAt { eye, target, up } => {
+                let forward = *target - *eye;
+                if !forward.norm().is_normal() {
+                    Err(SceneError::invalid(
+                        format!("{}.LookAt.target", path),
+                        format!("target {} must differ from the eye {}", target, eye),
+                    ))
+                } else if !up.cross(forward.normalized()).norm().is_normal() {
+                    Err(SceneError::invalid(
+                        format!("{}.LookAt.up", path),
+                        format!("up {} must not be parallel to the view direction", up),
+                    ))
+                } else {
+                    Ok(())
+                }
+            }
+            _ => Ok(()),
+        }
+    }
+
     /// Return the inverse of this transform.
     pub fn inverse(self) -> Transform {
         match self {
             Transform::Scale(scale) => Transform::Scale(Vector3::new(
                 1.0 / scale.x(),
-                1.0 / scale.x(),
-                1.0 / scale.x(),
+                1.0 / scale.y(),
+                1.0 / scale.z(),
             )),
             Transform::Rotate(axis, angle) => Transform::Rotate(axis, -angle),
+            Transform::Quaternion(q) => Transform::Quaternion(q.conjugate()),
+            Transform::Euler { x, y, z, order } => {
+                Transform::Quaternion(order.quaternion(x, y, z).conjugate())
+            }
             Transform::Translate(delta) => Transform::Translate(-delta),
+            // A singular matrix has no inverse, so it inverts to NaNs that nothing intersects.
+            Transform::Matrix(_) | Transform::Shear { .. } | Transform::LookAt { .. } => {
+                Transform::Matrix(
+                    self.matrix()
+                        .inverse()
+                        .map_or([[f32::NAN; 4]; 4], Matrix4::rows),
+                )
+            }
         }
     }
 
@@ -37,6 +156,7 @@ impl Transform {
                 vector.z() * scale.z(),
             ),
             Transform::Rotate(axis, angle) => {
+                let axis = axis.normalized();
                 let cos_theta = angle.cos();
                 let sin_theta = angle.sin();
                 let dot = vector.dot(axis);
@@ -44,7 +164,72 @@ impl Transform {
 
                 (vector * cos_theta) + (cross * sin_theta) + axis * dot * (1.0 - cos_theta)
             }
+            Transform::Quaternion(q) => q.normalized().rotate(vector),
+            Transform::Euler { x, y, z, order } => order.quaternion(x, y, z).rotate(vector),
             Transform::Translate(delta) => vector + delta,
+            Transform::Matrix(_) | Transform::Shear { .. } | Transform::LookAt { .. } => {
+                self.matrix().transform_point(vector)
+            }
+        }
+    }
+
+    /// Return the matrix that applies this transformation to points in homogeneous coordinates.
+    pub fn matrix(self) -> Matrix4 {
+        match self {
+            Transform::Scale(scale) => Matrix4::new([
+                [scale.x(), 0.0, 0.0, 0.0],
+                [0.0, scale.y(), 0.0, 0.0],
+                [0.0, 0.0, scale.z(), 0.0],
+                [0.0, 0.0, 0.0, 1.0],
+            ]),
+            Transform::Rotate(axis, angle) => {
+                let axis = axis.normalized();
+                let (x, y, z) = (axis.x(), axis.y(), axis.z());
+                let c = angle.cos();
+                let s = angle.sin();
+                let k = 1.0 - c;
+
+                Matrix4::new([
+                    [c + x * x * k, x * y * k - z * s, x * z * k + y * s, 0.0],
+                    [y * x * k + z * s, c + y * y * k, y * z * k - x * s, 0.0],
+                    [z * x * k - y * s, z * y * k + x * s, c + z * z * k, 0.0],
+                    [0.0, 0.0, 0.0, 1.0],
+                ])
+            }
+            Transform::Translate(delta) => Matrix4::new([
+                [1.0, 0.0, 0.0, delta.x()],
+                [0.0, 1.0, 0.0, delta.y()],
+                [0.0, 0.0, 1.0, delta.z()],
+                [0.0, 0.0, 0.0, 1.0],
+            ]),
+            Transform::Quaternion(q) => q.normalized().matrix(),
+            Transform::Euler { x, y, z, order } => order.quaternion(x, y, z).matrix(),
+            Transform::Matrix(m) => Matrix4::new(m),
+            Transform::Shear {
+                xy,
+                xz,
+                yx,
+                yz,
+                zx,
+                zy,
+            } => Matrix4::new([
+                [1.0, xy, xz, 0.0],
+                [yx, 1.0, yz, 0.0],
+                [zx, zy, 1.0, 0.0],
+                [0.0, 0.0, 0.0, 1.0],
+            ]),
+            Transform::LookAt { eye, target, up } => {
+                let forward = (target - eye).normalized();
+                let right = up.cross(forward).normalized();
+                let up = forward.cross(right);
+
+                Matrix4::new([
+                    [right.x(), up.x(), forward.x(), eye.x()],
+                    [right.y(), up.y(), forward.y(), eye.y()],
+                    [right.z(), up.z(), forward.z(), eye.z()],
+                    [0.0, 0.0, 0.0, 1.0],
+                ])
+            }
         }
     }
 
@@ -52,14 +237,698 @@ impl Transform {
     pub fn transform_ray(self, ray: Ray) -> Ray {
         // TODO: Implement Transform.
         match self {
-            Transform::Scale(_) | Transform::Rotate(_, _) => Ray {
+            Transform::Scale(_)
+            | Transform::Rotate(_, _)
+            | Transform::Quaternion(_)
+            | Transform::Euler { .. } => Ray {
                 origin: self.transform(ray.origin),
                 direction: self.transform(ray.direction),
+                ..ray
             },
             Transform::Translate(_) => Ray {
                 origin: self.transform(ray.origin),
                 direction: ray.direction,
+                ..ray
+            },
+            Transform::Matrix(_) | Transform::Shear { .. } | Transform::LookAt { .. } => {
+                let matrix = self.matrix();
+                Ray {
+                    origin: matrix.transform_point(ray.origin),
+                    direction: matrix.transform_vector(ray.direction),
+                    ..ray
+                }
+            }
+        }
+    }
+}
+
+/// The order in which Euler angle rotations are applied. `XYZ` rotates around the `x` axis
+/// first, then the `y` axis, then the `z` axis, all about the fixed parent axes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EulerOrder {
+    #[default]
+    XYZ,
+    XZY,
+    YXZ,
+    YZX,
+    ZXY,
+    ZYX,
+}
+
+impl EulerOrder {
+    /// Return the unit quaternion rotating by `x`, `y` and `z` radians around the respective
+    /// axes in this order.
+    pub fn quaternion(self, x: f32, y: f32, z: f32) -> Quaternion {
+        let qx = Quaternion::from_axis_angle(Vector3::new(1.0, 0.0, 0.0), x);
+        let qy = Quaternion::from_axis_angle(Vector3::new(0.0, 1.0, 0.0), y);
+        let qz = Quaternion::from_axis_angle(Vector3::new(0.0, 0.0, 1.0), z);
+
+        // Later rotations multiply on the left.
+        match self {
+            EulerOrder::XYZ => qz * qy * qx,
+            EulerOrder::XZY => qy * qz * qx,
+            EulerOrder::YXZ => qz * qx * qy,
+            EulerOrder::YZX => qx * qz * qy,
+            EulerOrder::ZXY => qy * qx * qz,
+            EulerOrder::ZYX => qx * qy * qz,
+        }
+    }
+}
+
+/// Deserialize an angle given either as a number of radians, or as `{"degrees": d}` or
+/// `{"radians": r}`.
+pub(crate) fn deserialize_angle<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<f32, D::Error> {
+    struct AngleVisitor;
+
+    impl<'de> Visitor<'de> for AngleVisitor {
+        type Value = f32;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(
+                f,
+                "an angle in radians or an object like {{\"degrees\": 90.0}}"
+            )
+        }
+
+        fn visit_f64<E: de::Error>(self, v: f64) -> Result<f32, E> {
+            Ok(v as f32)
+        }
+
+        fn visit_i64<E: de::Error>(self, v: i64) -> Result<f32, E> {
+            Ok(v as f32)
+        }
+
+        fn visit_u64<E: de::Error>(self, v: u64) -> Result<f32, E> {
+            Ok(v as f32)
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<f32, A::Error> {
+            let angle = match map.next_key::<String>()?.as_deref() {
+                Some("degrees") => map.next_value::<f32>()?.to_radians(),
+                Some("radians") => map.next_value::<f32>()?,
+                Some(unit) => return Err(de::Error::unknown_field(unit, &["degrees", "radians"])),
+                None => return Err(de::Error::missing_field("degrees")),
+            };
+
+            if let Some(key) = map.next_key::<String>()? {
+                return Err(de::Error::custom(format!(
+                    "an angle must have a single unit, found extra key `{}`",
+                    key
+                )));
+            }
+
+            Ok(angle)
+        }
+    }
+
+    deserializer.deserialize_any(AngleVisitor)
+}
+
+/// Deserialize a rotation axis, normalizing it and rejecting axes that have zero length.
+fn deserialize_axis<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vector3, D::Error> {
+    let axis = Vector3::deserialize(deserializer)?;
+    let norm = axis.norm();
+    if !norm.is_normal() {
+        return Err(de::Error::custom(format!(
+            "rotation axis {} must have a finite, non-zero length",
+            axis
+        )));
+    }
+
+    Ok(axis * (1.0 / norm))
+}
+
+/// Deserialize a rotation quaternion, normalizing it and rejecting quaternions that have zero
+/// length.
+fn deserialize_quaternion<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Quaternion, D::Error> {
+    let q = Quaternion::deserialize(deserializer)?;
+    if !q.norm().is_normal() {
+        return Err(de::Error::custom(
+            "rotation quaternion must have a finite, non-zero length",
+        ));
+    }
+
+    Ok(q.normalized())
+}
+
+impl Transform {
+    /// Return the rotation of this transform as a unit quaternion, or `None` if it is not a
+    /// pure rotation.
+    pub fn rotation(self) -> Option<Quaternion> {
+        match self {
+            Transform::Rotate(axis, angle) => Some(Quaternion::from_axis_angle(axis, angle)),
+            Transform::Quaternion(q) => Some(q.normalized()),
+            Transform::Euler { x, y, z, order } => Some(order.quaternion(x, y, z)),
+            _ => None,
+        }
+    }
+}
+
+impl Interpolate for Transform {
+    /// Blend the parameters of two transforms of the same kind. Rotations around the same axis
+    /// blend their angles so that they can turn more than half a revolution between keys, and
+    /// other rotations are blended with spherical linear interpolation. Transforms of different
+    /// kinds hold the first transform until the next key.
+    fn interpolate(&self, other: &Self, t: f32) -> Self {
+        match (*self, *other) {
+            (Transform::Scale(a), Transform::Scale(b)) => Transform::Scale(a.interpolate(&b, t)),
+            (Transform::Translate(a), Transform::Translate(b)) => {
+                Transform::Translate(a.interpolate(&b, t))
+            }
+            (Transform::Rotate(a, angle_a), Transform::Rotate(b, angle_b))
+                if (a.normalized() - b.normalized()).norm() < 1.0e-6 =>
+            {
+                Transform::Rotate(a, angle_a.interpolate(&angle_b, t))
+            }
+            (Transform::Matrix(a), Transform::Matrix(b)) => {
+                let mut m = a;
+                for (row, row_b) in m.iter_mut().zip(b.iter()) {
+                    for (entry, entry_b) in row.iter_mut().zip(row_b.iter()) {
+                        *entry = entry.interpolate(entry_b, t);
+                    }
+                }
+                Transform::Matrix(m)
+            }
+            (
+                Transform::Shear {
+                    xy,
+                    xz,
+                    yx,
+                    yz,
+                    zx,
+                    zy,
+                },
+                Transform::Shear {
+                    xy: xy_b,
+                    xz: xz_b,
+                    yx: yx_b,
+                    yz: yz_b,
+                    zx: zx_b,
+                    zy: zy_b,
+                },
+            ) => Transform::Shear {
+                xy: xy.interpolate(&xy_b, t),
+                xz: xz.interpolate(&xz_b, t),
+                yx: yx.interpolate(&yx_b, t),
+                yz: yz.interpolate(&yz_b, t),
+                zx: zx.interpolate(&zx_b, t),
+                zy: zy.interpolate(&zy_b, t),
+            },
+            (
+                Transform::LookAt { eye, target, up },
+                Transform::LookAt {
+                    eye: eye_b,
+                    target: target_b,
+                    up: up_b,
+                },
+            ) => Transform::LookAt {
+                eye: eye.interpolate(&eye_b, t),
+                target: target.interpolate(&target_b, t),
+                up: up.interpolate(&up_b, t),
+            },
+            (a, b) => match (a.rotation(), b.rotation()) {
+                (Some(qa), Some(qb)) => Transform::Quaternion(qa.slerp(qb, t)),
+                _ if t < 1.0 => a,
+                _ => b,
+            },
+        }
+    }
+}
+
+/// The composed matrices of a list of transforms applied in order, so that applying the list
+/// costs a single matrix multiplication regardless of how many transforms it contains.
+#[derive(Clone, Copy, Debug)]
+pub struct Affine {
+    matrix: Matrix4,
+    inverse: Matrix4,
+    normal: Matrix4,
+}
+
+impl Affine {
+    /// Compose the matrices of `transforms` applied from first to last.
+    pub fn new(transforms: &[Transform]) -> Self {
+        let matrix = transforms
+            .iter()
+            .fold(Matrix4::identity(), |m, t| t.matrix() * m);
+        let inverse = transforms
+            .iter()
+            .fold(Matrix4::identity(), |m, t| m * t.inverse().matrix());
+
+        Affine {
+            matrix,
+            inverse,
+            normal: inverse.transpose(),
+        }
+    }
+
+    /// Return the transform that applies this one and then `parent`.
+    pub fn then(&self, parent: &Affine) -> Affine {
+        let inverse = self.inverse * parent.inverse;
+        Affine {
+            matrix: parent.matrix * self.matrix,
+            inverse,
+            normal: inverse.transpose(),
+        }
+    }
+
+    /// Return the matrix taking local space to parent space.
+    pub fn matrix(&self) -> Matrix4 {
+        self.matrix
+    }
+
+    /// Return the matrix taking parent space to local space.
+    pub fn inverse(&self) -> Matrix4 {
+        self.inverse
+    }
+
+    /// Transform a ray from parent space into local space.
+    pub fn ray_to_local(&self, ray: Ray) -> Ray {
+        Ray {
+            origin: self.inverse.transform_point(ray.origin),
+            direction: self.inverse.transform_vector(ray.direction),
+            ..ray
+        }
+    }
+
+    /// Transform a point from local space into parent space.
+    pub fn point_to_world(&self, point: Vector3) -> Vector3 {
+        self.matrix.transform_point(point)
+    }
+
+    /// Transform a normal from local space into parent space using the inverse-transpose.
+    pub fn normal_to_world(&self, normal: Vector3) -> Vector3 {
+        self.normal.transform_vector(normal).normalized()
+    }
+}
+
+/// A list of possibly animated transforms applied in order. When none of the transforms are
+/// animated, their matrices are composed once when the stack is built.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(from = "Vec<Animated<Transform>>", into = "Vec<Animated<Transform>>")]
+pub struct TransformStack {
+    transforms: Vec<Animated<Transform>>,
+    cached: Option<Affine>,
+}
+
+impl TransformStack {
+    /// Check that every transform in the stack at `path` can be rendered.
+    pub fn validate(&self, path: &str) -> Result<(), SceneError> {
+        self.transforms.iter().enumerate().try_for_each(|(i, t)| {
+            t.try_each(&format!("{}[{}]", path, i), |path, t| t.validate(path))
+        })
+    }
+
+    /// Create a new `TransformStack` that applies `transforms` from first to last.
+    pub fn new(transforms: Vec<Animated<Transform>>) -> Self {
+        let cached = if transforms.iter().any(Animated::is_animated) {
+            None
+        } else {
+            Some(Affine::new(&evaluate(&transforms, 0.0)))
+        };
+
+        TransformStack { transforms, cached }
+    }
+
+    /// Return the list of transforms in this stack.
+    pub fn transforms(&self) -> &[Animated<Transform>] {
+        &self.transforms
+    }
+
+    /// Return whether any transform in this stack changes over time.
+    pub fn is_animated(&self) -> bool {
+        self.cached.is_none()
+    }
+
+    /// Return the composed matrices of this stack at the given `time`.
+    pub fn at(&self, time: f32) -> Affine {
+        match self.cached {
+            Some(affine) => affine,
+            None => Affine::new(&evaluate(&self.transforms, time)),
+        }
+    }
+}
+
+/// Evaluate a list of animated transforms at the given `time`.
+fn evaluate(transforms: &[Animated<Transform>], time: f32) -> Vec<Transform> {
+    transforms.iter().map(|t| t.at(time)).collect()
+}
+
+impl Default for TransformStack {
+    fn default() -> Self {
+        TransformStack::new(Vec::new())
+    }
+}
+
+impl From<Vec<Animated<Transform>>> for TransformStack {
+    fn from(transforms: Vec<Animated<Transform>>) -> Self {
+        TransformStack::new(transforms)
+    }
+}
+
+impl From<Vec<Transform>> for TransformStack {
+    fn from(transforms: Vec<Transform>) -> Self {
+        TransformStack::new(transforms.into_iter().map(Animated::Constant).collect())
+    }
+}
+
+impl From<TransformStack> for Vec<Animated<Transform>> {
+    fn from(stack: TransformStack) -> Self {
+        stack.transforms
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    const TRIALS: usize = 1000;
+
+    fn random_vector(rng: &mut StdRng, range: f32) -> Vector3 {
+        Vector3::new(
+            rng.gen_range(-range, range),
+            rng.gen_range(-range, range),
+            rng.gen_range(-range, range),
+        )
+    }
+
+    /// Generate a random invertible transform of any kind, with scales kept away from zero.
+    fn random_transform(rng: &mut StdRng) -> Transform {
+        let mut scale = || {
+            let s: f32 = rng.gen_range(0.2, 5.0);
+            if rng.gen() {
+                s
+            } else {
+                -s
+            }
+        };
+        let scale = Vector3::new(scale(), scale(), scale());
+
+        match rng.gen_range(0, 8) {
+            0 => Transform::Scale(scale),
+            1 => Transform::Translate(random_vector(rng, 10.0)),
+            2 => Transform::Rotate(
+                random_vector(rng, 1.0).normalized(),
+                rng.gen_range(-10.0, 10.0),
+            ),
+            3 => Transform::Shear {
+                xy: rng.gen_range(-1.0, 1.0),
+                xz: 0.0,
+                yx: 0.0,
+                yz: rng.gen_range(-1.0, 1.0),
+                zx: rng.gen_range(-1.0, 1.0),
+                zy: 0.0,
             },
+            4 => Transform::LookAt {
+                eye: random_vector(rng, 10.0),
+                target: random_vector(rng, 10.0) + Vector3::new(0.0, 0.0, 30.0),
+                up: Vector3::new(0.0, 1.0, 0.0),
+            },
+            5 => Transform::Quaternion(
+                Quaternion::new(
+                    rng.gen_range(-1.0, 1.0),
+                    rng.gen_range(-1.0, 1.0),
+                    rng.gen_range(-1.0, 1.0),
+                    rng.gen_range(-1.0, 1.0),
+                )
+                .normalized(),
+            ),
+            6 => Transform::Euler {
+                x: rng.gen_range(-3.0, 3.0),
+                y: rng.gen_range(-3.0, 3.0),
+                z: rng.gen_range(-3.0, 3.0),
+                order: EulerOrder::ZXY,
+            },
+            _ => {
+                let m = Transform::Rotate(random_vector(rng, 1.0).normalized(), 1.0).matrix()
+                    * Transform::Scale(scale).matrix()
+                    * Transform::Translate(random_vector(rng, 10.0)).matrix();
+                Transform::Matrix(m.rows())
+            }
+        }
+    }
+
+    fn close(a: Vector3, b: Vector3) -> bool {
+        (a - b).norm() <= 1.0e-3 * (1.0 + a.norm().max(b.norm()))
+    }
+
+    #[test]
+    fn test_inverse_round_trip() {
+        let mut rng = StdRng::seed_from_u64(29);
+        for _ in 0..TRIALS {
+            let t = random_transform(&mut rng);
+            let v = random_vector(&mut rng, 10.0);
+
+            let round_trip = t.transform(t.inverse().transform(v));
+            assert!(
+                close(round_trip, v),
+                "transform(inverse(v)) failed for {:?} on {}. Got {}.",
+                t,
+                v,
+                round_trip
+            );
+
+            let round_trip = t.inverse().transform(t.transform(v));
+            assert!(
+                close(round_trip, v),
+                "inverse(transform(v)) failed for {:?} on {}. Got {}.",
+                t,
+                v,
+                round_trip
+            );
+        }
+    }
+
+    #[test]
+    fn test_stack_round_trip() {
+        let mut rng = StdRng::seed_from_u64(2929);
+        for _ in 0..TRIALS {
+            let transforms: Vec<Transform> = (0..rng.gen_range(1, 6))
+                .map(|_| random_transform(&mut rng))
+                .collect();
+            let stack = Affine::new(&transforms);
+            let v = random_vector(&mut rng, 10.0);
+
+            let round_trip = stack.point_to_world(stack.inverse().transform_point(v));
+            assert!(
+                close(round_trip, v),
+                "Affine round trip failed for {:?} on {}. Got {}.",
+                transforms,
+                v,
+                round_trip
+            );
+
+            // A transformed tangent must stay perpendicular to the transformed normal.
+            let normal = random_vector(&mut rng, 1.0).normalized();
+            let tangent = normal.cross(random_vector(&mut rng, 1.0)).normalized();
+            let normal_t = stack.normal_to_world(normal);
+            let tangent_t = stack.matrix().transform_vector(tangent).normalized();
+            let dot = normal_t.dot(tangent_t);
+            assert!(
+                dot.abs() < 1.0e-3,
+                "Affine::normal_to_world() failed for {:?}. Normal {} is not \
+                 perpendicular to tangent {} (dot = {}).",
+                transforms,
+                normal_t,
+                tangent_t,
+                dot
+            );
         }
     }
+
+    #[test]
+    fn test_stack_matches_fold() {
+        let transforms = vec![
+            Transform::Scale(Vector3::new(2.0, 2.0, 2.0)),
+            Transform::Rotate(Vector3::unit(1.0, 2.0, 3.0), 0.7),
+            Transform::Translate(Vector3::new(1.0, -2.0, 3.0)),
+        ];
+        let stack = Affine::new(&transforms);
+
+        let p = Vector3::new(0.3, -1.2, 2.5);
+        let folded = transforms.iter().fold(p, |p, t| t.transform(p));
+        let composed = stack.point_to_world(p);
+        assert!(
+            (folded - composed).norm() < 1.0e-5,
+            "Affine::point_to_world() failed on {}. Expected {}, got {}.",
+            p,
+            folded,
+            composed
+        );
+
+        let round_trip = stack.inverse().transform_point(composed);
+        assert!(
+            (round_trip - p).norm() < 1.0e-5,
+            "Affine::inverse() failed on {}. Expected {}, got {}.",
+            composed,
+            p,
+            round_trip
+        );
+    }
+
+    #[test]
+    fn test_look_at() {
+        let eye = Vector3::new(1.0, 2.0, 3.0);
+        let target = Vector3::new(1.0, 2.0, 10.0);
+        let look_at = Transform::LookAt {
+            eye,
+            target,
+            up: Vector3::new(0.0, 1.0, 0.0),
+        };
+
+        let forward = look_at.transform(Vector3::new(0.0, 0.0, 1.0));
+        let expected = Vector3::new(1.0, 2.0, 4.0);
+        assert!(
+            (forward - expected).norm() < 1.0e-5,
+            "Transform::LookAt failed on local +z. Expected {}, got {}.",
+            expected,
+            forward
+        );
+
+        let back = look_at.inverse().transform(forward);
+        let expected = Vector3::new(0.0, 0.0, 1.0);
+        assert!(
+            (back - expected).norm() < 1.0e-5,
+            "Transform::inverse() failed on LookAt. Expected {}, got {}.",
+            expected,
+            back
+        );
+    }
+
+    #[test]
+    fn test_shear_and_matrix() {
+        let shear = Transform::Shear {
+            xy: 2.0,
+            xz: 0.0,
+            yx: 0.0,
+            yz: 0.0,
+            zx: 0.0,
+            zy: 0.0,
+        };
+        let v = Vector3::new(1.0, 1.0, 1.0);
+        let sheared = shear.transform(v);
+        let expected = Vector3::new(3.0, 1.0, 1.0);
+        assert_eq!(
+            expected, sheared,
+            "Transform::Shear failed on {}. Expected {}, got {}.",
+            v, expected, sheared
+        );
+
+        let matrix = Transform::Matrix(shear.matrix().rows());
+        let back = matrix.inverse().transform(sheared);
+        assert!(
+            (back - v).norm() < 1.0e-5,
+            "Transform::inverse() failed on Matrix. Expected {}, got {}.",
+            v,
+            back
+        );
+    }
+
+    #[test]
+    fn test_rotation_input() {
+        let rotate: Transform = serde_json::from_str(
+            r#"{"Rotate": [{"x": 0.0, "y": 0.0, "z": 3.0}, {"degrees": 90}]}"#,
+        )
+        .expect("Rotation in degrees should parse.");
+        let euler: Transform =
+            serde_json::from_str(r#"{"Euler": {"z": {"degrees": 90}, "order": "ZYX"}}"#)
+                .expect("Euler rotation should parse.");
+        let quaternion: Transform =
+            serde_json::from_str(r#"{"Quaternion": {"w": 1.0, "x": 0.0, "y": 0.0, "z": 1.0}}"#)
+                .expect("Quaternion rotation should parse.");
+
+        let v = Vector3::new(1.0, 0.0, 0.0);
+        let expected = Vector3::new(0.0, 1.0, 0.0);
+        for t in [rotate, euler, quaternion] {
+            let rotated = t.transform(v);
+            assert!(
+                (rotated - expected).norm() < 1.0e-6,
+                "{:?} failed on {}. Expected {}, got {}.",
+                t,
+                v,
+                expected,
+                rotated
+            );
+        }
+
+        let euler = Transform::Euler {
+            x: std::f32::consts::FRAC_PI_2,
+            y: std::f32::consts::FRAC_PI_2,
+            z: 0.0,
+            order: EulerOrder::XYZ,
+        };
+        let composed = Transform::Rotate(Vector3::new(0.0, 1.0, 0.0), std::f32::consts::FRAC_PI_2)
+            .transform(
+                Transform::Rotate(Vector3::new(1.0, 0.0, 0.0), std::f32::consts::FRAC_PI_2)
+                    .transform(expected),
+            );
+        let rotated = euler.transform(expected);
+        assert!(
+            (rotated - composed).norm() < 1.0e-6,
+            "Transform::Euler failed to apply x before y. Expected {}, got {}.",
+            composed,
+            rotated
+        );
+
+        let zero_axis = serde_json::from_str::<Transform>(
+            r#"{"Rotate": [{"x": 0.0, "y": 0.0, "z": 0.0}, 1.0]}"#,
+        );
+        assert!(
+            zero_axis.is_err(),
+            "Rotation around a zero-length axis should be rejected."
+        );
+    }
+
+    #[test]
+    fn test_animated_stack() {
+        let stack: TransformStack = serde_json::from_str(
+            r#"[
+                {"Keyframes": [
+                    {"time": 0.0, "value": {"Rotate": [{"x": 0.0, "y": 0.0, "z": 1.0}, 0.0]}},
+                    {"time": 4.0, "value": {"Rotate": [{"x": 0.0, "y": 0.0, "z": 1.0}, {"degrees": 360}]}}
+                ]},
+                {"Translate": {"x": 0.0, "y": 0.0, "z": 5.0}}
+            ]"#,
+        )
+        .expect("Animated transform stack should parse.");
+
+        let v = Vector3::new(1.0, 0.0, 0.0);
+        for (time, expected) in [
+            (0.0, Vector3::new(1.0, 0.0, 5.0)),
+            (1.0, Vector3::new(0.0, 1.0, 5.0)),
+            (2.0, Vector3::new(-1.0, 0.0, 5.0)),
+            (3.0, Vector3::new(0.0, -1.0, 5.0)),
+        ] {
+            let moved = stack.at(time).point_to_world(v);
+            assert!(
+                (moved - expected).norm() < 1.0e-5,
+                "TransformStack::at() failed at time {}. Expected {}, got {}.",
+                time,
+                expected,
+                moved
+            );
+        }
+
+        let a = Transform::Quaternion(Quaternion::identity());
+        let b = Transform::Euler {
+            x: 0.0,
+            y: 0.0,
+            z: std::f32::consts::FRAC_PI_2,
+            order: EulerOrder::XYZ,
+        };
+        let half = a.interpolate(&b, 0.5).transform(v);
+        let expected = Vector3::unit(1.0, 1.0, 0.0);
+        assert!(
+            (half - expected).norm() < 1.0e-5,
+            "Transform::interpolate() failed to slerp rotations. Expected {}, got {}.",
+            expected,
+            half
+        );
+    }
 }
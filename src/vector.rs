@@ -118,6 +118,19 @@ impl Vector3 {
         }
     }
 
+    /// Return two unit vectors that form an orthonormal basis together with this unit vector.
+    pub fn basis(self) -> (Vector3, Vector3) {
+        // Branchless construction from Duff et al., "Building an Orthonormal Basis, Revisited".
+        let sign = 1f32.copysign(self.z);
+        let a = -1.0 / (sign + self.z);
+        let b = self.x * self.y * a;
+
+        (
+            Vector3::new(1.0 + sign * self.x * self.x * a, sign * b, -sign * self.x),
+            Vector3::new(b, sign + self.y * self.y * a, -self.y),
+        )
+    }
+
     /// Apply a component-wise reduction operation `f` to the paired `x`, `y`, and `z`, returning
     /// the result as a new vector.
     pub fn cwise(self, other: Vector3, f: fn(f32, f32) -> f32) -> Vector3 {
@@ -157,7 +170,7 @@ impl Vector3 {
 impl Add<Vector3> for Vector3 {
     type Output = Vector3;
     fn add(self, rhs: Vector3) -> Self::Output {
-        self.cwise(rhs, |a, b| (a + b))
+        self.cwise(rhs, |a, b| a + b)
     }
 }
 
@@ -166,7 +179,7 @@ binop_ref_impl! { impl Add<Vector3> for Vector3, add -> Vector3 }
 impl Sub<Vector3> for Vector3 {
     type Output = Vector3;
     fn sub(self, rhs: Vector3) -> Self::Output {
-        self.cwise(rhs, |a, b| (a - b))
+        self.cwise(rhs, |a, b| a - b)
     }
 }
 
@@ -199,9 +212,9 @@ impl Neg for Vector3 {
     type Output = Vector3;
     fn neg(self) -> Self::Output {
         Vector3 {
-            x: self.x * -1f32,
-            y: self.y * -1f32,
-            z: self.z * -1f32,
+            x: -self.x,
+            y: -self.y,
+            z: -self.z,
         }
     }
 }
@@ -215,6 +228,278 @@ impl fmt::Display for Vector3 {
     }
 }
 
+/// A 4x4 matrix of `f32`s stored in row-major order, used to represent affine transformations
+/// of points, vectors and normals in homogeneous coordinates.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Matrix4 {
+    m: [[f32; 4]; 4],
+}
+
+impl Matrix4 {
+    /// Create a new `Matrix4` from its rows.
+    pub fn new(m: [[f32; 4]; 4]) -> Self {
+        Matrix4 { m }
+    }
+
+    /// Create a new identity `Matrix4`.
+    pub fn identity() -> Self {
+        let mut m = [[0f32; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            row[i] = 1f32;
+        }
+        Matrix4 { m }
+    }
+
+    /// Return the transpose of this matrix.
+    pub fn transpose(self) -> Matrix4 {
+        let mut m = [[0f32; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, entry) in row.iter_mut().enumerate() {
+                *entry = self.m[j][i];
+            }
+        }
+        Matrix4 { m }
+    }
+
+    /// Return the inverse of this matrix, or `None` if the matrix is singular.
+    pub fn inverse(self) -> Option<Matrix4> {
+        // Gauss-Jordan elimination with partial pivoting on the augmented matrix [self | I].
+        let mut a = self.m;
+        let mut inv = Matrix4::identity().m;
+
+        for col in 0..4 {
+            let pivot = (col..4)
+                .max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))
+                .unwrap_or(col);
+            if a[pivot][col].abs() < 1.0e-12 {
+                return None;
+            }
+            a.swap(col, pivot);
+            inv.swap(col, pivot);
+
+            let scale = 1.0 / a[col][col];
+            for j in 0..4 {
+                a[col][j] *= scale;
+                inv[col][j] *= scale;
+            }
+
+            for row in 0..4 {
+                if row != col {
+                    let factor = a[row][col];
+                    for j in 0..4 {
+                        a[row][j] -= factor * a[col][j];
+                        inv[row][j] -= factor * inv[col][j];
+                    }
+                }
+            }
+        }
+
+        Some(Matrix4 { m: inv })
+    }
+
+    /// Compute the determinant of this matrix.
+    pub fn determinant(self) -> f32 {
+        let m = &self.m;
+        let minor = |r: [usize; 3], c: [usize; 3]| {
+            m[r[0]][c[0]] * (m[r[1]][c[1]] * m[r[2]][c[2]] - m[r[1]][c[2]] * m[r[2]][c[1]])
+                - m[r[0]][c[1]] * (m[r[1]][c[0]] * m[r[2]][c[2]] - m[r[1]][c[2]] * m[r[2]][c[0]])
+                + m[r[0]][c[2]] * (m[r[1]][c[0]] * m[r[2]][c[1]] - m[r[1]][c[1]] * m[r[2]][c[0]])
+        };
+
+        m[0][0] * minor([1, 2, 3], [1, 2, 3]) - m[0][1] * minor([1, 2, 3], [0, 2, 3])
+            + m[0][2] * minor([1, 2, 3], [0, 1, 3])
+            - m[0][3] * minor([1, 2, 3], [0, 1, 2])
+    }
+
+    /// Apply this matrix to the point `p`, including the translation.
+    pub fn transform_point(self, p: Vector3) -> Vector3 {
+        let m = &self.m;
+        Vector3 {
+            x: m[0][0] * p.x + m[0][1] * p.y + m[0][2] * p.z + m[0][3],
+            y: m[1][0] * p.x + m[1][1] * p.y + m[1][2] * p.z + m[1][3],
+            z: m[2][0] * p.x + m[2][1] * p.y + m[2][2] * p.z + m[2][3],
+        }
+    }
+
+    /// Apply this matrix to the direction `v`, ignoring the translation.
+    pub fn transform_vector(self, v: Vector3) -> Vector3 {
+        let m = &self.m;
+        Vector3 {
+            x: m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+            y: m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+            z: m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z,
+        }
+    }
+
+    /// Apply this matrix to the surface normal `n`, which transforms by the inverse-transpose
+    /// so that it stays perpendicular to transformed tangents. The result is not normalized.
+    /// Callers transforming many normals should cache the inverse-transpose instead.
+    pub fn transform_normal(self, n: Vector3) -> Vector3 {
+        match self.inverse() {
+            Some(inverse) => inverse.transpose().transform_vector(n),
+            None => Vector3::new(f32::NAN, f32::NAN, f32::NAN),
+        }
+    }
+
+    /// Return the rows of this matrix.
+    pub fn rows(self) -> [[f32; 4]; 4] {
+        self.m
+    }
+}
+
+impl Mul<Matrix4> for Matrix4 {
+    type Output = Matrix4;
+    fn mul(self, rhs: Matrix4) -> Self::Output {
+        let mut m = [[0f32; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, entry) in row.iter_mut().enumerate() {
+                *entry = (0..4).map(|k| self.m[i][k] * rhs.m[k][j]).sum();
+            }
+        }
+        Matrix4 { m }
+    }
+}
+
+binop_ref_impl! { impl Mul<Matrix4> for Matrix4, mul -> Matrix4 }
+
+/// A quaternion `w + xi + yj + zk`. Unit quaternions represent rotations in 3D space.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Quaternion {
+    w: f32,
+    x: f32,
+    y: f32,
+    z: f32,
+}
+
+impl Quaternion {
+    /// Create a new `Quaternion` with the real part `w` and imaginary parts `x`, `y`, `z`.
+    pub fn new(w: f32, x: f32, y: f32, z: f32) -> Self {
+        Quaternion { w, x, y, z }
+    }
+
+    /// Create a new `Quaternion` representing no rotation.
+    pub fn identity() -> Self {
+        Quaternion::new(1.0, 0.0, 0.0, 0.0)
+    }
+
+    /// Create a new unit `Quaternion` rotating by `angle` radians around `axis`, which does not
+    /// need to be normalized.
+    pub fn from_axis_angle(axis: Vector3, angle: f32) -> Self {
+        let axis = axis.normalized();
+        let (sin, cos) = (angle / 2.0).sin_cos();
+        Quaternion::new(cos, axis.x * sin, axis.y * sin, axis.z * sin)
+    }
+
+    /// Compute the Euclidean norm of this quaternion.
+    pub fn norm(self) -> f32 {
+        (self.w.powi(2) + self.x.powi(2) + self.y.powi(2) + self.z.powi(2)).sqrt()
+    }
+
+    /// Return a normalized copy of this quaternion.
+    pub fn normalized(self) -> Quaternion {
+        let norm = self.norm();
+        Quaternion::new(self.w / norm, self.x / norm, self.y / norm, self.z / norm)
+    }
+
+    /// Return the conjugate of this quaternion, which is the inverse rotation for unit
+    /// quaternions.
+    pub fn conjugate(self) -> Quaternion {
+        Quaternion::new(self.w, -self.x, -self.y, -self.z)
+    }
+
+    /// Compute the dot product of this quaternion and `other`.
+    pub fn dot(self, other: Quaternion) -> f32 {
+        self.w * other.w + self.x * other.x + self.y * other.y + self.z * other.z
+    }
+
+    /// Rotate the vector `v` by this unit quaternion.
+    pub fn rotate(self, v: Vector3) -> Vector3 {
+        let u = Vector3::new(self.x, self.y, self.z);
+        let t = 2.0 * u.cross(v);
+        v + self.w * t + u.cross(t)
+    }
+
+    /// Return the rotation matrix of this unit quaternion.
+    pub fn matrix(self) -> Matrix4 {
+        let Quaternion { w, x, y, z } = self;
+        Matrix4::new([
+            [
+                1.0 - 2.0 * (y * y + z * z),
+                2.0 * (x * y - w * z),
+                2.0 * (x * z + w * y),
+                0.0,
+            ],
+            [
+                2.0 * (x * y + w * z),
+                1.0 - 2.0 * (x * x + z * z),
+                2.0 * (y * z - w * x),
+                0.0,
+            ],
+            [
+                2.0 * (x * z - w * y),
+                2.0 * (y * z + w * x),
+                1.0 - 2.0 * (x * x + y * y),
+                0.0,
+            ],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    /// Spherically interpolate between the unit quaternions `self` at `t = 0` and `other` at
+    /// `t = 1`, taking the shortest path.
+    pub fn slerp(self, other: Quaternion, t: f32) -> Quaternion {
+        let mut cos = self.dot(other);
+        let other = if cos < 0.0 {
+            cos = -cos;
+            Quaternion::new(-other.w, -other.x, -other.y, -other.z)
+        } else {
+            other
+        };
+
+        let (a, b) = if cos > 0.9995 {
+            // The rotations are nearly identical, so linear interpolation is accurate enough.
+            (1.0 - t, t)
+        } else {
+            let theta = cos.acos();
+            let sin = theta.sin();
+            (((1.0 - t) * theta).sin() / sin, (t * theta).sin() / sin)
+        };
+
+        Quaternion::new(
+            a * self.w + b * other.w,
+            a * self.x + b * other.x,
+            a * self.y + b * other.y,
+            a * self.z + b * other.z,
+        )
+        .normalized()
+    }
+
+    /// Return the `w` component of this quaternion.
+    pub fn w(self) -> f32 {
+        self.w
+    }
+
+    /// Return the imaginary components of this quaternion as a vector.
+    pub fn xyz(self) -> Vector3 {
+        Vector3::new(self.x, self.y, self.z)
+    }
+}
+
+impl Mul<Quaternion> for Quaternion {
+    type Output = Quaternion;
+    /// Compose two rotations so that `rhs` is applied first.
+    fn mul(self, rhs: Quaternion) -> Self::Output {
+        Quaternion {
+            w: self.w * rhs.w - self.x * rhs.x - self.y * rhs.y - self.z * rhs.z,
+            x: self.w * rhs.x + self.x * rhs.w + self.y * rhs.z - self.z * rhs.y,
+            y: self.w * rhs.y - self.x * rhs.z + self.y * rhs.w + self.z * rhs.x,
+            z: self.w * rhs.z + self.x * rhs.y - self.y * rhs.x + self.z * rhs.w,
+        }
+    }
+}
+
+binop_ref_impl! { impl Mul<Quaternion> for Quaternion, mul -> Quaternion }
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -377,6 +662,7 @@ mod test {
     }
 
     #[test]
+    #[allow(clippy::op_ref)]
     fn test_ops() {
         let test1 = Vector3::new(1.0, 1.0, 1.0);
         let test2 = Vector3::new(1.0, 2.0, 4.0);
@@ -433,4 +719,126 @@ mod test {
 
         let _ = -&test1;
     }
+
+    #[test]
+    fn test_matrix_inverse() {
+        let m = Matrix4::new([
+            [2.0, 0.5, 0.0, 1.0],
+            [0.0, 1.0, -1.0, 2.0],
+            [1.0, 0.0, 3.0, -1.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ]);
+        let inverse = m.inverse().expect("Matrix should be invertible.");
+        let product = (m * inverse).rows();
+        let identity = Matrix4::identity().rows();
+        for i in 0..4 {
+            for j in 0..4 {
+                assert!(
+                    (product[i][j] - identity[i][j]).abs() < 1.0e-5,
+                    "Matrix4::inverse() failed. Expected identity, got {:?}.",
+                    product
+                );
+            }
+        }
+
+        let det = m.determinant();
+        assert!(
+            (det - 5.5).abs() < 1.0e-5,
+            "Matrix4::determinant() failed. Expected {}, got {}.",
+            5.5,
+            det
+        );
+
+        let singular = Matrix4::new([
+            [1.0, 2.0, 3.0, 0.0],
+            [2.0, 4.0, 6.0, 0.0],
+            [0.0; 4],
+            [0.0; 4],
+        ]);
+        assert!(
+            singular.inverse().is_none(),
+            "Matrix4::inverse() should fail on a singular matrix."
+        );
+    }
+
+    #[test]
+    fn test_matrix_apply() {
+        let m = Matrix4::new([
+            [2.0, 0.0, 0.0, 1.0],
+            [0.0, 1.0, 0.0, 2.0],
+            [0.0, 0.0, 1.0, 3.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ]);
+        let v = Vector3::new(1.0, 1.0, 1.0);
+
+        let point = m.transform_point(v);
+        let expected = Vector3::new(3.0, 3.0, 4.0);
+        assert_eq!(
+            expected, point,
+            "Matrix4::transform_point() failed on {}. Expected {}, got {}.",
+            v, expected, point
+        );
+
+        let vector = m.transform_vector(v);
+        let expected = Vector3::new(2.0, 1.0, 1.0);
+        assert_eq!(
+            expected, vector,
+            "Matrix4::transform_vector() failed on {}. Expected {}, got {}.",
+            v, expected, vector
+        );
+
+        let normal = m.transform_normal(v);
+        let expected = Vector3::new(0.5, 1.0, 1.0);
+        assert_eq!(
+            expected, normal,
+            "Matrix4::transform_normal() failed on {}. Expected {}, got {}.",
+            v, expected, normal
+        );
+    }
+
+    #[test]
+    fn test_quaternion() {
+        let q =
+            Quaternion::from_axis_angle(Vector3::new(0.0, 0.0, 2.0), std::f32::consts::FRAC_PI_2);
+        let v = Vector3::new(1.0, 0.0, 0.0);
+        let expected = Vector3::new(0.0, 1.0, 0.0);
+
+        let rotated = q.rotate(v);
+        assert!(
+            (rotated - expected).norm() < 1.0e-6,
+            "Quaternion::rotate() failed on {}. Expected {}, got {}.",
+            v,
+            expected,
+            rotated
+        );
+
+        let rotated = q.matrix().transform_vector(v);
+        assert!(
+            (rotated - expected).norm() < 1.0e-6,
+            "Quaternion::matrix() failed on {}. Expected {}, got {}.",
+            v,
+            expected,
+            rotated
+        );
+
+        let twice = (q * q).rotate(v);
+        let expected = Vector3::new(-1.0, 0.0, 0.0);
+        assert!(
+            (twice - expected).norm() < 1.0e-6,
+            "Quaternion::mul() failed on {}. Expected {}, got {}.",
+            v,
+            expected,
+            twice
+        );
+
+        let half = Quaternion::identity().slerp(q, 0.5).rotate(v);
+        let expected = Vector3::unit(1.0, 1.0, 0.0);
+        assert!(
+            (half - expected).norm() < 1.0e-6,
+            "Quaternion::slerp() failed on {}. Expected {}, got {}.",
+            v,
+            expected,
+            half
+        );
+    }
 }
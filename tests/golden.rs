@@ -0,0 +1,101 @@
+//! Golden-image regression tests, which render each bundled scene at a low resolution with a
+//! fixed seed and compare it against a reference in `tests/golden`.
+//!
+//! When a change alters the output on purpose, regenerate the references with
+//!
+//! ```text
+//! UPDATE_GOLDEN=1 cargo test --test golden
+//! ```
+//!
+//! and check the new images before committing them. When a test fails, its render and a
+//! false-color image of the error are written to `target/golden` for inspection.
+
+use raytracer::compare::{self, Metric};
+use raytracer::image::Image;
+use raytracer::scene::Scene;
+use raytracer::vector::Vector3;
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+const WIDTH: u32 = 64;
+const HEIGHT: u32 = 64;
+const SAMPLES: usize = 16;
+const SEED: u64 = 7;
+
+/// The largest mean and per-pixel FLIP-style errors allowed. Renders with the same seed match
+/// exactly on the same platform, so these only leave room for floating point differences
+/// between platforms to change a few pixels by one 8-bit level. Raising every pixel of the
+/// references by one level gives a mean error of about 0.025 and pixel errors up to 0.04,
+/// while two levels give pixel errors up to 0.06.
+const THRESHOLD: f32 = 0.002;
+const PIXEL_THRESHOLD: f32 = 0.045;
+
+fn check(name: &str) {
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let scene_path = root.join("scenes").join(format!("{}.json", name));
+    let reference_path = root.join("tests/golden").join(format!("{}.png", name));
+
+    let mut scene = Scene::from_json(scene_path.to_str().unwrap()).unwrap();
+    scene.seed = Some(SEED);
+    let image = scene.render(WIDTH, HEIGHT, SAMPLES);
+
+    if env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::create_dir_all(reference_path.parent().unwrap()).unwrap();
+        image.save(&reference_path).unwrap();
+        return;
+    }
+
+    let reference = Image::load(&reference_path).unwrap_or_else(|err| {
+        panic!(
+            "Unable to load the reference: {}. Run UPDATE_GOLDEN=1 cargo test --test golden \
+             to create it.",
+            err
+        )
+    });
+    // Compare against the render as it would be saved, since the reference is 8-bit.
+    let image = image.map(|p| {
+        p.cwise(Vector3::ones(), |u, _| {
+            (u.clamp(0.0, 1.0) * 255.0).round() / 255.0
+        })
+    });
+    let comparison = compare::compare(&reference, &image);
+    let worst = comparison.errors.iter().copied().fold(0.0, f32::max);
+
+    if comparison.exceeds(Metric::Flip, THRESHOLD) || worst > PIXEL_THRESHOLD {
+        let out = root.join("target/golden");
+        fs::create_dir_all(&out).unwrap();
+        image.save(out.join(format!("{}.png", name))).unwrap();
+        comparison
+            .false_color()
+            .save(out.join(format!("{}_diff.png", name)))
+            .unwrap();
+        panic!(
+            "Rendering {} failed. Expected a FLIP error of at most {} and at most {} in every \
+             pixel, got a largest pixel error of {} and:\n{}\nThe render and its error are in \
+             {}.",
+            name,
+            THRESHOLD,
+            PIXEL_THRESHOLD,
+            worst,
+            comparison,
+            out.display()
+        );
+    }
+}
+
+#[test]
+fn test_checker() {
+    check("checker");
+}
+
+#[test]
+fn test_cornell_box() {
+    check("cornell_box");
+}
+
+#[test]
+fn test_mirror() {
+    check("mirror");
+}